@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- Single row holding the corpus statistics used for ranking
+CREATE TABLE corpus_stats (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    document_count BIGINT NOT NULL DEFAULT 0,
+    total_word_count BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO corpus_stats (document_count, total_word_count)
+SELECT COUNT(*), COALESCE(SUM(word_count), 0) FROM websites;
@@ -0,0 +1,5 @@
+-- Add migration script here
+
+-- Positions of the keyword in the normalized token stream of the website
+ALTER TABLE website_keywords
+ADD COLUMN positions INTEGER[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- Add migration script here
+
+-- Visible text of the website, used to build result snippets
+ALTER TABLE websites
+ADD COLUMN content TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,12 @@
+-- Add migration script here
+
+-- Queries users searched for, with how often, used for autocompletion
+CREATE TABLE query_logs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    query VARCHAR(255) UNIQUE NOT NULL,
+    count BIGINT NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX query_logs_count_idx ON query_logs (count DESC);
@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- Occurrences of the keyword outside the body, `frequency` stays the count in the body
+ALTER TABLE website_keywords
+ADD COLUMN title_frequency INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN heading_frequency INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN description_frequency INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN url_frequency INTEGER NOT NULL DEFAULT 0;
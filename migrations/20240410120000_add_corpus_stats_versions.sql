@@ -0,0 +1,10 @@
+-- Add migration script here
+
+-- `version` counts the changes to the corpus, `idf_version` is the version the stored idf was last recomputed at
+ALTER TABLE corpus_stats
+ADD COLUMN version BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN idf_version BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN idf_recomputed_at TIMESTAMPTZ;
+
+-- Existing rows were scored against a smaller corpus
+UPDATE corpus_stats SET version = 1;
@@ -0,0 +1,10 @@
+-- Add migration script here
+
+-- Deleting a website deletes its postings and tfidf scores
+ALTER TABLE website_keywords
+DROP CONSTRAINT website_keywords_website_id_fkey,
+ADD CONSTRAINT website_keywords_website_id_fkey FOREIGN KEY (website_id) REFERENCES websites(id) ON DELETE CASCADE;
+
+ALTER TABLE website_keyword_tfidf
+DROP CONSTRAINT website_keyword_tfidf_website_id_fkey,
+ADD CONSTRAINT website_keyword_tfidf_website_id_fkey FOREIGN KEY (website_id) REFERENCES websites(id) ON DELETE CASCADE;
@@ -0,0 +1,7 @@
+-- Add migration script here
+
+-- Fingerprint of the normalized token stream, a page crawled again with the same fingerprint is not reindexed
+-- `last_seen_at` is the last time the crawler saw the page, changed or not
+ALTER TABLE websites
+ADD COLUMN fingerprint BIGINT,
+ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- SimHash of the body tokens, websites whose simhashes differ in a few bits are near duplicates
+-- `cluster_id` is the canonical website of the near duplicate cluster, NULL for the canonical website itself
+ALTER TABLE websites
+ADD COLUMN simhash BIGINT,
+ADD COLUMN cluster_id UUID REFERENCES websites(id) ON DELETE SET NULL;
+
+-- Near duplicates share at least one of the four 16 bit bands of their simhash
+CREATE INDEX websites_simhash_band_0_idx ON websites (((simhash >> 48) & 65535));
+CREATE INDEX websites_simhash_band_1_idx ON websites (((simhash >> 32) & 65535));
+CREATE INDEX websites_simhash_band_2_idx ON websites (((simhash >> 16) & 65535));
+CREATE INDEX websites_simhash_band_3_idx ON websites ((simhash & 65535));
+CREATE INDEX websites_cluster_id_idx ON websites (cluster_id);
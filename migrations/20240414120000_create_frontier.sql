@@ -0,0 +1,18 @@
+-- Add migration script here
+
+-- Urls waiting to be crawled, so that a crawl resumes where it stopped after a restart
+CREATE TYPE frontier_state AS ENUM ('queued', 'in_flight', 'done', 'failed');
+
+CREATE TABLE frontier (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    url TEXT UNIQUE NOT NULL,
+    state frontier_state NOT NULL DEFAULT 'queued',
+    -- `attempts` is the number of times the url was leased to a crawler
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    leased_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX frontier_state_updated_at_idx ON frontier (state, updated_at);
@@ -0,0 +1,16 @@
+-- Add migration script here
+
+-- Tranco rank of the crawled domains, a query-independent quality signal of their pages
+CREATE TABLE domains (
+    host TEXT PRIMARY KEY,
+    -- `rank` is the Tranco rank of the domain, 1 being the most popular
+    rank INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Urls of higher-ranked domains are leased first, urls without a rank last
+ALTER TABLE frontier
+ADD COLUMN rank INT;
+
+CREATE INDEX frontier_state_rank_idx ON frontier (state, rank, updated_at);
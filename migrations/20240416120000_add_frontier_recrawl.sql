@@ -0,0 +1,15 @@
+-- Add migration script here
+
+-- `site` urls are crawled with every page they link to, `page` urls alone
+CREATE TYPE crawl_scope AS ENUM ('site', 'page');
+
+-- Crawl history of every url, so pages that change often are crawled again sooner than static ones
+-- `crawl_count` is the number of times the url was crawled, `change_count` how many of those found it changed
+ALTER TABLE frontier
+ADD COLUMN scope crawl_scope NOT NULL DEFAULT 'site',
+ADD COLUMN crawl_count INT NOT NULL DEFAULT 0,
+ADD COLUMN change_count INT NOT NULL DEFAULT 0,
+ADD COLUMN crawled_at TIMESTAMPTZ,
+ADD COLUMN next_crawl_at TIMESTAMPTZ;
+
+CREATE INDEX frontier_state_next_crawl_at_idx ON frontier (state, next_crawl_at);
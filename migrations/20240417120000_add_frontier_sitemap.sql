@@ -0,0 +1,8 @@
+-- Add migration script here
+
+-- What the sitemaps of a site say of its urls
+-- `lastmod` is when the site last modified the url, a crawled url modified since is due to be crawled again
+-- `priority` is how important the url is relative to the other urls of its site, from 0 to 1
+ALTER TABLE frontier
+ADD COLUMN lastmod TIMESTAMPTZ,
+ADD COLUMN priority REAL;
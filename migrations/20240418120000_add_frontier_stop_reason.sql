@@ -0,0 +1,7 @@
+-- Add migration script here
+
+-- The budget that stopped the crawl of a site before it reached every page it links to
+CREATE TYPE crawl_stop_reason AS ENUM ('max_pages', 'max_bytes', 'timeout');
+
+ALTER TABLE frontier
+ADD COLUMN stop_reason crawl_stop_reason;
@@ -0,0 +1,5 @@
+-- Add migration script here
+
+-- The word of a page a keyword was first normalized from, shown instead of the keyword in spelling suggestions
+ALTER TABLE keywords
+ADD COLUMN surface VARCHAR(255);
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- The occurrences of a keyword in the headings of every level, <h1> first
+ALTER TABLE website_keywords
+ADD COLUMN heading_frequencies INTEGER[] NOT NULL DEFAULT '{0,0,0,0,0,0}';
+
+-- The level of the headings counted so far is unknown, they are counted as <h2> whose default boost is the one they had.
+-- The pages are reindexed with their heading levels the next time they are crawled, their fingerprint changed.
+UPDATE website_keywords
+SET heading_frequencies = ARRAY[0, heading_frequency, 0, 0, 0, 0]
+WHERE heading_frequency <> 0;
+
+ALTER TABLE website_keywords
+DROP COLUMN heading_frequency;
@@ -0,0 +1,27 @@
+-- Add migration script here
+
+-- The counts of the corpus split over rows, so that the writers indexing pages at the same time update different rows
+-- instead of queueing on a single one. The statistics are the sums of the shards.
+CREATE TABLE corpus_stats_shards (
+    shard SMALLINT PRIMARY KEY,
+    document_count BIGINT NOT NULL DEFAULT 0,
+    total_word_count BIGINT NOT NULL DEFAULT 0,
+    version BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO corpus_stats_shards (shard)
+SELECT generate_series(0, 15);
+
+-- The counts so far go to the first shard
+UPDATE corpus_stats_shards
+SET document_count = corpus_stats.document_count,
+    total_word_count = corpus_stats.total_word_count,
+    version = corpus_stats.version
+FROM corpus_stats
+WHERE corpus_stats_shards.shard = 0;
+
+ALTER TABLE corpus_stats
+DROP COLUMN document_count,
+DROP COLUMN total_word_count,
+DROP COLUMN version;
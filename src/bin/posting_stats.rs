@@ -0,0 +1,51 @@
+use search_engine::segment::codec::PostingStats;
+#[cfg(not(feature = "segment"))]
+use search_engine::store::PgIndexStore;
+#[cfg(feature = "segment")]
+use search_engine::store::SegmentIndexStore;
+
+#[macro_use]
+extern crate dotenv_codegen;
+
+/// Report how well the posting lists of the index compress and how fast they decode.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let stats = collect_stats().await?;
+    println!("Posting lists: {}", stats.lists);
+    println!("Postings: {}", stats.postings);
+    println!("Raw size: {} bytes", stats.raw_bytes);
+    println!("Compressed size: {} bytes", stats.encoded_bytes);
+    println!("Compression ratio: {:.2}", stats.compression_ratio());
+    println!("Decode throughput: {:.0} postings/s", stats.decode_throughput());
+    Ok(())
+}
+
+/// Compress the postings of the postgres index.
+#[cfg(not(feature = "segment"))]
+async fn collect_stats() -> Result<PostingStats, Box<dyn std::error::Error>> {
+    let host = dotenv!("DB_HOST");
+    let port = dotenv!("DB_PORT").parse().expect("DB_PORT must be a number");
+    let username = dotenv!("DB_USERNAME");
+    let password = dotenv!("DB_PASSWORD");
+    let database = dotenv!("DB_DATABASE");
+    let db_options = sqlx::postgres::PgConnectOptions::new()
+        .host(host)
+        .port(port)
+        .username(username)
+        .password(password)
+        .database(database);
+    let db = sqlx::PgPool::connect_with(db_options).await.map_err(|e| {println!("Error connecting to database: {:?}", e);e})?;
+    // The stats only read, so no keyword id is ever cached.
+    let store = PgIndexStore::new(db, 0);
+    println!("The postgres index keeps its postings uncompressed, the compressed size is an estimate.");
+    store.posting_stats().await
+}
+
+/// Decode and compress again the posting lists of the local segment index.
+#[cfg(feature = "segment")]
+async fn collect_stats() -> Result<PostingStats, Box<dyn std::error::Error>> {
+    let segment_index_path = std::path::PathBuf::from(dotenv!("SEGMENT_INDEX_PATH"));
+    // Nothing is indexed, so nothing is flushed or merged.
+    let store = SegmentIndexStore::open(&segment_index_path, usize::MAX, usize::MAX).map_err(|e| format!("Error opening segment index: {:?}", e))?;
+    Ok(store.posting_stats().map_err(|e| format!("Error reading postings: {:?}", e))?)
+}
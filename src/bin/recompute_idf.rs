@@ -0,0 +1,49 @@
+use std::time::Duration;
+use sqlx::PgPool;
+use search_engine::services::IdfRecomputer;
+
+#[macro_use]
+extern crate dotenv_codegen;
+
+/// Recompute the stored idf of every keyword once, or every `--interval <seconds>` while the corpus changes.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let interval = parse_interval(std::env::args().skip(1).collect())?;
+    let host = dotenv!("DB_HOST");
+    let port = dotenv!("DB_PORT").parse().expect("DB_PORT must be a number");
+    let username = dotenv!("DB_USERNAME");
+    let password = dotenv!("DB_PASSWORD");
+    let database = dotenv!("DB_DATABASE");
+    let db_options = sqlx::postgres::PgConnectOptions::new()
+        .host(host)
+        .port(port)
+        .username(username)
+        .password(password)
+        .database(database);
+    let db = PgPool::connect_with(db_options).await.map_err(|e| {println!("Error connecting to database: {:?}", e);e})?;
+
+    let idf_recomputer = IdfRecomputer::new(db);
+    match interval {
+        Some(interval) => idf_recomputer.start(interval).await,
+        None => {
+            let recompute = idf_recomputer.recompute().await?;
+            println!("Recomputed idf of {} rows at corpus version {}.", recompute.rows, recompute.version);
+        }
+    }
+    Ok(())
+}
+
+/// Read `--interval <seconds>` from the arguments, `None` runs once.
+fn parse_interval(args: Vec<String>) -> Result<Option<Duration>, Box<dyn std::error::Error>> {
+    match args.as_slice() {
+        [] => Ok(None),
+        [flag, seconds] if flag == "--interval" => {
+            let seconds: u64 = seconds.parse().map_err(|_| format!("Invalid interval: {}", seconds))?;
+            if seconds == 0 {
+                return Err("Interval must be at least one second".into());
+            }
+            Ok(Some(Duration::from_secs(seconds)))
+        }
+        _ => Err("Usage: recompute_idf [--interval <seconds>]".into()),
+    }
+}
@@ -0,0 +1,280 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use search_engine::services::{Analyzer, Bm25Params, Completion, FieldBoosts, ParseError, Ranking, SearchQuery, SearchResult, SearchService};
+#[cfg(not(feature = "segment"))]
+use search_engine::store::PgIndexStore;
+#[cfg(feature = "segment")]
+use search_engine::store::SegmentIndexStore;
+
+#[macro_use]
+extern crate dotenv_codegen;
+
+// Number of results returned when `limit` is not given.
+const DEFAULT_LIMIT: usize = 10;
+// Upper bound on `limit` so a single request cannot pull the whole index.
+const MAX_LIMIT: usize = 100;
+// Number of completions returned when `limit` is not given.
+const DEFAULT_COMPLETION_LIMIT: usize = 5;
+// Number of keyword ids cached by the store, the API only reads so it never fills.
+#[cfg(not(feature = "segment"))]
+const KEYWORD_CACHE_CAPACITY: usize = 0;
+// How often the segments written by the crawl pipeline are reloaded.
+#[cfg(feature = "segment")]
+const SEGMENT_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+// How often keywords created by the crawl pipeline are loaded for spelling suggestions.
+const VOCABULARY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// How often every keyword is loaded again, updating the document frequencies of the keywords already loaded.
+const VOCABULARY_RELOAD_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(not(feature = "segment"))]
+type Store = PgIndexStore;
+#[cfg(feature = "segment")]
+type Store = SegmentIndexStore;
+
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    // `ranking` is either `tfidf` (default) or `bm25`.
+    ranking: Option<String>,
+    k1: Option<f64>,
+    b: Option<f64>,
+    // `boosts` weighs the fields, e.g. `title:3,h1:2.5,heading:2,body:1`, fields left out keep their default boost.
+    boosts: Option<String>,
+    // `collapse` keeps a single result per near duplicate cluster, on unless set to `false`.
+    collapse: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResponse {
+    query: String,
+    limit: usize,
+    offset: usize,
+    ranking: Ranking,
+    // `field_boosts` are the boosts the results were scored with.
+    field_boosts: FieldBoosts,
+    // `scores_stale` is set when the corpus changed since the stored idf was last recomputed.
+    scores_stale: bool,
+    // `suggestion` is the query with misspelled words corrected, if any.
+    suggestion: Option<String>,
+    results: Vec<SearchResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompleteParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct CompleteResponse {
+    query: String,
+    completions: Vec<Completion>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Open the lemmatizer JSON file - must be the same one the crawl pipeline indexes with
+    let lemmatizer_json_path = dotenv!("LEMMATIZER_JSON_PATH");
+    let lemmatizer_json_path_buf = PathBuf::from(lemmatizer_json_path);
+    // Address the API listens on, e.g. 0.0.0.0:8080
+    let address = dotenv!("SEARCH_API_ADDRESS");
+    // Create the search service
+    let analyzer = Analyzer::new(lemmatizer_json_path_buf).map_err(|e| {
+        println!("Error creating analyzer: {:?}", e);
+        e
+    })?;
+    let store = Arc::new(open_store().await?);
+    // Pick up the segments the crawl pipeline flushes and merges
+    #[cfg(feature = "segment")]
+    {
+        let store = store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SEGMENT_RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = tokio::task::block_in_place(|| store.reload()) {
+                    eprintln!("Error reloading segments: {:?}", e);
+                }
+            }
+        });
+    }
+    let search_service = Arc::new(SearchService::new(store, analyzer));
+    match search_service.load_popular_queries().await {
+        Ok(count) => println!("Loaded {} past queries.", count),
+        Err(e) => eprintln!("Error loading past queries: {:?}", e),
+    }
+    // Keep the spelling and autocomplete vocabulary in step with the keywords the crawl pipeline creates
+    let vocabulary_service = search_service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(VOCABULARY_REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            match vocabulary_service.refresh_vocabulary().await {
+                Ok(count) => println!("Loaded {} new keywords.", count),
+                Err(e) => eprintln!("Error refreshing vocabulary: {:?}", e),
+            }
+        }
+    });
+    let vocabulary_service = search_service.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(VOCABULARY_RELOAD_INTERVAL);
+        // The first tick completes immediately, and the vocabulary was just loaded by the refresh.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match vocabulary_service.reload_vocabulary().await {
+                Ok(count) => println!("Reloaded {} keywords.", count),
+                Err(e) => eprintln!("Error reloading vocabulary: {:?}", e),
+            }
+        }
+    });
+
+    let app = Router::new()
+        .route("/search", get(search))
+        .route("/complete", get(complete))
+        .with_state(search_service);
+    let listener = tokio::net::TcpListener::bind(address).await.map_err(|e| {
+        println!("Error binding to {}: {:?}", address, e);
+        e
+    })?;
+    println!("Search API listening on {}", address);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Connect to the postgres index.
+#[cfg(not(feature = "segment"))]
+async fn open_store() -> Result<Store, Box<dyn std::error::Error>> {
+    let host = dotenv!("DB_HOST");
+    let port = dotenv!("DB_PORT").parse().expect("DB_PORT must be a number");
+    let username = dotenv!("DB_USERNAME");
+    let password = dotenv!("DB_PASSWORD");
+    let database = dotenv!("DB_DATABASE");
+    let db_options = sqlx::postgres::PgConnectOptions::new()
+        .host(host)
+        .port(port)
+        .username(username)
+        .password(password)
+        .database(database);
+    let db = sqlx::PgPool::connect_with(db_options).await.map_err(|e| {println!("Error connecting to database: {:?}", e);e})?;
+    Ok(PgIndexStore::new(db, KEYWORD_CACHE_CAPACITY))
+}
+
+/// Open the local segment index the crawl pipeline writes, reading only.
+#[cfg(feature = "segment")]
+async fn open_store() -> Result<Store, Box<dyn std::error::Error>> {
+    let segment_index_path = PathBuf::from(dotenv!("SEGMENT_INDEX_PATH"));
+    // The API never indexes pages, so nothing is buffered, flushed or merged here.
+    let store = SegmentIndexStore::open(&segment_index_path, usize::MAX, usize::MAX).map_err(|e| {
+        println!("Error opening segment index: {:?}", e);
+        e
+    })?;
+    Ok(store)
+}
+
+/// Handle `GET /search?q=...&limit=&offset=&ranking=&k1=&b=&boosts=&collapse=`.
+async fn search(State(search_service): State<Arc<SearchService<Store>>>, Query(params): Query<SearchParams>) -> Result<Json<SearchResponse>, (StatusCode, String)> {
+    let ranking = parse_ranking(&params)?;
+    let field_boosts = params.boosts.as_deref()
+        .map(str::parse::<FieldBoosts>)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    let query = SearchQuery {
+        text: params.q,
+        limit: params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+        offset: params.offset.unwrap_or(0),
+        ranking,
+        field_boosts,
+        collapse_duplicates: params.collapse.unwrap_or(true),
+    };
+    let results = match search_service.search(&query).await {
+        Ok(results) => results,
+        // A malformed query is the caller's mistake, tell them where it is.
+        Err(e) if e.is::<ParseError>() => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+        Err(e) => {
+            eprintln!("Error searching for {:?}: {:?}", query.text, e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Error searching".to_string()));
+        }
+    };
+    // Remember queries that found something so they can be completed for other users.
+    if !results.is_empty() {
+        if let Err(e) = search_service.record_query(&query.text).await {
+            eprintln!("Error recording query {:?}: {:?}", query.text, e);
+        }
+    }
+    let suggestion = search_service.suggest(&query.text).await;
+    let scores_stale = match search_service.scores_stale(&query.ranking).await {
+        Ok(scores_stale) => scores_stale,
+        Err(e) => {
+            eprintln!("Error checking whether scores are stale: {:?}", e);
+            false
+        }
+    };
+    Ok(Json(SearchResponse {
+        query: query.text,
+        limit: query.limit,
+        offset: query.offset,
+        ranking: query.ranking,
+        field_boosts: query.field_boosts.unwrap_or_default(),
+        scores_stale,
+        suggestion,
+        results,
+    }))
+}
+
+/// Handle `GET /complete?q=...&limit=`.
+async fn complete(State(search_service): State<Arc<SearchService<Store>>>, Query(params): Query<CompleteParams>) -> Json<CompleteResponse> {
+    let limit = params.limit.unwrap_or(DEFAULT_COMPLETION_LIMIT).min(MAX_LIMIT);
+    let completions = search_service.complete(&params.q, limit).await;
+    Json(CompleteResponse {
+        query: params.q,
+        completions,
+    })
+}
+
+/// Select the ranking function from the request parameters.
+fn parse_ranking(params: &SearchParams) -> Result<Ranking, (StatusCode, String)> {
+    match params.ranking.as_deref() {
+        None | Some("tfidf") => Ok(Ranking::TfIdf),
+        Some("bm25") => {
+            let defaults = Bm25Params::default();
+            let k1 = params.k1.unwrap_or(defaults.k1);
+            let b = params.b.unwrap_or(defaults.b);
+            // `NaN` and infinite parameters parse as numbers, they would poison every score.
+            if !(k1.is_finite() && k1 >= 0.0 && (0.0..=1.0).contains(&b)) {
+                return Err((StatusCode::BAD_REQUEST, "k1 must be finite and non-negative and b must be between 0 and 1".to_string()));
+            }
+            Ok(Ranking::Bm25(Bm25Params { k1, b }))
+        }
+        Some(ranking) => Err((StatusCode::BAD_REQUEST, format!("Unknown ranking: {}", ranking))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::Uri;
+
+    fn search_params(uri: &str) -> SearchParams {
+        Query::<SearchParams>::try_from_uri(&uri.parse::<Uri>().unwrap()).unwrap().0
+    }
+
+    // BM25 parameters that are not numbers in range are rejected
+    #[test]
+    fn can_reject_invalid_bm25_params() {
+        for query in ["k1=NaN", "k1=inf", "k1=-1", "b=NaN", "b=2"] {
+            let params = search_params(&format!("/search?q=rust&ranking=bm25&{}", query));
+            assert_eq!(parse_ranking(&params).unwrap_err().0, StatusCode::BAD_REQUEST, "{}", query);
+        }
+        let params = search_params("/search?q=rust&ranking=bm25&k1=1.5&b=0.5");
+        assert!(matches!(parse_ranking(&params), Ok(Ranking::Bm25(Bm25Params { k1, b })) if k1 == 1.5 && b == 0.5));
+    }
+}
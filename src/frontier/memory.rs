@@ -0,0 +1,350 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::RwLock;
+use std::time::Duration;
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::frontier::{Frontier, Site};
+use crate::models::frontier::{CrawlScope, FrontierState, FrontierUrl, StopReason};
+
+/// MemoryFrontier keeps the frontier in memory, for tests and crawls that need not survive a restart.
+#[derive(Default)]
+pub struct MemoryFrontier {
+    frontier: RwLock<MemoryFrontierState>,
+}
+
+#[derive(Default)]
+struct MemoryFrontierState {
+    urls: HashMap<Uuid, FrontierUrl>,
+    // `url_ids` maps each url to its id.
+    url_ids: HashMap<String, Uuid>,
+    // `queue` holds the rank, queue order and id of the queued urls, the highest-ranked first then the least recently queued.
+    queue: BTreeSet<(i32, u64, Uuid)>,
+    // `sequence` is the queue order of the next url queued.
+    sequence: u64,
+    // `domain_ranks` maps the host of every ranked url queued to its rank.
+    domain_ranks: HashMap<String, i32>,
+}
+
+impl MemoryFrontierState {
+    /// Add a url that is not in the frontier yet, returning its id.
+    fn insert(&mut self, url: &url::Url, state: FrontierState, rank: Option<i32>, scope: CrawlScope, now: OffsetDateTime) -> Uuid {
+        let id = Uuid::new_v4();
+        self.urls.insert(id, FrontierUrl {
+            id,
+            url: url.to_string(),
+            state,
+            attempts: 0,
+            last_error: None,
+            rank,
+            scope,
+            crawl_count: 0,
+            change_count: 0,
+            crawled_at: None,
+            next_crawl_at: None,
+            lastmod: None,
+            priority: None,
+            stop_reason: None,
+            leased_at: None,
+            created_at: now,
+            updated_at: now,
+        });
+        self.url_ids.insert(url.to_string(), id);
+        id
+    }
+
+    /// Queue a url behind the urls of the same rank.
+    fn push(&mut self, id: Uuid, rank: Option<i32>) {
+        self.queue.insert((queue_rank(rank), self.sequence, id));
+        self.sequence += 1;
+    }
+
+    /// Move a queued url to its new rank, keeping its queue order.
+    fn rerank(&mut self, id: Uuid, rank: Option<i32>) {
+        let queued = self.queue.iter().find(|(_, _, queued_id)| *queued_id == id).copied();
+        if let Some((old_rank, sequence, _)) = queued {
+            self.queue.remove(&(old_rank, sequence, id));
+            self.queue.insert((queue_rank(rank), sequence, id));
+        }
+    }
+}
+
+/// The rank a url is ordered by in the queue, urls without a rank go last.
+fn queue_rank(rank: Option<i32>) -> i32 {
+    rank.unwrap_or(i32::MAX)
+}
+
+impl MemoryFrontier {
+    /// Create an empty MemoryFrontier instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, MemoryFrontierState> {
+        self.frontier.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl Frontier for MemoryFrontier {
+    async fn enqueue(&self, sites: &[Site]) -> Result<u64, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut frontier = self.write();
+        let mut queued = 0;
+        for site in sites {
+            let url = &site.url;
+            if let (Some(host), Some(rank)) = (url.host_str(), site.rank) {
+                let domain_rank = frontier.domain_ranks.entry(host.to_lowercase()).or_insert(rank);
+                *domain_rank = (*domain_rank).min(rank);
+            }
+            if let Some(id) = frontier.url_ids.get(url.as_str()).copied() {
+                let frontier_url = match frontier.urls.get_mut(&id) {
+                    Some(frontier_url) => frontier_url,
+                    None => continue,
+                };
+                let reranked = site.rank.is_some() && frontier_url.rank != site.rank;
+                let updated = reranked
+                    || (site.lastmod.is_some() && frontier_url.lastmod != site.lastmod)
+                    || (site.priority.is_some() && frontier_url.priority != site.priority);
+                if !updated {
+                    continue;
+                }
+                frontier_url.rank = site.rank.or(frontier_url.rank);
+                frontier_url.lastmod = site.lastmod.or(frontier_url.lastmod);
+                frontier_url.priority = site.priority.or(frontier_url.priority);
+                // A crawled url modified since its last crawl is due at once.
+                let modified = site.lastmod.is_some_and(|lastmod| frontier_url.crawled_at.is_some_and(|crawled_at| lastmod > crawled_at));
+                if frontier_url.state == FrontierState::Done && modified {
+                    frontier_url.next_crawl_at = Some(now);
+                }
+                if frontier_url.state == FrontierState::Queued && reranked {
+                    frontier.rerank(id, site.rank);
+                }
+                queued += 1;
+                continue;
+            }
+            let id = frontier.insert(url, FrontierState::Queued, site.rank, site.scope, now);
+            if let Some(frontier_url) = frontier.urls.get_mut(&id) {
+                frontier_url.lastmod = site.lastmod;
+                frontier_url.priority = site.priority;
+            }
+            frontier.push(id, site.rank);
+            queued += 1;
+        }
+        Ok(queued)
+    }
+
+    async fn lease(&self, limit: usize) -> Result<Vec<FrontierUrl>, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut frontier = self.write();
+        let mut leased = Vec::new();
+        while leased.len() < limit {
+            let id = match frontier.queue.pop_first() {
+                Some((_, _, id)) => id,
+                None => break,
+            };
+            if let Some(frontier_url) = frontier.urls.get_mut(&id) {
+                frontier_url.state = FrontierState::InFlight;
+                frontier_url.attempts += 1;
+                frontier_url.leased_at = Some(now);
+                frontier_url.updated_at = now;
+                leased.push(frontier_url.clone());
+            }
+        }
+        Ok(leased)
+    }
+
+    async fn complete(&self, id: Uuid, stop_reason: Option<StopReason>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut frontier = self.write();
+        let frontier_url = frontier.urls.get_mut(&id).ok_or("Url is not in the frontier")?;
+        frontier_url.state = FrontierState::Done;
+        frontier_url.last_error = None;
+        frontier_url.stop_reason = stop_reason;
+        frontier_url.leased_at = None;
+        frontier_url.updated_at = OffsetDateTime::now_utc();
+        Ok(())
+    }
+
+    async fn fail(&self, id: Uuid, error: &str, max_attempts: i32) -> Result<FrontierState, Box<dyn std::error::Error>> {
+        let mut frontier = self.write();
+        let frontier_url = frontier.urls.get_mut(&id).ok_or("Url is not in the frontier")?;
+        frontier_url.state = if frontier_url.attempts >= max_attempts {
+            FrontierState::Failed
+        } else {
+            FrontierState::Queued
+        };
+        frontier_url.last_error = Some(error.to_string());
+        frontier_url.leased_at = None;
+        frontier_url.updated_at = OffsetDateTime::now_utc();
+        let state = frontier_url.state;
+        let rank = frontier_url.rank;
+        if state == FrontierState::Queued {
+            frontier.push(id, rank);
+        }
+        Ok(state)
+    }
+
+    async fn renew(&self, ids: &[Uuid]) -> Result<(), Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut frontier = self.write();
+        for id in ids {
+            if let Some(frontier_url) = frontier.urls.get_mut(id).filter(|frontier_url| frontier_url.state == FrontierState::InFlight) {
+                frontier_url.leased_at = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    async fn requeue_expired(&self, lease_timeout: Duration) -> Result<u64, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let expired_at = now - lease_timeout;
+        let mut frontier = self.write();
+        let mut in_flight: Vec<&mut FrontierUrl> = frontier.urls.values_mut()
+            .filter(|frontier_url| frontier_url.state == FrontierState::InFlight)
+            .filter(|frontier_url| frontier_url.leased_at.is_some_and(|leased_at| leased_at < expired_at))
+            .collect();
+        // Requeue in the order they were leased.
+        in_flight.sort_by_key(|frontier_url| (frontier_url.updated_at, frontier_url.id));
+        let mut requeued = Vec::with_capacity(in_flight.len());
+        for frontier_url in in_flight {
+            frontier_url.state = FrontierState::Queued;
+            frontier_url.leased_at = None;
+            frontier_url.updated_at = now;
+            requeued.push((frontier_url.id, frontier_url.rank));
+        }
+        let count = requeued.len() as u64;
+        for (id, rank) in requeued {
+            frontier.push(id, rank);
+        }
+        Ok(count)
+    }
+
+    async fn record_crawl(&self, url: &url::Url, changed: bool) -> Result<FrontierUrl, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut frontier = self.write();
+        let id = match frontier.url_ids.get(url.as_str()).copied() {
+            Some(id) => id,
+            None => {
+                let rank = url.host_str().and_then(|host| frontier.domain_ranks.get(&host.to_lowercase()).copied());
+                frontier.insert(url, FrontierState::Done, rank, CrawlScope::Page, now)
+            }
+        };
+        let frontier_url = frontier.urls.get_mut(&id).ok_or("Url is not in the frontier")?;
+        frontier_url.crawl_count += 1;
+        // The first crawl of a url can't find it changed.
+        if changed && frontier_url.crawl_count > 1 {
+            frontier_url.change_count += 1;
+        }
+        frontier_url.crawled_at = Some(now);
+        frontier_url.updated_at = now;
+        Ok(frontier_url.clone())
+    }
+
+    async fn schedule_recrawl(&self, id: Uuid, next_crawl_at: OffsetDateTime) -> Result<(), Box<dyn std::error::Error>> {
+        let mut frontier = self.write();
+        let frontier_url = frontier.urls.get_mut(&id).ok_or("Url is not in the frontier")?;
+        frontier_url.next_crawl_at = Some(next_crawl_at);
+        Ok(())
+    }
+
+    async fn requeue_due(&self, limit: usize) -> Result<u64, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut frontier = self.write();
+        let mut due: Vec<&mut FrontierUrl> = frontier.urls.values_mut()
+            .filter(|frontier_url| frontier_url.state == FrontierState::Done)
+            .filter(|frontier_url| frontier_url.next_crawl_at.is_some_and(|next_crawl_at| next_crawl_at <= now))
+            .collect();
+        due.sort_by_key(|frontier_url| (frontier_url.next_crawl_at, frontier_url.id));
+        let mut requeued = Vec::with_capacity(limit.min(due.len()));
+        for frontier_url in due.into_iter().take(limit) {
+            frontier_url.state = FrontierState::Queued;
+            frontier_url.attempts = 0;
+            frontier_url.last_error = None;
+            frontier_url.next_crawl_at = None;
+            frontier_url.updated_at = now;
+            requeued.push((frontier_url.id, frontier_url.rank));
+        }
+        let count = requeued.len() as u64;
+        for (id, rank) in requeued {
+            frontier.push(id, rank);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<Site> {
+        urls.iter().map(|url| Site { url: url::Url::parse(url).unwrap(), rank: None, scope: CrawlScope::Site, lastmod: None, priority: None }).collect()
+    }
+
+    // Urls are leased once, retried until they fail for good and queued again once their lease expires
+    #[tokio::test]
+    async fn can_lease_urls() {
+        let frontier = MemoryFrontier::new();
+        assert_eq!(frontier.enqueue(&urls(&["https://a.example/", "https://b.example/"])).await.unwrap(), 2);
+        assert_eq!(frontier.enqueue(&urls(&["https://a.example/", "https://c.example/"])).await.unwrap(), 1);
+
+        let leased = frontier.lease(2).await.unwrap();
+        assert_eq!(leased.iter().map(|frontier_url| frontier_url.url.as_str()).collect::<Vec<&str>>(), vec!["https://a.example/", "https://b.example/"]);
+        frontier.complete(leased[0].id, None).await.unwrap();
+        assert_eq!(frontier.fail(leased[1].id, "timed out", 2).await.unwrap(), FrontierState::Queued);
+
+        // The failed url goes behind the urls queued before it failed.
+        let leased = frontier.lease(10).await.unwrap();
+        assert_eq!(leased.iter().map(|frontier_url| frontier_url.url.as_str()).collect::<Vec<&str>>(), vec!["https://c.example/", "https://b.example/"]);
+        assert_eq!(frontier.fail(leased[1].id, "timed out", 2).await.unwrap(), FrontierState::Failed);
+
+        assert_eq!(frontier.requeue_expired(Duration::from_secs(60)).await.unwrap(), 0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        frontier.renew(&[leased[0].id]).await.unwrap();
+        assert_eq!(frontier.requeue_expired(Duration::from_millis(10)).await.unwrap(), 0);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(frontier.requeue_expired(Duration::from_millis(10)).await.unwrap(), 1);
+        let leased = frontier.lease(10).await.unwrap();
+        assert_eq!(leased.len(), 1);
+        assert_eq!(leased[0].url, "https://c.example/");
+        assert_eq!(leased[0].attempts, 2);
+    }
+
+    // Urls of higher-ranked domains are leased first, whenever they are queued or ranked
+    #[tokio::test]
+    async fn can_lease_by_rank() {
+        let frontier = MemoryFrontier::new();
+        let mut sites = urls(&["https://a.example/", "https://b.example/", "https://c.example/"]);
+        sites[1].rank = Some(20);
+        sites[2].rank = Some(10);
+        assert_eq!(frontier.enqueue(&sites).await.unwrap(), 3);
+        // Ranking a queued url moves it ahead.
+        sites[0].rank = Some(5);
+        assert_eq!(frontier.enqueue(&sites[..1]).await.unwrap(), 1);
+
+        let leased = frontier.lease(10).await.unwrap();
+        assert_eq!(leased.iter().map(|frontier_url| frontier_url.url.as_str()).collect::<Vec<&str>>(), vec!["https://a.example/", "https://c.example/", "https://b.example/"]);
+        assert_eq!(leased[0].rank, Some(5));
+    }
+
+    // A crawled url its sitemap says was modified since is due at once
+    #[tokio::test]
+    async fn can_requeue_modified_urls() {
+        let frontier = MemoryFrontier::new();
+        let mut sites = urls(&["https://a.example/page"]);
+        frontier.enqueue(&sites).await.unwrap();
+        let leased = frontier.lease(1).await.unwrap();
+        frontier.complete(leased[0].id, None).await.unwrap();
+        let crawled = frontier.record_crawl(&sites[0].url, false).await.unwrap();
+        frontier.schedule_recrawl(crawled.id, OffsetDateTime::now_utc() + time::Duration::days(30)).await.unwrap();
+        assert_eq!(frontier.requeue_due(10).await.unwrap(), 0);
+
+        sites[0].priority = Some(0.8);
+        assert_eq!(frontier.enqueue(&sites).await.unwrap(), 1);
+        assert_eq!(frontier.requeue_due(10).await.unwrap(), 0);
+        sites[0].lastmod = Some(OffsetDateTime::now_utc() + time::Duration::seconds(1));
+        assert_eq!(frontier.enqueue(&sites).await.unwrap(), 1);
+        assert_eq!(frontier.requeue_due(10).await.unwrap(), 1);
+        let leased = frontier.lease(1).await.unwrap();
+        assert_eq!(leased[0].priority, Some(0.8));
+    }
+}
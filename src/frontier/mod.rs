@@ -0,0 +1,61 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::models::frontier::{CrawlScope, FrontierState, FrontierUrl, StopReason};
+
+mod memory;
+mod postgres;
+
+pub use memory::MemoryFrontier;
+pub use postgres::PgFrontier;
+
+/// A url to queue and the Tranco rank of its domain, `None` when the domain is not ranked.
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub url: url::Url,
+    pub rank: Option<i32>,
+    // `scope` is how much of the website crawling the url covers.
+    pub scope: CrawlScope,
+    // `lastmod` is when the site last modified the url according to its sitemaps, `None` when they don't say.
+    pub lastmod: Option<OffsetDateTime>,
+    // `priority` is how important the url is relative to the other urls of its site according to its sitemaps.
+    pub priority: Option<f32>,
+}
+
+/// Frontier holds the urls to crawl and where each of them is in the crawl, so a crawl can resume after a restart.
+#[async_trait]
+pub trait Frontier: Send + Sync {
+    /// Queue the urls that are not in the frontier yet, returning how many were queued or updated.
+    /// Urls already in the frontier keep their state, so queueing the same urls again after a restart is harmless,
+    /// but take the rank, lastmod and priority they are queued with. A crawled url modified since its last crawl is
+    /// due to be crawled again at once.
+    async fn enqueue(&self, sites: &[Site]) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Lease up to `limit` queued urls, the highest-ranked first then the least recently queued, marking them in flight.
+    async fn lease(&self, limit: usize) -> Result<Vec<FrontierUrl>, Box<dyn std::error::Error>>;
+
+    /// Mark a leased url as crawled, with the budget that stopped its crawl if one did.
+    async fn complete(&self, id: Uuid, stop_reason: Option<StopReason>) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Record a failed crawl of a leased url, queueing it again unless it was leased `max_attempts` times.
+    /// Returns the new state of the url.
+    async fn fail(&self, id: Uuid, error: &str, max_attempts: i32) -> Result<FrontierState, Box<dyn std::error::Error>>;
+
+    /// Renew the leases of urls still in flight, so they are not taken for urls a stopped pipeline left in flight.
+    async fn renew(&self, ids: &[Uuid]) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Queue again the urls whose lease was not renewed for `lease_timeout`, left in flight by a pipeline that stopped,
+    /// returning how many were queued.
+    async fn requeue_expired(&self, lease_timeout: Duration) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Count a crawl of a url, whether it found the url changed or not, returning the url with its crawl history.
+    /// A url that is not in the frontier yet is added as a crawled page, with the rank of its domain.
+    async fn record_crawl(&self, url: &url::Url, changed: bool) -> Result<FrontierUrl, Box<dyn std::error::Error>>;
+
+    /// Set when a crawled url is due to be crawled again.
+    async fn schedule_recrawl(&self, id: Uuid, next_crawl_at: OffsetDateTime) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Queue again up to `limit` crawled urls that are due, the most overdue first, returning how many were queued.
+    async fn requeue_due(&self, limit: usize) -> Result<u64, Box<dyn std::error::Error>>;
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::frontier::{Frontier, Site};
+use crate::models::domain::Domain;
+use crate::models::frontier::{CrawlScope, FrontierState, FrontierUrl, StopReason};
+
+/// PgFrontier keeps the frontier in postgres, with the rank of the domains it was given.
+pub struct PgFrontier {
+    // `db` is a postgres connection pool.
+    db: sqlx::PgPool,
+}
+
+impl PgFrontier {
+    /// Create a new PgFrontier instance.
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self {
+            db,
+        }
+    }
+}
+
+#[async_trait]
+impl Frontier for PgFrontier {
+    async fn enqueue(&self, sites: &[Site]) -> Result<u64, Box<dyn std::error::Error>> {
+        // A url or host may only appear once in an upsert, the best rank, latest lastmod and highest priority win.
+        let mut scope_sites: HashMap<CrawlScope, HashMap<String, Site>> = HashMap::new();
+        let mut host_ranks: HashMap<String, i32> = HashMap::new();
+        for site in sites {
+            scope_sites.entry(site.scope).or_default().entry(site.url.to_string())
+                .and_modify(|queued| merge_site(queued, site))
+                .or_insert_with(|| site.clone());
+            if let (Some(host), Some(rank)) = (site.url.host_str(), site.rank) {
+                let host_rank = host_ranks.entry(host.to_lowercase()).or_insert(rank);
+                *host_rank = (*host_rank).min(rank);
+            }
+        }
+        let (hosts, host_ranks): (Vec<String>, Vec<i32>) = host_ranks.into_iter().unzip();
+
+        let mut tx = self.db.begin().await.map_err(|e| format!("Error starting transaction: {:?}", e))?;
+        let mut queued = 0;
+        for (scope, sites) in scope_sites {
+            let mut urls = Vec::with_capacity(sites.len());
+            let mut ranks = Vec::with_capacity(sites.len());
+            let mut lastmods = Vec::with_capacity(sites.len());
+            let mut priorities = Vec::with_capacity(sites.len());
+            for (url, site) in sites {
+                urls.push(url);
+                ranks.push(site.rank);
+                lastmods.push(site.lastmod);
+                priorities.push(site.priority);
+            }
+            queued += FrontierUrl::insert_many(&mut *tx, &urls, &ranks, &lastmods, &priorities, scope).await.map_err(|e| format!("Error queueing urls: {:?}", e))?;
+        }
+        if !hosts.is_empty() {
+            Domain::upsert_many(&mut *tx, &hosts, &host_ranks).await.map_err(|e| format!("Error ranking domains: {:?}", e))?;
+        }
+        tx.commit().await.map_err(|e| format!("Error committing transaction: {:?}", e))?;
+        Ok(queued)
+    }
+
+    async fn lease(&self, limit: usize) -> Result<Vec<FrontierUrl>, Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::lease(&self.db, limit as i64).await.map_err(|e| format!("Error leasing urls: {:?}", e))?)
+    }
+
+    async fn complete(&self, id: Uuid, stop_reason: Option<StopReason>) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::complete(&self.db, id, stop_reason).await.map_err(|e| format!("Error completing url: {:?}", e))?)
+    }
+
+    async fn fail(&self, id: Uuid, error: &str, max_attempts: i32) -> Result<FrontierState, Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::fail(&self.db, id, error, max_attempts).await.map_err(|e| format!("Error failing url: {:?}", e))?)
+    }
+
+    async fn renew(&self, ids: &[Uuid]) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::renew(&self.db, ids).await.map_err(|e| format!("Error renewing leases: {:?}", e))?)
+    }
+
+    async fn requeue_expired(&self, lease_timeout: Duration) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::requeue_expired(&self.db, lease_timeout.as_secs_f64()).await.map_err(|e| format!("Error requeueing urls: {:?}", e))?)
+    }
+
+    async fn record_crawl(&self, url: &url::Url, changed: bool) -> Result<FrontierUrl, Box<dyn std::error::Error>> {
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        Ok(FrontierUrl::record_crawl(&self.db, url.as_str(), &host, changed).await.map_err(|e| format!("Error recording crawl: {:?}", e))?)
+    }
+
+    async fn schedule_recrawl(&self, id: Uuid, next_crawl_at: OffsetDateTime) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::schedule_recrawl(&self.db, id, next_crawl_at).await.map_err(|e| format!("Error scheduling recrawl: {:?}", e))?)
+    }
+
+    async fn requeue_due(&self, limit: usize) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(FrontierUrl::requeue_due(&self.db, limit as i64).await.map_err(|e| format!("Error requeueing due urls: {:?}", e))?)
+    }
+}
+
+/// Merge a site queued twice into the first, keeping the best rank, latest lastmod and highest priority.
+fn merge_site(queued: &mut Site, site: &Site) {
+    queued.rank = match (queued.rank, site.rank) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    queued.lastmod = queued.lastmod.max(site.lastmod);
+    queued.priority = match (queued.priority, site.priority) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    };
+}
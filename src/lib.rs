@@ -1,2 +1,5 @@
+pub mod frontier;
 pub mod models;
-pub mod services;
\ No newline at end of file
+pub mod segment;
+pub mod services;
+pub mod store;
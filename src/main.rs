@@ -1,11 +1,45 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 use crossbeam_channel::unbounded;
-use sqlx::PgPool;
-use search_engine::services::{Crawler, FileReader, PageParser, SitePool, TextPool};
+use search_engine::frontier::PgFrontier;
+use search_engine::services::{CrawlBudgets, Crawler, DnsResolver, FileReader, PageParser, PolitenessPolicy, RecrawlPolicy, RecrawlScheduler, SitePool, TextPool};
+#[cfg(not(feature = "segment"))]
+use search_engine::store::PgIndexStore;
+#[cfg(feature = "segment")]
+use search_engine::store::SegmentIndexStore;
 
 #[macro_use]
 extern crate dotenv_codegen;
 
+// Number of crawlers crawling sites concurrently.
+const CRAWLERS: usize = 10;
+// Shortest time between two requests to a host, or two crawls starting on an IP address.
+const MIN_CRAWL_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+// Longest Crawl-delay of a robots.txt that is honoured.
+const MAX_CRAWL_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+// Number of crawls of a host at once.
+const MAX_CRAWLS_PER_HOST: usize = 1;
+// Number of crawls of the hosts sharing an IP address at once.
+const MAX_CRAWLS_PER_IP: usize = 2;
+// Shortest wait before crawling a page again, for pages that change on every crawl.
+const MIN_RECRAWL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+// Longest wait before crawling a page again, for pages that never change.
+const MAX_RECRAWL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30 * 24 * 60 * 60);
+// Number of text pool workers writing pages to the database concurrently.
+const TEXT_POOL_WORKERS: usize = 4;
+// Number of keyword ids kept in memory, shared by the text pool workers.
+#[cfg(not(feature = "segment"))]
+const KEYWORD_CACHE_CAPACITY: usize = 100_000;
+// Number of pages buffered in memory before they are written as a segment.
+#[cfg(feature = "segment")]
+const SEGMENT_FLUSH_DOCS: usize = 1_000;
+// Number of segments merged together at once.
+#[cfg(feature = "segment")]
+const SEGMENT_MERGE_FACTOR: usize = 10;
+// How often the buffered pages are flushed and the segments merged.
+#[cfg(feature = "segment")]
+const SEGMENT_MAINTENANCE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Open the CSV file - https://tranco-list.eu/list/XJJZN/1000000, need to add "rank,root_domain" as the first line
@@ -14,49 +48,67 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Open the lemmatizer JSON file - https://github.com/conaticus/search-engine-crawler/blob/dev/lemmatizedMap.json - credit to conaticus
     let lemmatizer_json_path = dotenv!("LEMMATIZER_JSON_PATH");
     let lemmatizer_json_path_buf = PathBuf::from(lemmatizer_json_path);
-    let host = dotenv!("DB_HOST");
-    let port = dotenv!("DB_PORT").parse().expect("DB_PORT must be a number");
-    let username = dotenv!("DB_USERNAME");
-    let password = dotenv!("DB_PASSWORD");
-    let database = dotenv!("DB_DATABASE");
-    let db_options = sqlx::postgres::PgConnectOptions::new()
-        .host(host)
-        .port(port)
-        .username(username)
-        .password(password)
-        .database(database);
-    let db = PgPool::connect_with(db_options).await.map_err(|e| {println!("Error connecting to database: {:?}", e);e})?;
+    // Open the crawl budgets JSON file, limiting how much of every site is crawled with overrides per domain
+    let crawl_budgets_path = dotenv!("CRAWL_BUDGETS_PATH");
+    let crawl_budgets = Arc::new(CrawlBudgets::load(PathBuf::from(crawl_budgets_path)).map_err(|e| {
+        println!("Error loading crawl budgets: {:?}", e);
+        e
+    })?);
 
     // url channel
     let (url_sender, url_receiver) = unbounded();
     // crawler channel
     let (crawler_sender, crawler_receiver) = unbounded();
+    // crawl outcome channel
+    let (outcome_sender, outcome_receiver) = unbounded();
     // page channel
     let (page_sender, page_receiver) = unbounded();
     // text channel
     let (text_sender, text_receiver) = unbounded();
+    // crawl report channel
+    let (report_sender, report_receiver) = unbounded();
 
     // Create a new FileReader
-    let file_reader = FileReader::new(sites_path_buf, url_sender).await.map_err(|e| {
+    let file_reader = FileReader::new(sites_path_buf, url_sender.clone()).await.map_err(|e| {
         println!("Error creating file reader: {:?}", e);
         e
     })?;
-    // Create a site pool
-    let site_pool = SitePool::new(url_receiver, crawler_sender);
+    // The frontier is kept in postgres whichever store holds the index, so the crawl resumes after a restart
+    let db = connect_db().await?;
+    let frontier = Arc::new(PgFrontier::new(db.clone()));
+    // Create a site pool leasing the sites to crawl from the frontier and scheduling them politely per host
+    let policy = PolitenessPolicy {
+        min_delay: MIN_CRAWL_DELAY,
+        max_crawl_delay: MAX_CRAWL_DELAY,
+        max_per_host: MAX_CRAWLS_PER_HOST,
+        max_per_ip: MAX_CRAWLS_PER_IP,
+    };
+    let site_pool = SitePool::new(url_receiver, crawler_sender, outcome_receiver, frontier.clone(), Arc::new(DnsResolver), CRAWLERS, policy);
+    // Create a recrawl scheduler queueing the crawled pages again once they are due
+    let recrawl_policy = RecrawlPolicy {
+        min_interval: MIN_RECRAWL_INTERVAL,
+        max_interval: MAX_RECRAWL_INTERVAL,
+    };
+    let recrawl_scheduler = RecrawlScheduler::new(report_receiver, frontier, recrawl_policy);
     // Create multiple crawlers
     let mut crawlers = Vec::new();
-    for _ in 0..10 {
-        let crawler = Crawler::new(page_sender.clone(), crawler_receiver.clone());
+    for _ in 0..CRAWLERS {
+        let crawler = Crawler::new(page_sender.clone(), crawler_receiver.clone(), outcome_sender.clone(), url_sender.clone(), crawl_budgets.clone(), policy);
         crawlers.push(crawler);
     }
     // Create Page Parser
     let page_parser = PageParser::new(page_receiver, text_sender, lemmatizer_json_path_buf).map_err(|e| {
-        format!("Error creating page parser: {:?}", e);
+        println!("Error creating page parser: {:?}", e);
         e
     })?;
 
-    // Create a text pool
-    let text_pool = TextPool::new(text_receiver, db);
+    // Create multiple text pools sharing the index store and its keyword cache
+    let store = Arc::new(open_store(&db)?);
+    let mut text_pools = Vec::new();
+    for _ in 0..TEXT_POOL_WORKERS {
+        let text_pool = TextPool::new(text_receiver.clone(), store.clone(), report_sender.clone());
+        text_pools.push(text_pool);
+    }
 
     // Start all services
     tokio::spawn(async move {
@@ -74,10 +126,86 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         page_parser.start().await;
     });
     tokio::spawn(async move {
-        text_pool.start().await;
-    }).await.map_err(|e| {
-        println!("Error starting text pool: {:?}", e);
+        recrawl_scheduler.start().await;
+    });
+    // Flush and merge the segments in the background
+    #[cfg(feature = "segment")]
+    {
+        let store = store.clone();
+        tokio::spawn(async move {
+            store.start(SEGMENT_MAINTENANCE_INTERVAL).await;
+        });
+    }
+    let mut text_pool_handles = Vec::new();
+    for text_pool in text_pools {
+        text_pool_handles.push(tokio::spawn(async move {
+            text_pool.start().await;
+        }));
+    }
+    // Index until the text pools stop or the process is asked to stop
+    tokio::select! {
+        result = futures::future::try_join_all(text_pool_handles) => {
+            result.map_err(|e| {
+                println!("Error starting text pool: {:?}", e);
+                e
+            })?;
+        }
+        result = shutdown_signal() => {
+            result.map_err(|e| {
+                println!("Error waiting for shutdown: {:?}", e);
+                e
+            })?;
+            println!("Shutting down.");
+        }
+    }
+    // Write the pages still buffered in memory, they would be lost otherwise
+    #[cfg(feature = "segment")]
+    tokio::task::block_in_place(|| store.flush()).map_err(|e| {
+        println!("Error flushing segment: {:?}", e);
         e
     })?;
     Ok(())
 }
+
+/// Wait for Ctrl-C or SIGTERM.
+async fn shutdown_signal() -> std::io::Result<()> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+/// Connect to postgres.
+async fn connect_db() -> Result<sqlx::PgPool, Box<dyn std::error::Error>> {
+    let host = dotenv!("DB_HOST");
+    let port = dotenv!("DB_PORT").parse().expect("DB_PORT must be a number");
+    let username = dotenv!("DB_USERNAME");
+    let password = dotenv!("DB_PASSWORD");
+    let database = dotenv!("DB_DATABASE");
+    let db_options = sqlx::postgres::PgConnectOptions::new()
+        .host(host)
+        .port(port)
+        .username(username)
+        .password(password)
+        .database(database);
+    let db = sqlx::PgPool::connect_with(db_options).await.map_err(|e| {println!("Error connecting to database: {:?}", e);e})?;
+    Ok(db)
+}
+
+/// Open the postgres index.
+#[cfg(not(feature = "segment"))]
+fn open_store(db: &sqlx::PgPool) -> Result<PgIndexStore, Box<dyn std::error::Error>> {
+    Ok(PgIndexStore::new(db.clone(), KEYWORD_CACHE_CAPACITY))
+}
+
+/// Open the local segment index, postgres only holds the frontier.
+#[cfg(feature = "segment")]
+fn open_store(_db: &sqlx::PgPool) -> Result<SegmentIndexStore, Box<dyn std::error::Error>> {
+    let segment_index_path = PathBuf::from(dotenv!("SEGMENT_INDEX_PATH"));
+    let store = SegmentIndexStore::open(&segment_index_path, SEGMENT_FLUSH_DOCS, SEGMENT_MERGE_FACTOR).map_err(|e| {
+        println!("Error opening segment index: {:?}", e);
+        e
+    })?;
+    Ok(store)
+}
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+// Number of rows the counts are split over, as created by the migration.
+const SHARDS: u128 = 16;
+
+/// Corpus wide statistics, kept up to date by the text pool as websites are indexed.
+/// The counts are the sums of shards, so that concurrent writers do not wait on each other.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CorpusStats {
+    pub document_count: i64,
+    pub total_word_count: i64,
+    // `version` is bumped every time a website is added or reindexed.
+    pub version: i64,
+    // `idf_version` is the version the stored idf of every keyword was last recomputed at.
+    pub idf_version: i64,
+    pub idf_recomputed_at: Option<OffsetDateTime>,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) updated_at: OffsetDateTime,
+}
+
+impl CorpusStats {
+    pub async fn get<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            CorpusStats,
+            r#"
+            SELECT SUM(shards.document_count)::BIGINT AS "document_count!",
+                SUM(shards.total_word_count)::BIGINT AS "total_word_count!",
+                SUM(shards.version)::BIGINT AS "version!",
+                corpus_stats.idf_version,
+                corpus_stats.idf_recomputed_at,
+                corpus_stats.created_at,
+                MAX(shards.updated_at) AS "updated_at!"
+            FROM corpus_stats, corpus_stats_shards AS shards
+            GROUP BY corpus_stats.id
+            "#,
+        ).fetch_one(executor).await
+    }
+
+    /// Add `document_delta` documents and `word_count_delta` words to the corpus, moving it to a new version.
+    /// The change goes to the shard of the website, only the writers of websites of the same shard wait on each other.
+    pub async fn adjust<'e, E: sqlx::PgExecutor<'e>>(executor: E, website_id: uuid::Uuid, document_delta: i64, word_count_delta: i64) -> Result<(), sqlx::Error> {
+        let shard = (website_id.as_u128() % SHARDS) as i16;
+        sqlx::query!(
+            r#"
+            UPDATE corpus_stats_shards
+            SET document_count = document_count + $1,
+                total_word_count = total_word_count + $2,
+                version = version + 1,
+                updated_at = NOW()
+            WHERE shard = $3
+            "#,
+            document_delta,
+            word_count_delta,
+            shard
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Record that the stored idf was recomputed against the corpus at `version`.
+    pub async fn mark_idf_recomputed(pool: &sqlx::PgPool, version: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE corpus_stats
+            SET idf_version = $1,
+                idf_recomputed_at = NOW()
+            "#,
+            version
+        )
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Whether the corpus changed since the stored idf was last recomputed.
+    pub fn is_idf_stale(&self) -> bool {
+        self.idf_version < self.version
+    }
+
+    /// Average number of words per document.
+    pub fn average_document_length(&self) -> f64 {
+        if self.document_count == 0 {
+            return 0.0;
+        }
+        self.total_word_count as f64 / self.document_count as f64
+    }
+}
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Domain {
+    pub(crate) host: String,
+    // `rank` is the Tranco rank of the domain, 1 being the most popular.
+    pub(crate) rank: i32,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) updated_at: OffsetDateTime,
+}
+
+impl Domain {
+    /// Record the rank of the hosts, `hosts` are unique and in the same order as `ranks`.
+    /// A host ranked again takes its new rank.
+    pub async fn upsert_many<'e, E: sqlx::PgExecutor<'e>>(executor: E, hosts: &[String], ranks: &[i32]) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO domains (host, rank)
+            SELECT * FROM UNNEST($1::text[], $2::int[])
+            ON CONFLICT (host) DO UPDATE
+            SET rank = EXCLUDED.rank, updated_at = NOW()
+            WHERE domains.rank <> EXCLUDED.rank
+            "#,
+            hosts,
+            ranks
+        )
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// Where a url of the frontier is in the crawl.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "frontier_state", rename_all = "snake_case")]
+pub enum FrontierState {
+    // `Queued` is a url waiting for a crawler.
+    Queued,
+    // `InFlight` is a url leased to a crawler.
+    InFlight,
+    // `Done` is a url that was crawled.
+    Done,
+    // `Failed` is a url that failed to crawl too many times.
+    Failed,
+}
+
+/// How much of a website the crawl of a url covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "crawl_scope", rename_all = "snake_case")]
+pub enum CrawlScope {
+    // `Site` is a url crawled with every page it links to.
+    Site,
+    // `Page` is a url crawled alone.
+    Page,
+}
+
+/// Which budget stopped the crawl of a site before it reached every page it links to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "crawl_stop_reason", rename_all = "snake_case")]
+pub enum StopReason {
+    // `MaxPages` is a crawl that fetched as many pages as its budget allows.
+    MaxPages,
+    // `MaxBytes` is a crawl that downloaded as many bytes as its budget allows.
+    MaxBytes,
+    // `Timeout` is a crawl that ran as long as its budget allows.
+    Timeout,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrontierUrl {
+    pub(crate) id: uuid::Uuid,
+    pub(crate) url: String,
+    pub(crate) state: FrontierState,
+    // `attempts` is the number of times the url was leased to a crawler.
+    pub(crate) attempts: i32,
+    // `last_error` is why the last crawl of the url failed.
+    pub(crate) last_error: Option<String>,
+    // `rank` is the Tranco rank of the domain of the url, `None` when it is not ranked.
+    pub(crate) rank: Option<i32>,
+    pub(crate) scope: CrawlScope,
+    // `crawl_count` is the number of times the url was crawled, `change_count` how many of those found it changed.
+    pub(crate) crawl_count: i32,
+    pub(crate) change_count: i32,
+    pub(crate) crawled_at: Option<OffsetDateTime>,
+    // `next_crawl_at` is when the crawled url is due to be crawled again.
+    pub(crate) next_crawl_at: Option<OffsetDateTime>,
+    // `lastmod` is when the site last modified the url, according to its sitemaps.
+    pub(crate) lastmod: Option<OffsetDateTime>,
+    // `priority` is how important the url is relative to the other urls of its site according to its sitemaps, from 0 to 1.
+    pub(crate) priority: Option<f32>,
+    // `stop_reason` is the budget that stopped the last crawl of the url, `None` when it crawled every page it could reach.
+    pub(crate) stop_reason: Option<StopReason>,
+    pub(crate) leased_at: Option<OffsetDateTime>,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) updated_at: OffsetDateTime,
+}
+
+impl FrontierUrl {
+    /// Queue the urls that are not in the frontier yet with the scope, `urls` are in the same order as `ranks`,
+    /// `lastmods` and `priorities`.
+    /// A url already in the frontier keeps its state and takes the rank, lastmod and priority it is queued with, unless
+    /// they are `None`. A crawled url modified since its last crawl is due to be crawled again at once.
+    /// Returns how many urls were queued or updated.
+    pub async fn insert_many<'e, E: sqlx::PgExecutor<'e>>(executor: E, urls: &[String], ranks: &[Option<i32>], lastmods: &[Option<OffsetDateTime>], priorities: &[Option<f32>], scope: CrawlScope) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO frontier (url, rank, lastmod, priority, scope)
+            SELECT url, rank, lastmod, priority, $5::crawl_scope
+            FROM UNNEST($1::text[], $2::int[], $3::timestamptz[], $4::real[]) AS sites (url, rank, lastmod, priority)
+            ON CONFLICT (url) DO UPDATE
+            SET rank = COALESCE(EXCLUDED.rank, frontier.rank),
+                lastmod = COALESCE(EXCLUDED.lastmod, frontier.lastmod),
+                priority = COALESCE(EXCLUDED.priority, frontier.priority),
+                next_crawl_at = CASE
+                    WHEN frontier.state = 'done' AND EXCLUDED.lastmod > frontier.crawled_at THEN NOW()
+                    ELSE frontier.next_crawl_at
+                END
+            WHERE (EXCLUDED.rank IS NOT NULL AND frontier.rank IS DISTINCT FROM EXCLUDED.rank)
+                OR (EXCLUDED.lastmod IS NOT NULL AND frontier.lastmod IS DISTINCT FROM EXCLUDED.lastmod)
+                OR (EXCLUDED.priority IS NOT NULL AND frontier.priority IS DISTINCT FROM EXCLUDED.priority)
+            "#,
+            urls,
+            ranks as &[Option<i32>],
+            lastmods as &[Option<OffsetDateTime>],
+            priorities as &[Option<f32>],
+            scope as CrawlScope
+        )
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Lease up to `limit` queued urls, the highest-ranked first then the least recently queued, marking them in flight.
+    /// Skips the rows other transactions are leasing, so several crawl pipelines can share the frontier as long as each
+    /// renews the leases of the urls it holds.
+    pub async fn lease<'e, E: sqlx::PgExecutor<'e>>(executor: E, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            FrontierUrl,
+            r#"
+            UPDATE frontier
+            SET state = 'in_flight', attempts = attempts + 1, leased_at = NOW(), updated_at = NOW()
+            WHERE id IN (
+                SELECT id FROM frontier
+                WHERE state = 'queued'
+                ORDER BY rank NULLS LAST, updated_at, id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, url, state AS "state: FrontierState", attempts, last_error, rank, scope AS "scope: CrawlScope",
+                crawl_count, change_count, crawled_at, next_crawl_at, lastmod, priority,
+                stop_reason AS "stop_reason?: StopReason", leased_at, created_at, updated_at
+            "#,
+            limit
+        ).fetch_all(executor).await
+    }
+
+    /// Mark a leased url as crawled, with the budget that stopped its crawl if one did.
+    pub async fn complete<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, stop_reason: Option<StopReason>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE frontier
+            SET state = 'done', last_error = NULL, stop_reason = $2, leased_at = NULL, updated_at = NOW()
+            WHERE id = $1
+            "#,
+            id,
+            stop_reason as Option<StopReason>
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Record a failed crawl of a leased url, queueing it again unless it was leased `max_attempts` times.
+    /// Returns the new state of the url.
+    pub async fn fail<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, error: &str, max_attempts: i32) -> Result<FrontierState, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE frontier
+            SET state = CASE WHEN attempts >= $3 THEN 'failed'::frontier_state ELSE 'queued'::frontier_state END,
+                last_error = $2, leased_at = NULL, updated_at = NOW()
+            WHERE id = $1
+            RETURNING state AS "state: FrontierState"
+            "#,
+            id,
+            error,
+            max_attempts
+        )
+            .fetch_one(executor)
+            .await?;
+        Ok(row.state)
+    }
+
+    /// Renew the leases of the urls still in flight.
+    pub async fn renew<'e, E: sqlx::PgExecutor<'e>>(executor: E, ids: &[uuid::Uuid]) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE frontier
+            SET leased_at = NOW()
+            WHERE state = 'in_flight' AND id = ANY($1)
+            "#,
+            ids
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Queue again the urls leased more than `lease_timeout_secs` seconds ago, returning how many were queued.
+    pub async fn requeue_expired<'e, E: sqlx::PgExecutor<'e>>(executor: E, lease_timeout_secs: f64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE frontier
+            SET state = 'queued', leased_at = NULL, updated_at = NOW()
+            WHERE state = 'in_flight' AND leased_at < NOW() - make_interval(secs => $1)
+            "#,
+            lease_timeout_secs
+        )
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Count a crawl of a url, whether it found the url changed or not, returning the url with its crawl history.
+    /// A url that is not in the frontier yet is added as a crawled page, with the rank of its domain.
+    /// The first crawl of a url can't find it changed.
+    pub async fn record_crawl<'e, E: sqlx::PgExecutor<'e>>(executor: E, url: &str, host: &str, changed: bool) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            FrontierUrl,
+            r#"
+            INSERT INTO frontier (url, state, rank, scope, crawl_count, change_count, crawled_at)
+            VALUES ($1, 'done', (SELECT rank FROM domains WHERE host = $2), 'page', 1, 0, NOW())
+            ON CONFLICT (url) DO UPDATE
+            SET crawl_count = frontier.crawl_count + 1,
+                change_count = frontier.change_count + CASE WHEN $3 AND frontier.crawl_count > 0 THEN 1 ELSE 0 END,
+                crawled_at = NOW(), updated_at = NOW()
+            RETURNING id, url, state AS "state: FrontierState", attempts, last_error, rank, scope AS "scope: CrawlScope",
+                crawl_count, change_count, crawled_at, next_crawl_at, lastmod, priority,
+                stop_reason AS "stop_reason?: StopReason", leased_at, created_at, updated_at
+            "#,
+            url,
+            host,
+            changed
+        ).fetch_one(executor).await
+    }
+
+    /// Set when a url is due to be crawled again.
+    pub async fn schedule_recrawl<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, next_crawl_at: OffsetDateTime) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE frontier
+            SET next_crawl_at = $2
+            WHERE id = $1
+            "#,
+            id,
+            next_crawl_at
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Queue again up to `limit` crawled urls that are due, the most overdue first, returning how many were queued.
+    pub async fn requeue_due<'e, E: sqlx::PgExecutor<'e>>(executor: E, limit: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query!(
+            r#"
+            UPDATE frontier
+            SET state = 'queued', attempts = 0, last_error = NULL, next_crawl_at = NULL, updated_at = NOW()
+            WHERE id IN (
+                SELECT id FROM frontier
+                WHERE state = 'done' AND next_crawl_at <= NOW()
+                ORDER BY next_crawl_at, id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            "#,
+            limit
+        )
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
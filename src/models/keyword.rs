@@ -1,23 +1,33 @@
+use std::collections::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Keyword {
     pub(crate) id: uuid::Uuid,
     pub(crate) keyword: String,
-    created_at: time::OffsetDateTime,
-    updated_at: time::OffsetDateTime,
+    pub(crate) created_at: time::OffsetDateTime,
+    pub(crate) updated_at: time::OffsetDateTime,
 }
 
 
 
+/// A keyword with the number of websites containing it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeywordFrequency {
+    pub keyword: String,
+    // `surface` is the word the keyword was first normalized from, the keyword itself when it is not known.
+    pub surface: String,
+    pub document_frequency: i64,
+    pub created_at: time::OffsetDateTime,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InsertKeywordDao {
     pub keyword: String,
 }
 
 impl Keyword {
-    pub async fn insert(pool: &sqlx::PgPool, insert_keyword: InsertKeywordDao) -> Result<Self, sqlx::Error> {
+    pub async fn insert<'e, E: sqlx::PgExecutor<'e>>(executor: E, insert_keyword: InsertKeywordDao) -> Result<Self, sqlx::Error> {
         let row = sqlx::query!(
             r#"
             INSERT INTO keywords ( keyword)
@@ -26,7 +36,7 @@ impl Keyword {
             "#,
             insert_keyword.keyword,
         )
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await?;
 
         Ok(Self {
@@ -37,7 +47,7 @@ impl Keyword {
         })
     }
 
-    pub async fn find_by_word(pool: &sqlx::PgPool, keyword: &str) -> Result<Self, sqlx::Error> {
+    pub async fn find_by_word<'e, E: sqlx::PgExecutor<'e>>(executor: E, keyword: &str) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             Keyword,
             r#"
@@ -46,21 +56,97 @@ impl Keyword {
             WHERE keyword = $1
             "#,
             keyword
-        ).fetch_one(pool).await
+        ).fetch_one(executor).await
+    }
+
+    pub async fn find_by_words<'e, E: sqlx::PgExecutor<'e>>(executor: E, keywords: &[String]) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Keyword,
+            r#"
+            SELECT id, keyword, created_at, updated_at
+            FROM keywords
+            WHERE keyword = ANY($1)
+            "#,
+            keywords
+        ).fetch_all(executor).await
+    }
+
+    /// Insert many keywords with their surface form in one statement, skipping the ones that already exist.
+    /// Only the keywords inserted by this statement are returned.
+    pub async fn insert_many<'e, E: sqlx::PgExecutor<'e>>(executor: E, keywords: &[String], surfaces: &[Option<String>]) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Keyword,
+            r#"
+            INSERT INTO keywords (keyword, surface)
+            SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[])
+            ON CONFLICT (keyword) DO NOTHING
+            RETURNING id, keyword, created_at, updated_at
+            "#,
+            keywords,
+            surfaces as &[Option<String>]
+        ).fetch_all(executor).await
+    }
+
+    /// Find the keywords, creating the missing ones with their surface form, in at most three round trips whatever the
+    /// number of keywords. Safe against concurrent writers creating the same keywords.
+    pub async fn find_or_create_many(conn: &mut sqlx::PgConnection, keywords: &[String], surfaces: &HashMap<String, String>) -> Result<Vec<Self>, sqlx::Error> {
+        let mut found = Self::find_by_words(&mut *conn, keywords).await?;
+        let mut missing = missing_keywords(keywords, &found);
+        if missing.is_empty() {
+            return Ok(found);
+        }
+        // Insert in a fixed order so that writers creating overlapping keywords wait on each other instead of deadlocking.
+        missing.sort();
+        let missing_surfaces: Vec<Option<String>> = missing.iter().map(|keyword| surfaces.get(keyword).cloned()).collect();
+        found.extend(Self::insert_many(&mut *conn, &missing, &missing_surfaces).await?);
+        // The keywords skipped by the insert were committed by another writer in the meantime.
+        let skipped = missing_keywords(keywords, &found);
+        if !skipped.is_empty() {
+            found.extend(Self::find_by_words(&mut *conn, &skipped).await?);
+        }
+        Ok(found)
+    }
+
+    /// Find the keywords created at or after `since` with their surface form and document frequency, oldest first.
+    pub async fn find_frequencies_created_since(pool: &sqlx::PgPool, since: time::OffsetDateTime) -> Result<Vec<KeywordFrequency>, sqlx::Error> {
+        sqlx::query_as!(
+            KeywordFrequency,
+            r#"
+            SELECT keywords.keyword, COALESCE(keywords.surface, keywords.keyword) AS "surface!", COUNT(website_keywords.id) AS "document_frequency!", keywords.created_at
+            FROM keywords
+            LEFT JOIN website_keywords ON website_keywords.keyword_id = keywords.id
+            WHERE keywords.created_at >= $1
+            GROUP BY keywords.id
+            ORDER BY keywords.created_at
+            "#,
+            since
+        ).fetch_all(pool).await
     }
     
-    pub async fn find_or_create(pool: &sqlx::PgPool, keyword: &str) -> Result<Self, sqlx::Error> {
-        match Self::find_by_word(pool, keyword).await{
-            Ok(keyword) => Ok(keyword),
-            Err(sqlx::Error::RowNotFound) => {
-                let insert_keyword = InsertKeywordDao{
-                    keyword: keyword.to_string(),
-                };
-                Self::insert(pool, insert_keyword).await
-            },
-            Err(e) => {
-                Err(e)
-            }
+    /// Find the keyword, creating it if it doesn't exist. Safe against concurrent writers creating the same keyword.
+    pub async fn find_or_create(conn: &mut sqlx::PgConnection, keyword: &str) -> Result<Self, sqlx::Error> {
+        let inserted = sqlx::query_as!(
+            Keyword,
+            r#"
+            INSERT INTO keywords (keyword)
+            VALUES ($1)
+            ON CONFLICT (keyword) DO NOTHING
+            RETURNING id, keyword, created_at, updated_at
+            "#,
+            keyword
+        ).fetch_optional(&mut *conn).await?;
+        match inserted {
+            Some(keyword) => Ok(keyword),
+            None => Self::find_by_word(&mut *conn, keyword).await,
         }
     }
 }
+
+/// The keywords that are not in `found`.
+fn missing_keywords(keywords: &[String], found: &[Keyword]) -> Vec<String> {
+    let known: HashSet<&str> = found.iter().map(|keyword| keyword.keyword.as_str()).collect();
+    keywords.iter()
+        .filter(|keyword| !known.contains(keyword.as_str()))
+        .cloned()
+        .collect()
+}
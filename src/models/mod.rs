@@ -1,4 +1,8 @@
 pub mod keyword;
 pub mod website;
 pub mod website_keywords;
-pub mod website_keyword_tfidf;
\ No newline at end of file
+pub mod website_keyword_tfidf;
+pub mod corpus_stats;
+pub mod query_log;
+pub mod frontier;
+pub mod domain;
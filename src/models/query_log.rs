@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryLog {
+    pub(crate) id: uuid::Uuid,
+    pub(crate) query: String,
+    pub(crate) count: i64,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) updated_at: OffsetDateTime,
+}
+
+impl QueryLog {
+    /// Count one more search for the query.
+    pub async fn record(pool: &sqlx::PgPool, query: &str) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            QueryLog,
+            r#"
+            INSERT INTO query_logs (query)
+            VALUES ($1)
+            ON CONFLICT (query) DO UPDATE
+            SET count = query_logs.count + 1, updated_at = NOW()
+            RETURNING id, query, count, created_at, updated_at
+            "#,
+            query
+        ).fetch_one(pool).await
+    }
+
+    /// Find the most searched queries.
+    pub async fn find_popular(pool: &sqlx::PgPool, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            QueryLog,
+            r#"
+            SELECT id, query, count, created_at, updated_at
+            FROM query_logs
+            ORDER BY count DESC
+            LIMIT $1
+            "#,
+            limit
+        ).fetch_all(pool).await
+    }
+}
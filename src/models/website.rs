@@ -1,38 +1,69 @@
 use serde::{Deserialize, Serialize};
 use time::{OffsetDateTime};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Website {
     pub(crate) id: uuid::Uuid,
-    url: String,
+    pub(crate) url: String,
     pub(crate) word_count: i32,
-    created_at: OffsetDateTime,
-    updated_at: OffsetDateTime,
+    // `fingerprint` is the fingerprint of the normalized token stream the website was last indexed with.
+    pub(crate) fingerprint: Option<i64>,
+    // `simhash` is the simhash of the body tokens the website was last indexed with.
+    pub(crate) simhash: Option<i64>,
+    // `cluster_id` is the canonical website of the near duplicate cluster of the website, `None` when it is canonical itself.
+    pub(crate) cluster_id: Option<uuid::Uuid>,
+    pub(crate) last_seen_at: OffsetDateTime,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) updated_at: OffsetDateTime,
 }
 
 pub struct InsertWebsiteDao {
     pub url: url::Url,
     pub word_count: i32,
+    pub content: String,
+    pub fingerprint: Option<i64>,
+    pub simhash: Option<i64>,
+    pub cluster_id: Option<uuid::Uuid>,
+}
+
+/// The visible text of a website.
+#[derive(Debug)]
+pub struct WebsiteContent {
+    pub id: uuid::Uuid,
+    pub content: String,
 }
 
 impl Website {
-    pub async fn insert(pool: &sqlx::PgPool, insert_website: InsertWebsiteDao) -> Result<Self, sqlx::Error> {
+    /// The id of the canonical website of the near duplicate cluster of the website.
+    pub fn canonical_id(&self) -> uuid::Uuid {
+        self.cluster_id.unwrap_or(self.id)
+    }
+
+    pub async fn insert<'e, E: sqlx::PgExecutor<'e>>(executor: E, insert_website: InsertWebsiteDao) -> Result<Self, sqlx::Error> {
         let row = sqlx::query!(
             r#"
-            INSERT INTO websites (url, word_count)
-            VALUES ($1, $2)
-            RETURNING id, url, word_count, created_at, updated_at
+            INSERT INTO websites (url, word_count, content, fingerprint, simhash, cluster_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, url, word_count, fingerprint, simhash, cluster_id, last_seen_at, created_at, updated_at
             "#,
             insert_website.url.to_string(),
-            insert_website.word_count
+            insert_website.word_count,
+            insert_website.content,
+            insert_website.fingerprint,
+            insert_website.simhash,
+            insert_website.cluster_id
         )
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await?;
 
         Ok(Self {
             id: row.id,
             url: row.url,
             word_count: row.word_count,
+            fingerprint: row.fingerprint,
+            simhash: row.simhash,
+            cluster_id: row.cluster_id,
+            last_seen_at: row.last_seen_at,
             created_at: row.created_at,
             updated_at: row.updated_at,
         })
@@ -41,14 +72,18 @@ impl Website {
     pub async fn upsert(pool: &sqlx::PgPool, insert_website: InsertWebsiteDao) -> Result<Self, sqlx::Error> {
         let row = sqlx::query!(
             r#"
-            INSERT INTO websites (url, word_count)
-            VALUES ($1, $2)
+            INSERT INTO websites (url, word_count, content, fingerprint, simhash, cluster_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
             ON CONFLICT (url) DO UPDATE
-            SET word_count = $2
-            RETURNING id, url, word_count, created_at, updated_at
+            SET word_count = $2, content = $3, fingerprint = $4, simhash = $5, cluster_id = $6, last_seen_at = NOW()
+            RETURNING id, url, word_count, fingerprint, simhash, cluster_id, last_seen_at, created_at, updated_at
             "#,
             insert_website.url.to_string(),
-            insert_website.word_count
+            insert_website.word_count,
+            insert_website.content,
+            insert_website.fingerprint,
+            insert_website.simhash,
+            insert_website.cluster_id
         )
             .fetch_one(pool)
             .await?;
@@ -57,34 +92,50 @@ impl Website {
             id: row.id,
             url: row.url,
             word_count: row.word_count,
+            fingerprint: row.fingerprint,
+            simhash: row.simhash,
+            cluster_id: row.cluster_id,
+            last_seen_at: row.last_seen_at,
             created_at: row.created_at,
             updated_at: row.updated_at,
         })
     }
 
-    pub async fn find_by_url(pool: &sqlx::PgPool, url: String) -> Result<Self, sqlx::Error> {
+    pub async fn find_by_url<'e, E: sqlx::PgExecutor<'e>>(executor: E, url: String) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             Website,
             r#"
-            SELECT id, url, word_count, created_at, updated_at
+            SELECT id, url, word_count, fingerprint, simhash, cluster_id, last_seen_at, created_at, updated_at
             FROM websites
             WHERE url = $1
             "#,
             url
-        ).fetch_one(pool).await
+        ).fetch_one(executor).await
     }
 
     pub async fn find_by_id(pool: &sqlx::PgPool, id: uuid::Uuid) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             Website,
             r#"
-            SELECT id, url, word_count, created_at, updated_at
+            SELECT id, url, word_count, fingerprint, simhash, cluster_id, last_seen_at, created_at, updated_at
             FROM websites
             WHERE id = $1
             "#,
             id
         ).fetch_one(pool).await
     }
+
+    pub async fn find_by_ids(pool: &sqlx::PgPool, ids: &[uuid::Uuid]) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Website,
+            r#"
+            SELECT id, url, word_count, fingerprint, simhash, cluster_id, last_seen_at, created_at, updated_at
+            FROM websites
+            WHERE id = ANY($1)
+            "#,
+            ids
+        ).fetch_all(pool).await
+    }
     
     pub async fn find_or_create(pool: &sqlx::PgPool, url: &str, word_count: i32) -> Result<Self, sqlx::Error> {
         match Self::find_by_url(pool, url.to_string()).await{
@@ -93,6 +144,10 @@ impl Website {
                 let insert_website = InsertWebsiteDao{
                     url: url::Url::parse(url).unwrap(),
                     word_count,
+                    content: String::new(),
+                    fingerprint: None,
+                    simhash: None,
+                    cluster_id: None,
                 };
                 Self::insert(pool, insert_website).await
             },
@@ -101,7 +156,7 @@ impl Website {
             }
         }
     }
-    pub async fn update_word_count(pool: &sqlx::PgPool, id: uuid::Uuid, word_count: i32) -> Result<(), sqlx::Error> {
+    pub async fn update_word_count<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, word_count: i32) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
             UPDATE websites
@@ -111,17 +166,173 @@ impl Website {
             word_count,
             id
         )
-            .execute(pool)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+    pub async fn update_content<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, content: &str) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE websites
+            SET content = $1
+            WHERE id = $2
+            "#,
+            content,
+            id
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+    /// Record the fingerprint the website was reindexed with.
+    pub async fn update_fingerprint<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, fingerprint: i64) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE websites
+            SET fingerprint = $1, last_seen_at = NOW(), updated_at = NOW()
+            WHERE id = $2
+            "#,
+            fingerprint,
+            id
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Record the simhash the website was reindexed with and the near duplicate cluster it joined.
+    pub async fn update_simhash<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid, simhash: Option<i64>, cluster_id: Option<uuid::Uuid>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE websites
+            SET simhash = $1, cluster_id = $2
+            WHERE id = $3
+            "#,
+            simhash,
+            cluster_id,
+            id
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Move the near duplicates of a canonical website to another cluster.
+    pub async fn move_cluster<'e, E: sqlx::PgExecutor<'e>>(executor: E, from: uuid::Uuid, to: uuid::Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE websites
+            SET cluster_id = $2
+            WHERE cluster_id = $1
+            "#,
+            from,
+            to
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Make the oldest near duplicate of a canonical website the canonical website of the others, before deleting it.
+    pub async fn promote_cluster<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            WITH canonical AS (
+                SELECT id FROM websites
+                WHERE cluster_id = $1
+                ORDER BY created_at, id
+                LIMIT 1
+            )
+            UPDATE websites
+            SET cluster_id = CASE WHEN websites.id = canonical.id THEN NULL ELSE canonical.id END
+            FROM canonical
+            WHERE websites.cluster_id = $1
+            "#,
+            id
+        )
+            .execute(executor)
             .await?;
         Ok(())
     }
-    pub async fn count(pool: &sqlx::PgPool) -> Result<i64, sqlx::Error> {
+
+    /// Find the websites sharing one of the simhash `bands`, the candidates for near duplicates.
+    pub async fn find_by_simhash_bands<'e, E: sqlx::PgExecutor<'e>>(executor: E, bands: [i64; 4]) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Website,
+            r#"
+            SELECT id, url, word_count, fingerprint, simhash, cluster_id, last_seen_at, created_at, updated_at
+            FROM websites
+            WHERE ((simhash >> 48) & 65535) = $1
+            OR ((simhash >> 32) & 65535) = $2
+            OR ((simhash >> 16) & 65535) = $3
+            OR (simhash & 65535) = $4
+            "#,
+            bands[0],
+            bands[1],
+            bands[2],
+            bands[3]
+        ).fetch_all(executor).await
+    }
+
+    /// Record that the website was crawled again without changing.
+    pub async fn touch<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE websites
+            SET last_seen_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            "#,
+            id
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a website, its postings and tfidf scores are deleted with it.
+    pub async fn delete<'e, E: sqlx::PgExecutor<'e>>(executor: E, id: uuid::Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM websites
+            WHERE id = $1
+            "#,
+            id
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+    pub async fn find_contents_by_ids(pool: &sqlx::PgPool, ids: &[uuid::Uuid]) -> Result<Vec<WebsiteContent>, sqlx::Error> {
+        sqlx::query_as!(
+            WebsiteContent,
+            r#"
+            SELECT id, content
+            FROM websites
+            WHERE id = ANY($1)
+            "#,
+            ids
+        ).fetch_all(pool).await
+    }
+    /// Find the id of every website, oldest first.
+    pub async fn find_ids(pool: &sqlx::PgPool) -> Result<Vec<uuid::Uuid>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT id FROM websites
+            ORDER BY created_at, id
+            "#,
+        )
+            .fetch_all(pool)
+            .await?;
+        Ok(rows.into_iter().map(|row| row.id).collect())
+    }
+
+    pub async fn count<'e, E: sqlx::PgExecutor<'e>>(executor: E) -> Result<i64, sqlx::Error> {
         let row = sqlx::query!(
             r#"
             SELECT COUNT(*) FROM websites
             "#,
         )
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await?;
         let count = row.count.ok_or(sqlx::Error::RowNotFound)?;
         Ok(count)
@@ -1,9 +1,8 @@
-use serde::{Deserialize, Serialize};
 use sqlx::types::BigDecimal;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-#[derive(Debug, )]
+#[derive(Debug, Clone)]
 pub struct WebsiteKeywordTfidf {
     pub id: Uuid,
     pub website_id: Uuid,
@@ -11,8 +10,8 @@ pub struct WebsiteKeywordTfidf {
     pub tf: BigDecimal,
     pub idf: BigDecimal,
     pub tfidf: BigDecimal,
-    created_at: OffsetDateTime,
-    updated_at: OffsetDateTime,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
 }
 
 pub struct InsertWebsiteKeywordTfidfDao {
@@ -25,7 +24,7 @@ pub struct InsertWebsiteKeywordTfidfDao {
 }
 
 impl WebsiteKeywordTfidf {
-    pub async fn insert(pool: &sqlx::PgPool, insert_website_keyword_tfidf: InsertWebsiteKeywordTfidfDao) -> Result<Self, sqlx::Error> {
+    pub async fn insert<'e, E: sqlx::PgExecutor<'e>>(executor: E, insert_website_keyword_tfidf: InsertWebsiteKeywordTfidfDao) -> Result<Self, sqlx::Error> {
         let row = sqlx::query!(
             r#"
             INSERT INTO website_keyword_tfidf (website_id, keyword_id, tf, idf, tfidf)
@@ -38,7 +37,7 @@ impl WebsiteKeywordTfidf {
             insert_website_keyword_tfidf.idf,
             insert_website_keyword_tfidf.tfidf
         )
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await?;
 
         Ok(Self {
@@ -53,7 +52,37 @@ impl WebsiteKeywordTfidf {
         })
     }
 
-    pub async fn find_by_website_keyword(pool: &sqlx::PgPool, website_id: Uuid, keyword_id: Uuid) -> Result<Self, sqlx::Error> {
+    /// Insert many rows in one statement, returning the number inserted.
+    pub async fn insert_many<'e, E: sqlx::PgExecutor<'e>>(executor: E, insert_website_keyword_tfidfs: Vec<InsertWebsiteKeywordTfidfDao>) -> Result<u64, sqlx::Error> {
+        let mut website_ids = Vec::with_capacity(insert_website_keyword_tfidfs.len());
+        let mut keyword_ids = Vec::with_capacity(insert_website_keyword_tfidfs.len());
+        let mut tfs = Vec::with_capacity(insert_website_keyword_tfidfs.len());
+        let mut idfs = Vec::with_capacity(insert_website_keyword_tfidfs.len());
+        let mut tfidfs = Vec::with_capacity(insert_website_keyword_tfidfs.len());
+        for insert_website_keyword_tfidf in insert_website_keyword_tfidfs {
+            website_ids.push(insert_website_keyword_tfidf.website_id);
+            keyword_ids.push(insert_website_keyword_tfidf.keyword_id);
+            tfs.push(insert_website_keyword_tfidf.tf);
+            idfs.push(insert_website_keyword_tfidf.idf);
+            tfidfs.push(insert_website_keyword_tfidf.tfidf);
+        }
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO website_keyword_tfidf (website_id, keyword_id, tf, idf, tfidf)
+            SELECT * FROM UNNEST($1::UUID[], $2::UUID[], $3::NUMERIC[], $4::NUMERIC[], $5::NUMERIC[])
+            "#,
+            &website_ids,
+            &keyword_ids,
+            &tfs,
+            &idfs,
+            &tfidfs
+        )
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn find_by_website_keyword<'e, E: sqlx::PgExecutor<'e>>(executor: E, website_id: Uuid, keyword_id: Uuid) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             WebsiteKeywordTfidf,
             r#"
@@ -63,12 +92,62 @@ impl WebsiteKeywordTfidf {
             "#,
             website_id,
             keyword_id
-        ).fetch_one(pool).await
+        ).fetch_one(executor).await
+    }
+
+    pub async fn find_by_keyword_id(pool: &sqlx::PgPool, keyword_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            WebsiteKeywordTfidf,
+            r#"
+            SELECT id, website_id, keyword_id, tf, idf, tfidf, created_at, updated_at
+            FROM website_keyword_tfidf
+            WHERE keyword_id = $1
+            "#,
+            keyword_id
+        ).fetch_all(pool).await
+    }
+
+    /// Rewrite the idf and tfidf of the rows of the first `batch_size` keywords after `after` in id order, from their
+    /// current document frequencies in a corpus of `total_docs` documents.
+    /// Uses the same idf as the text pool, `1 + ln(total docs / docs with keyword)`. Returns the number of rows rewritten
+    /// and the last keyword of the batch, `None` once there are no keywords left.
+    pub async fn recompute_idf(pool: &sqlx::PgPool, total_docs: i64, after: Uuid, batch_size: i64) -> Result<(u64, Option<Uuid>), sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            WITH batch AS (
+                SELECT id FROM keywords
+                WHERE id > $2
+                ORDER BY id
+                LIMIT $3
+            ), document_frequencies AS (
+                -- Pages indexed since the document count was read may hold the keyword, the idf does not go below 1.
+                SELECT keyword_id, 1 + LN(GREATEST($1, COUNT(*))::numeric / COUNT(*)) AS idf
+                FROM website_keywords
+                WHERE keyword_id IN (SELECT id FROM batch)
+                GROUP BY keyword_id
+            ), updated AS (
+                UPDATE website_keyword_tfidf
+                SET idf = document_frequencies.idf,
+                    tfidf = website_keyword_tfidf.tf * document_frequencies.idf,
+                    updated_at = NOW()
+                FROM document_frequencies
+                WHERE website_keyword_tfidf.keyword_id = document_frequencies.keyword_id
+                RETURNING 1
+            )
+            SELECT (SELECT id FROM batch ORDER BY id DESC LIMIT 1) AS last_keyword_id, (SELECT COUNT(*) FROM updated) AS "rows!"
+            "#,
+            total_docs,
+            after,
+            batch_size
+        )
+            .fetch_one(pool)
+            .await?;
+        Ok((row.rows as u64, row.last_keyword_id))
     }
 
-    pub async fn upsert_by_website_keyword(pool: &sqlx::PgPool, insert_website_keyword_tfidf: InsertWebsiteKeywordTfidfDao) -> Result<Self, sqlx::Error> {
+    pub async fn upsert_by_website_keyword(conn: &mut sqlx::PgConnection, insert_website_keyword_tfidf: InsertWebsiteKeywordTfidfDao) -> Result<Self, sqlx::Error> {
         // Check if the website keyword tfidf exists
-        match Self::find_by_website_keyword(pool, insert_website_keyword_tfidf.website_id, insert_website_keyword_tfidf.keyword_id).await {
+        match Self::find_by_website_keyword(&mut *conn, insert_website_keyword_tfidf.website_id, insert_website_keyword_tfidf.keyword_id).await {
             Ok(website_keyword_tfidf) => {
                 // Update the website keyword tfidf
                 let row = sqlx::query!(
@@ -83,7 +162,7 @@ impl WebsiteKeywordTfidf {
                     insert_website_keyword_tfidf.tfidf,
                     website_keyword_tfidf.id
                 )
-                    .fetch_one(pool)
+                    .fetch_one(&mut *conn)
                     .await?;
                 Ok(Self {
                     id: row.id,
@@ -98,11 +177,24 @@ impl WebsiteKeywordTfidf {
             }
             Err(sqlx::Error::RowNotFound) => {
                 // Insert the website keyword tfidf to the database
-                Self::insert(pool, insert_website_keyword_tfidf).await
+                Self::insert(&mut *conn, insert_website_keyword_tfidf).await
             }
             Err(e) => {
                 Err(e)
             }
         }
     }
+
+    pub async fn delete_by_website<'e, E: sqlx::PgExecutor<'e>>(executor: E, website_id: Uuid) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM website_keyword_tfidf
+            WHERE website_id = $1
+            "#,
+            website_id
+        )
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
 }
\ No newline at end of file
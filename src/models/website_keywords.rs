@@ -1,39 +1,56 @@
+use std::collections::HashMap;
+use futures::stream::BoxStream;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use time::OffsetDateTime;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebsiteKeywords {
-    id: uuid::Uuid,
-    keyword_id: uuid::Uuid,
-    website_id: uuid::Uuid,
-    frequency: i32,
-    created_at: OffsetDateTime,
-    updated_at: OffsetDateTime
+    pub(crate) id: uuid::Uuid,
+    pub(crate) keyword_id: uuid::Uuid,
+    pub(crate) website_id: uuid::Uuid,
+    pub(crate) frequency: i32,
+    pub(crate) positions: Vec<i32>,
+    pub(crate) title_frequency: i32,
+    // `heading_frequencies` are the occurrences in the headings of every level, `<h1>` first.
+    pub(crate) heading_frequencies: Vec<i32>,
+    pub(crate) description_frequency: i32,
+    pub(crate) url_frequency: i32,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) updated_at: OffsetDateTime
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InsertWebsiteKeywordsDao {
     pub keyword_id: uuid::Uuid,
     pub website_id: uuid::Uuid,
-    pub frequency: i32
+    pub frequency: i32,
+    pub positions: Vec<i32>,
+    pub title_frequency: i32,
+    pub heading_frequencies: Vec<i32>,
+    pub description_frequency: i32,
+    pub url_frequency: i32,
 }
 
 impl WebsiteKeywords {
 
-    pub async fn insert(pool: &PgPool, insert_website_keywords_dao: InsertWebsiteKeywordsDao) -> Result<Self, sqlx::Error> {
+    pub async fn insert<'e, E: sqlx::PgExecutor<'e>>(executor: E, insert_website_keywords_dao: InsertWebsiteKeywordsDao) -> Result<Self, sqlx::Error> {
         let row = sqlx::query!(
             r#"
-            INSERT INTO website_keywords (keyword_id, website_id, frequency)
-            VALUES ($1, $2, $3)
-            RETURNING id, keyword_id, website_id, frequency, created_at, updated_at
+            INSERT INTO website_keywords (keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id, keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency, created_at, updated_at
             "#,
             insert_website_keywords_dao.keyword_id,
             insert_website_keywords_dao.website_id,
-            insert_website_keywords_dao.frequency
-            
+            insert_website_keywords_dao.frequency,
+            &insert_website_keywords_dao.positions,
+            insert_website_keywords_dao.title_frequency,
+            &insert_website_keywords_dao.heading_frequencies,
+            insert_website_keywords_dao.description_frequency,
+            insert_website_keywords_dao.url_frequency
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await?;
 
         Ok(Self {
@@ -41,17 +58,54 @@ impl WebsiteKeywords {
             keyword_id: row.keyword_id,
             website_id: row.website_id,
             frequency: row.frequency,
+            positions: row.positions,
+            title_frequency: row.title_frequency,
+            heading_frequencies: row.heading_frequencies,
+            description_frequency: row.description_frequency,
+            url_frequency: row.url_frequency,
             created_at: row.created_at,
             updated_at: row.updated_at,
         })
     }
     
 
+    /// Insert many postings in one statement, returning the number inserted.
+    pub async fn insert_many<'e, E: sqlx::PgExecutor<'e>>(executor: E, insert_website_keywords_daos: &[InsertWebsiteKeywordsDao]) -> Result<u64, sqlx::Error> {
+        let keyword_ids: Vec<uuid::Uuid> = insert_website_keywords_daos.iter().map(|dao| dao.keyword_id).collect();
+        let website_ids: Vec<uuid::Uuid> = insert_website_keywords_daos.iter().map(|dao| dao.website_id).collect();
+        let frequencies: Vec<i32> = insert_website_keywords_daos.iter().map(|dao| dao.frequency).collect();
+        // Multidimensional arrays must be rectangular, so the positions are sent as array literals and cast back.
+        let positions: Vec<String> = insert_website_keywords_daos.iter().map(|dao| array_literal(&dao.positions)).collect();
+        let title_frequencies: Vec<i32> = insert_website_keywords_daos.iter().map(|dao| dao.title_frequency).collect();
+        let heading_frequencies: Vec<String> = insert_website_keywords_daos.iter().map(|dao| array_literal(&dao.heading_frequencies)).collect();
+        let description_frequencies: Vec<i32> = insert_website_keywords_daos.iter().map(|dao| dao.description_frequency).collect();
+        let url_frequencies: Vec<i32> = insert_website_keywords_daos.iter().map(|dao| dao.url_frequency).collect();
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO website_keywords (keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency)
+            SELECT keyword_id, website_id, frequency, positions::INTEGER[], title_frequency, heading_frequencies::INTEGER[], description_frequency, url_frequency
+            FROM UNNEST($1::UUID[], $2::UUID[], $3::INTEGER[], $4::TEXT[], $5::INTEGER[], $6::TEXT[], $7::INTEGER[], $8::INTEGER[])
+                AS postings (keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency)
+            "#,
+            &keyword_ids,
+            &website_ids,
+            &frequencies,
+            &positions,
+            &title_frequencies,
+            &heading_frequencies,
+            &description_frequencies,
+            &url_frequencies
+        )
+        .execute(executor)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
     pub async fn find_by_keyword_id(pool: &PgPool, keyword_id: uuid::Uuid) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as!(
             WebsiteKeywords,
             r#"
-            SELECT id, keyword_id, website_id, frequency,created_at, updated_at
+            SELECT id, keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency, created_at, updated_at
             FROM website_keywords
             WHERE keyword_id = $1
             "#,
@@ -59,11 +113,23 @@ impl WebsiteKeywords {
         ).fetch_all(pool).await
     }
 
+    /// Stream every posting, the postings of a keyword together, in one query.
+    pub fn stream_by_keyword(pool: &PgPool) -> BoxStream<'_, Result<Self, sqlx::Error>> {
+        sqlx::query_as!(
+            WebsiteKeywords,
+            r#"
+            SELECT id, keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency, created_at, updated_at
+            FROM website_keywords
+            ORDER BY keyword_id, website_id
+            "#,
+        ).fetch(pool)
+    }
+
     pub async fn find_by_website_id(pool: &PgPool, website_id: uuid::Uuid) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as!(
             WebsiteKeywords,
             r#"
-            SELECT id, keyword_id, website_id, frequency, created_at, updated_at
+            SELECT id, keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency, created_at, updated_at
             FROM website_keywords
             WHERE website_id = $1
             "#,
@@ -75,7 +141,7 @@ impl WebsiteKeywords {
         sqlx::query_as!(
             WebsiteKeywords,
             r#"
-            SELECT id, keyword_id, website_id, frequency, created_at, updated_at
+            SELECT id, keyword_id, website_id, frequency, positions, title_frequency, heading_frequencies, description_frequency, url_frequency, created_at, updated_at
             FROM website_keywords
             WHERE id = $1
             "#,
@@ -83,7 +149,7 @@ impl WebsiteKeywords {
         ).fetch_optional(pool).await
     }
 
-    pub async fn count_by_keyword_id(pool: &PgPool, keyword_id: uuid::Uuid) -> Result<i64, sqlx::Error> {
+    pub async fn count_by_keyword_id<'e, E: sqlx::PgExecutor<'e>>(executor: E, keyword_id: uuid::Uuid) -> Result<i64, sqlx::Error> {
         sqlx::query!(
             r#"
             SELECT COUNT(*)
@@ -91,7 +157,21 @@ impl WebsiteKeywords {
             WHERE keyword_id = $1
             "#,
             keyword_id
-        ).fetch_one(pool).await.map(|row| row.count.unwrap())
+        ).fetch_one(executor).await.map(|row| row.count.unwrap())
+    }
+
+    /// Count the websites containing each keyword, keyed by keyword id.
+    pub async fn count_by_keyword_ids<'e, E: sqlx::PgExecutor<'e>>(executor: E, keyword_ids: &[uuid::Uuid]) -> Result<HashMap<uuid::Uuid, i64>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT keyword_id, COUNT(*) AS "count!"
+            FROM website_keywords
+            WHERE keyword_id = ANY($1)
+            GROUP BY keyword_id
+            "#,
+            keyword_ids
+        ).fetch_all(executor).await?;
+        Ok(rows.into_iter().map(|row| (row.keyword_id, row.count)).collect())
     }
 
     pub async fn count_by_website_id(pool: &PgPool, website_id: uuid::Uuid) -> Result<i64, sqlx::Error> {
@@ -105,7 +185,7 @@ impl WebsiteKeywords {
         ).fetch_one(pool).await.map(|row| row.count.unwrap())
     }
     
-    pub async fn delete_by_website(pool: &PgPool, website_id: uuid::Uuid) -> Result<(), sqlx::Error> {
+    pub async fn delete_by_website<'e, E: sqlx::PgExecutor<'e>>(executor: E, website_id: uuid::Uuid) -> Result<(), sqlx::Error> {
         sqlx::query!(
             r#"
             DELETE FROM website_keywords
@@ -113,8 +193,13 @@ impl WebsiteKeywords {
             "#,
             website_id
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Write the integers as a postgres array literal.
+fn array_literal(values: &[i32]) -> String {
+    format!("{{{}}}", values.iter().map(i32::to_string).collect::<Vec<String>>().join(","))
+}
@@ -0,0 +1,397 @@
+//! Compressed posting lists.
+//!
+//! A posting list is split into blocks of [`BLOCK_LEN`] postings, preceded by skip data to jump to the block holding a doc:
+//!
+//! ```text
+//! count      varint number of postings
+//! skips      per block: u32 last doc of the block, u32 offset of the block after the skips
+//! blocks     per posting: varint doc delta, varint body/title/h1 to h6/description/url frequency,
+//!            varint position count, varint position deltas
+//! ```
+//!
+//! Docs are encoded as the difference with the previous doc of the list and positions with the previous position of the
+//! posting, so dense lists take a byte or two per number.
+use std::io;
+use std::time::{Duration, Instant};
+use crate::segment::{get_u32, invalid_data, put_u32, Posting};
+use crate::services::HEADING_LEVELS;
+
+/// Number of postings per block, every block has an entry in the skip data.
+pub const BLOCK_LEN: usize = 128;
+// Size of a posting as fixed width 4 byte integers, without its positions: the doc, the body, title, heading,
+// description and url frequencies, and the position count.
+const RAW_POSTING_BYTES: u64 = 4 * (HEADING_LEVELS as u64 + 6);
+
+/// Append `value` with 7 bits per byte, the high bit set on every byte but the last.
+pub(crate) fn put_varint(buffer: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Read a varint at `offset`, returning it with the offset after it.
+pub(crate) fn get_varint(bytes: &[u8], mut offset: usize) -> io::Result<(u32, usize)> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = *bytes.get(offset).ok_or_else(|| invalid_data("Segment is truncated"))?;
+        offset += 1;
+        value |= ((byte & 0x7f) as u32).checked_shl(shift).unwrap_or(0);
+        if byte & 0x80 == 0 {
+            return Ok((value, offset));
+        }
+    }
+    Err(invalid_data("Varint is too long"))
+}
+
+/// Append the postings, which must be sorted by doc.
+pub fn encode_postings(buffer: &mut Vec<u8>, postings: &[Posting]) {
+    put_varint(buffer, postings.len() as u32);
+    let mut blocks = Vec::new();
+    let mut skips = Vec::with_capacity(postings.len().div_ceil(BLOCK_LEN) * 8);
+    let mut previous_doc = 0;
+    for block in postings.chunks(BLOCK_LEN) {
+        let block_offset = blocks.len() as u32;
+        for posting in block {
+            put_varint(&mut blocks, posting.doc - previous_doc);
+            put_varint(&mut blocks, posting.frequency);
+            put_varint(&mut blocks, posting.title_frequency);
+            for heading_frequency in posting.heading_frequencies {
+                put_varint(&mut blocks, heading_frequency);
+            }
+            put_varint(&mut blocks, posting.description_frequency);
+            put_varint(&mut blocks, posting.url_frequency);
+            put_varint(&mut blocks, posting.positions.len() as u32);
+            let mut previous_position = 0;
+            for position in posting.positions.iter() {
+                put_varint(&mut blocks, position - previous_position);
+                previous_position = *position;
+            }
+            previous_doc = posting.doc;
+        }
+        put_u32(&mut skips, previous_doc);
+        put_u32(&mut skips, block_offset);
+    }
+    buffer.extend_from_slice(&skips);
+    buffer.extend_from_slice(&blocks);
+}
+
+/// PostingList decodes a posting list lazily, using the skip data to jump over the blocks a seek does not need.
+pub struct PostingList<'a> {
+    bytes: &'a [u8],
+    len: u32,
+    block_count: usize,
+    skips_offset: usize,
+    blocks_offset: usize,
+    // `block` is the block the cursor is in.
+    block: usize,
+    cursor: usize,
+    // `read` is the number of postings decoded so far, skipped ones included.
+    read: u32,
+    previous_doc: u32,
+}
+
+impl<'a> PostingList<'a> {
+    /// Start decoding the posting list at the start of `bytes`.
+    pub fn new(bytes: &'a [u8]) -> io::Result<Self> {
+        let (len, skips_offset) = get_varint(bytes, 0)?;
+        let block_count = (len as usize).div_ceil(BLOCK_LEN);
+        let blocks_offset = skips_offset + block_count * 8;
+        if blocks_offset > bytes.len() {
+            return Err(invalid_data("Segment is truncated"));
+        }
+        Ok(Self {
+            bytes,
+            len,
+            block_count,
+            skips_offset,
+            blocks_offset,
+            block: 0,
+            cursor: blocks_offset,
+            read: 0,
+            previous_doc: 0,
+        })
+    }
+
+    /// Number of postings in the list.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Whether the list has no postings.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of bytes decoded so far, the size of the whole list once every posting is read.
+    pub fn encoded_len(&self) -> usize {
+        self.cursor
+    }
+
+    fn skip(&self, block: usize) -> io::Result<(u32, usize)> {
+        let last_doc = get_u32(self.bytes, self.skips_offset + block * 8)?;
+        let offset = get_u32(self.bytes, self.skips_offset + block * 8 + 4)? as usize;
+        Ok((last_doc, offset))
+    }
+
+    /// Decode the next posting, `None` at the end of the list.
+    pub fn next_posting(&mut self) -> io::Result<Option<Posting>> {
+        if self.read >= self.len {
+            return Ok(None);
+        }
+        let bytes = self.bytes;
+        let (doc_delta, cursor) = get_varint(bytes, self.cursor)?;
+        let (frequency, cursor) = get_varint(bytes, cursor)?;
+        let (title_frequency, cursor) = get_varint(bytes, cursor)?;
+        let mut heading_frequencies = [0; HEADING_LEVELS];
+        let mut cursor = cursor;
+        for heading_frequency in heading_frequencies.iter_mut() {
+            let (frequency, next) = get_varint(bytes, cursor)?;
+            *heading_frequency = frequency;
+            cursor = next;
+        }
+        let (description_frequency, cursor) = get_varint(bytes, cursor)?;
+        let (url_frequency, cursor) = get_varint(bytes, cursor)?;
+        let (position_count, mut cursor) = get_varint(bytes, cursor)?;
+        // Every position takes at least a byte, so a corrupt count cannot allocate more than the segment holds.
+        if position_count as usize > bytes.len() - cursor {
+            return Err(invalid_data("Segment is truncated"));
+        }
+        let mut positions = Vec::with_capacity(position_count as usize);
+        let mut previous_position: u32 = 0;
+        for _ in 0..position_count {
+            let (position_delta, next) = get_varint(bytes, cursor)?;
+            previous_position = previous_position.wrapping_add(position_delta);
+            positions.push(previous_position);
+            cursor = next;
+        }
+        let doc = self.previous_doc.wrapping_add(doc_delta);
+        self.cursor = cursor;
+        self.previous_doc = doc;
+        self.read += 1;
+        self.block = self.read as usize / BLOCK_LEN;
+        Ok(Some(Posting {
+            doc,
+            frequency,
+            title_frequency,
+            heading_frequencies,
+            description_frequency,
+            url_frequency,
+            positions,
+        }))
+    }
+
+    /// Find the first posting with a doc at or after `target`, skipping whole blocks that end before it.
+    pub fn seek(&mut self, target: u32) -> io::Result<Option<Posting>> {
+        // Find the first block from the current one whose last doc reaches the target.
+        let (mut low, mut high) = (self.block, self.block_count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.skip(middle)?.0 < target {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        if low >= self.block_count {
+            self.read = self.len;
+            return Ok(None);
+        }
+        if low > self.block {
+            self.previous_doc = self.skip(low - 1)?.0;
+            self.cursor = self.blocks_offset + self.skip(low)?.1;
+            self.read = (low * BLOCK_LEN) as u32;
+            self.block = low;
+        }
+        while let Some(posting) = self.next_posting()? {
+            if posting.doc >= target {
+                return Ok(Some(posting));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Find the docs in every posting list, seeking each list to the largest doc seen so far.
+pub fn intersect(lists: &mut [PostingList]) -> io::Result<Vec<u32>> {
+    let mut docs = Vec::new();
+    if lists.is_empty() {
+        return Ok(docs);
+    }
+    // Seek the shortest list first, it rules out the most docs.
+    lists.sort_by_key(|list| list.len());
+    // `current` is the doc each list was last seeked to, a list already at or past the target is not seeked again.
+    let mut current: Vec<Option<u32>> = vec![None; lists.len()];
+    let mut target = 0;
+    'outer: loop {
+        for (list, list_doc) in lists.iter_mut().zip(current.iter_mut()) {
+            let doc = match *list_doc {
+                Some(doc) if doc >= target => doc,
+                _ => match list.seek(target)? {
+                    Some(posting) => *list_doc.insert(posting.doc),
+                    None => return Ok(docs),
+                },
+            };
+            if doc > target {
+                target = doc;
+                continue 'outer;
+            }
+        }
+        docs.push(target);
+        match target.checked_add(1) {
+            Some(next) => target = next,
+            None => return Ok(docs),
+        }
+    }
+}
+
+/// Find the postings of the docs, given in increasing order, that are in the list, seeking over the others.
+pub fn seek_docs(list: &mut PostingList, docs: impl IntoIterator<Item = u32>) -> io::Result<Vec<Posting>> {
+    let mut postings = Vec::new();
+    // `current` is the posting the list was last seeked to, a seek past a doc may land on the next one asked for.
+    let mut current: Option<Posting> = None;
+    for doc in docs {
+        if !matches!(&current, Some(posting) if posting.doc >= doc) {
+            current = match list.seek(doc)? {
+                Some(posting) => Some(posting),
+                None => break,
+            };
+        }
+        if let Some(posting) = current.as_ref().filter(|posting| posting.doc == doc) {
+            postings.push(posting.clone());
+        }
+    }
+    Ok(postings)
+}
+
+/// PostingStats measures how well posting lists compress and how fast they decode.
+#[derive(Debug, Default, Clone)]
+pub struct PostingStats {
+    pub lists: u64,
+    pub postings: u64,
+    // `raw_bytes` is the size of the postings as fixed width 4 byte integers.
+    pub raw_bytes: u64,
+    pub encoded_bytes: u64,
+    pub decode_time: Duration,
+}
+
+impl PostingStats {
+    /// Encode a posting list and time decoding it back.
+    pub fn add(&mut self, postings: &[Posting]) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        encode_postings(&mut buffer, postings);
+        let start = Instant::now();
+        let mut list = PostingList::new(&buffer)?;
+        let mut decoded = 0;
+        while list.next_posting()?.is_some() {
+            decoded += 1;
+        }
+        self.decode_time += start.elapsed();
+        if decoded != postings.len() {
+            return Err(invalid_data("Decoded a different number of postings"));
+        }
+        self.lists += 1;
+        self.postings += postings.len() as u64;
+        // A count, then the fixed width fields and the positions of each posting.
+        self.raw_bytes += 4 + postings.iter().map(|posting| RAW_POSTING_BYTES + 4 * posting.positions.len() as u64).sum::<u64>();
+        self.encoded_bytes += buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Raw size divided by encoded size.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.encoded_bytes == 0 {
+            return 0.0;
+        }
+        self.raw_bytes as f64 / self.encoded_bytes as f64
+    }
+
+    /// Postings decoded per second.
+    pub fn decode_throughput(&self) -> f64 {
+        let seconds = self.decode_time.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.postings as f64 / seconds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn postings(docs: impl Iterator<Item = u32>) -> Vec<Posting> {
+        docs.map(|doc| Posting {
+            doc,
+            frequency: 2,
+            positions: vec![doc % 7, doc % 7 + 300],
+            ..Default::default()
+        }).collect()
+    }
+
+    #[test]
+    fn can_encode_varint() {
+        let mut buffer = Vec::new();
+        for value in [0, 127, 128, 16_383, 16_384, u32::MAX] {
+            put_varint(&mut buffer, value);
+        }
+        let mut offset = 0;
+        for value in [0, 127, 128, 16_383, 16_384, u32::MAX] {
+            let (decoded, next) = get_varint(&buffer, offset).unwrap();
+            assert_eq!(decoded, value);
+            offset = next;
+        }
+        assert_eq!(offset, buffer.len());
+    }
+
+    #[test]
+    fn can_decode_and_seek_postings() {
+        let postings = postings((0..1_000).map(|doc| doc * 3));
+        let mut buffer = Vec::new();
+        encode_postings(&mut buffer, &postings);
+        let mut list = PostingList::new(&buffer).unwrap();
+        let mut decoded = Vec::new();
+        while let Some(posting) = list.next_posting().unwrap() {
+            decoded.push(posting);
+        }
+        assert_eq!(decoded, postings);
+        assert_eq!(list.encoded_len(), buffer.len());
+
+        let mut list = PostingList::new(&buffer).unwrap();
+        assert_eq!(list.seek(1_000).unwrap().unwrap().doc, 1_002);
+        assert_eq!(list.seek(2_500).unwrap().unwrap(), postings[834]);
+        assert!(list.seek(3_000).unwrap().is_none());
+    }
+
+    #[test]
+    fn can_intersect_postings() {
+        let mut threes = Vec::new();
+        encode_postings(&mut threes, &postings((0..1_000).map(|doc| doc * 3)));
+        let mut fives = Vec::new();
+        encode_postings(&mut fives, &postings((0..1_000).map(|doc| doc * 5)));
+        let mut lists = [PostingList::new(&threes).unwrap(), PostingList::new(&fives).unwrap()];
+        let docs = intersect(&mut lists).unwrap();
+        assert_eq!(docs, (0..200).map(|doc| doc * 15).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn can_seek_docs() {
+        let postings = postings((0..1_000).map(|doc| doc * 3));
+        let mut buffer = Vec::new();
+        encode_postings(&mut buffer, &postings);
+        let mut list = PostingList::new(&buffer).unwrap();
+        let found = seek_docs(&mut list, [1, 3, 4, 6, 2_400, 2_997, 3_000]).unwrap();
+        assert_eq!(found, vec![postings[1].clone(), postings[2].clone(), postings[800].clone(), postings[999].clone()]);
+    }
+
+    // The raw size counts every fixed width field of a posting, the six heading frequencies included
+    #[test]
+    fn can_measure_posting_stats() {
+        let mut stats = PostingStats::default();
+        stats.add(&postings(0..10)).unwrap();
+        assert_eq!((stats.lists, stats.postings), (1, 10));
+        assert_eq!(stats.raw_bytes, 4 + 10 * (48 + 2 * 4));
+        assert!(stats.compression_ratio() > 1.0);
+    }
+}
@@ -0,0 +1,124 @@
+//! Immutable on-disk segments of the inverted index.
+//!
+//! A segment is a single file holding a doc store, a posting list per term and a sorted term dictionary:
+//!
+//! ```text
+//! header     magic "SEG1", format version, doc count, term count, docs offset, terms offset
+//! postings   per term: a posting list compressed by the [`codec`]
+//! docs       offset of every doc, then per doc: website id, word count, fingerprint, simhash, flags, url, content
+//! terms      offset of every term, then per term: postings offset, doc frequency, term, surface
+//! ```
+//!
+//! Numbers are little endian. Segments are written once by a [`SegmentWriter`] and read through a memory map by a
+//! `SegmentReader`, available with the `segment` feature.
+use std::io;
+use uuid::Uuid;
+use crate::services::HEADING_LEVELS;
+
+pub mod codec;
+#[cfg(feature = "segment")]
+mod reader;
+mod writer;
+
+#[cfg(feature = "segment")]
+pub use reader::SegmentReader;
+pub use writer::SegmentWriter;
+
+pub(crate) const MAGIC: &[u8; 4] = b"SEG1";
+// Version 2 compresses the posting lists, version 3 stores removed pages, version 4 stores fingerprints,
+// version 5 stores simhashes, version 6 stores the surface forms of the terms, version 7 stores the heading frequencies
+// of every level.
+pub(crate) const FORMAT_VERSION: u32 = 7;
+// Magic, version, doc count, term count, docs offset, terms offset.
+pub(crate) const HEADER_LEN: usize = 4 + 4 + 4 + 4 + 8 + 8;
+// Flags of a doc, set when the doc is removed and when it has a simhash.
+pub(crate) const REMOVED_FLAG: u8 = 1;
+pub(crate) const SIMHASH_FLAG: u8 = 2;
+// Website id, word count, fingerprint, simhash, flags.
+#[cfg(feature = "segment")]
+pub(crate) const DOC_URL_OFFSET: usize = 16 + 4 + 8 + 8 + 1;
+
+/// A document of a segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredDoc {
+    pub website_id: Uuid,
+    pub url: String,
+    // `word_count` is the number of keywords in the body.
+    pub word_count: u32,
+    // `content` is the visible text of the page, kept for result snippets.
+    pub content: String,
+    // `fingerprint` is the fingerprint of the normalized token stream of the page.
+    pub fingerprint: i64,
+    // `simhash` is the simhash of the body tokens of the page, `None` for a page without body.
+    pub simhash: Option<i64>,
+    // `removed` marks the page as removed from the index, shadowing the earlier versions of its url.
+    pub removed: bool,
+}
+
+/// The occurrences of a term in a document of a segment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Posting {
+    // `doc` is the number of the document within its segment.
+    pub doc: u32,
+    // `frequency` is the number of occurrences in the body.
+    pub frequency: u32,
+    pub title_frequency: u32,
+    // `heading_frequencies` are the occurrences in the headings of every level, `<h1>` first.
+    pub heading_frequencies: [u32; HEADING_LEVELS],
+    pub description_frequency: u32,
+    pub url_frequency: u32,
+    // `positions` are the positions of the term in the body.
+    pub positions: Vec<u32>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(buffer, bytes.len() as u32);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Read `len` bytes at `offset`, failing on a truncated segment instead of panicking.
+pub(crate) fn get_slice(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    offset.checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| invalid_data("Segment is truncated"))
+}
+
+pub(crate) fn get_u32(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    let slice = get_slice(bytes, offset, 4)?;
+    Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+#[cfg(feature = "segment")]
+pub(crate) fn get_u64(bytes: &[u8], offset: usize) -> io::Result<u64> {
+    let slice = get_slice(bytes, offset, 8)?;
+    let mut value = [0; 8];
+    value.copy_from_slice(slice);
+    Ok(u64::from_le_bytes(value))
+}
+
+/// Read a length prefixed byte string at `offset`, returning it with the offset after it.
+#[cfg(feature = "segment")]
+pub(crate) fn get_bytes(bytes: &[u8], offset: usize) -> io::Result<(&[u8], usize)> {
+    let len = get_u32(bytes, offset)? as usize;
+    let slice = get_slice(bytes, offset + 4, len)?;
+    Ok((slice, offset + 4 + len))
+}
+
+#[cfg(feature = "segment")]
+pub(crate) fn get_string(bytes: &[u8], offset: usize) -> io::Result<(String, usize)> {
+    let (slice, next) = get_bytes(bytes, offset)?;
+    let string = String::from_utf8(slice.to_vec()).map_err(|_| invalid_data("Segment holds invalid UTF-8"))?;
+    Ok((string, next))
+}
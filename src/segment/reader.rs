@@ -0,0 +1,211 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use memmap2::Mmap;
+use uuid::Uuid;
+use crate::segment::codec::PostingList;
+use crate::segment::{get_bytes, get_slice, get_string, get_u32, get_u64, invalid_data, Posting, StoredDoc, DOC_URL_OFFSET, FORMAT_VERSION, HEADER_LEN, MAGIC, REMOVED_FLAG, SIMHASH_FLAG};
+
+/// SegmentReader reads a segment through a memory map, so only the pages a query touches are loaded.
+pub struct SegmentReader {
+    path: PathBuf,
+    mmap: Mmap,
+    doc_count: u32,
+    term_count: u32,
+    docs_offset: usize,
+    terms_offset: usize,
+}
+
+impl SegmentReader {
+    /// Open the segment at `path`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        // SAFETY: segments are immutable once renamed into place and are only ever deleted, never modified,
+        // and a deleted file stays mapped until the reader is dropped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let bytes: &[u8] = &mmap;
+        if get_slice(bytes, 0, 4)? != MAGIC {
+            return Err(invalid_data("Not a segment"));
+        }
+        if get_u32(bytes, 4)? != FORMAT_VERSION {
+            return Err(invalid_data("Unsupported segment format version"));
+        }
+        let doc_count = get_u32(bytes, 8)?;
+        let term_count = get_u32(bytes, 12)?;
+        let docs_offset = get_u64(bytes, 16)? as usize;
+        let terms_offset = get_u64(bytes, 24)? as usize;
+        if docs_offset < HEADER_LEN || terms_offset < docs_offset {
+            return Err(invalid_data("Segment header is corrupt"));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            mmap,
+            doc_count,
+            term_count,
+            docs_offset,
+            terms_offset,
+        })
+    }
+
+    /// The file the segment was opened from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of documents in the segment.
+    pub fn doc_count(&self) -> u32 {
+        self.doc_count
+    }
+
+    /// Number of terms in the segment.
+    pub fn term_count(&self) -> u32 {
+        self.term_count
+    }
+
+    /// Read a document.
+    pub fn doc(&self, doc: u32) -> io::Result<StoredDoc> {
+        if doc >= self.doc_count {
+            return Err(invalid_data("Document is out of range"));
+        }
+        let bytes: &[u8] = &self.mmap;
+        let offset = get_u64(bytes, self.docs_offset + doc as usize * 8)? as usize;
+        let website_id = Uuid::from_slice(get_slice(bytes, offset, 16)?).map_err(|_| invalid_data("Invalid website id"))?;
+        let word_count = get_u32(bytes, offset + 16)?;
+        let fingerprint = get_u64(bytes, offset + 20)? as i64;
+        let simhash = get_u64(bytes, offset + 28)? as i64;
+        let flags = get_slice(bytes, offset + 36, 1)?[0];
+        let (url, next) = get_string(bytes, offset + DOC_URL_OFFSET)?;
+        let (content, _) = get_string(bytes, next)?;
+        Ok(StoredDoc {
+            website_id,
+            url,
+            word_count,
+            content,
+            fingerprint,
+            simhash: (flags & SIMHASH_FLAG != 0).then_some(simhash),
+            removed: flags & REMOVED_FLAG != 0,
+        })
+    }
+
+    /// Read the url of a document without its content.
+    pub fn url(&self, doc: u32) -> io::Result<String> {
+        if doc >= self.doc_count {
+            return Err(invalid_data("Document is out of range"));
+        }
+        let bytes: &[u8] = &self.mmap;
+        let offset = get_u64(bytes, self.docs_offset + doc as usize * 8)? as usize;
+        Ok(get_string(bytes, offset + DOC_URL_OFFSET)?.0)
+    }
+
+    /// Read the term at `index` of the dictionary, with its postings offset and doc frequency.
+    fn term(&self, index: u32) -> io::Result<(&[u8], usize, u32)> {
+        let bytes: &[u8] = &self.mmap;
+        let offset = get_u64(bytes, self.terms_offset + index as usize * 8)? as usize;
+        let postings_offset = get_u64(bytes, offset)? as usize;
+        let doc_frequency = get_u32(bytes, offset + 8)?;
+        let (term, _) = get_bytes(bytes, offset + 12)?;
+        Ok((term, postings_offset, doc_frequency))
+    }
+
+    /// Every term with the number of documents containing it, in order.
+    pub fn terms(&self) -> io::Result<Vec<(String, u32)>> {
+        (0..self.term_count)
+            .map(|index| {
+                let (term, _, doc_frequency) = self.term(index)?;
+                let term = String::from_utf8(term.to_vec()).map_err(|_| invalid_data("Segment holds invalid UTF-8"))?;
+                Ok((term, doc_frequency))
+            })
+            .collect()
+    }
+
+    /// Every term with the word it was first normalized from, in order.
+    pub fn surfaces(&self) -> io::Result<Vec<(String, String)>> {
+        let bytes: &[u8] = &self.mmap;
+        (0..self.term_count)
+            .map(|index| {
+                let offset = get_u64(bytes, self.terms_offset + index as usize * 8)? as usize;
+                let (term, next) = get_string(bytes, offset + 12)?;
+                let (surface, _) = get_string(bytes, next)?;
+                Ok((term, surface))
+            })
+            .collect()
+    }
+
+    /// Find the posting list of a term by binary search over the dictionary, `None` when the segment does not contain it.
+    pub fn posting_list(&self, term: &str) -> io::Result<Option<PostingList<'_>>> {
+        let (mut low, mut high) = (0, self.term_count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (candidate, postings_offset, _) = self.term(middle)?;
+            match candidate.cmp(term.as_bytes()) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => {
+                    // Posting lists are written before the docs.
+                    let bytes = self.mmap.get(postings_offset..self.docs_offset).ok_or_else(|| invalid_data("Segment is truncated"))?;
+                    return PostingList::new(bytes).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find and decode the postings of a term, `None` when the segment does not contain it.
+    pub fn postings(&self, term: &str) -> io::Result<Option<Vec<Posting>>> {
+        let mut list = match self.posting_list(term)? {
+            Some(list) => list,
+            None => return Ok(None),
+        };
+        let mut postings = Vec::with_capacity(list.len() as usize);
+        while let Some(posting) = list.next_posting()? {
+            postings.push(posting);
+        }
+        Ok(Some(postings))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::segment::SegmentWriter;
+    use crate::store::Occurrences;
+
+    #[test]
+    fn can_read_written_segment() {
+        let mut writer = SegmentWriter::new();
+        for (url, keywords) in [("https://a.example/", vec!["rust", "async", "rust"]), ("https://b.example/", vec!["tokio", "rust"])] {
+            let mut occurrences: HashMap<String, Occurrences> = HashMap::new();
+            for (position, keyword) in keywords.iter().enumerate() {
+                occurrences.entry(keyword.to_string()).or_default().positions.push(position as i32);
+            }
+            if let Some(occurrence) = occurrences.get_mut("async") {
+                occurrence.surface = Some("asynchronous".to_string());
+            }
+            writer.add_document(StoredDoc {
+                website_id: Uuid::new_v4(),
+                url: url.to_string(),
+                word_count: keywords.len() as u32,
+                content: keywords.join(" "),
+                fingerprint: 0,
+                simhash: Some(keywords.len() as i64),
+                removed: false,
+            }, occurrences);
+        }
+        let path = std::env::temp_dir().join(format!("{}.seg", Uuid::new_v4()));
+        writer.write(&path).unwrap();
+
+        let reader = SegmentReader::open(&path).unwrap();
+        assert_eq!(reader.doc_count(), 2);
+        assert_eq!(reader.doc(1).unwrap().url, "https://b.example/");
+        assert_eq!(reader.doc(1).unwrap().simhash, Some(2));
+        let rust = reader.postings("rust").unwrap().unwrap();
+        assert_eq!(rust.iter().map(|posting| posting.doc).collect::<Vec<u32>>(), vec![0, 1]);
+        assert_eq!(rust[0].positions, vec![0, 2]);
+        assert!(reader.postings("python").unwrap().is_none());
+        assert_eq!(reader.terms().unwrap(), vec![("async".to_string(), 1), ("rust".to_string(), 2), ("tokio".to_string(), 1)]);
+        assert_eq!(reader.surfaces().unwrap()[..2], [("async".to_string(), "asynchronous".to_string()), ("rust".to_string(), "rust".to_string())]);
+        fs::remove_file(&path).unwrap();
+    }
+}
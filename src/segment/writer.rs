@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use crate::segment::codec::encode_postings;
+use crate::segment::{put_bytes, put_u32, put_u64, Posting, StoredDoc, FORMAT_VERSION, HEADER_LEN, MAGIC, REMOVED_FLAG, SIMHASH_FLAG};
+use crate::store::Occurrences;
+
+/// SegmentWriter buffers documents in memory and writes them out as one immutable segment.
+/// The buffered documents can be read before they are written, so they are searchable right away.
+#[derive(Default)]
+pub struct SegmentWriter {
+    docs: Vec<StoredDoc>,
+    // `terms` maps each term to its postings ordered by doc.
+    terms: BTreeMap<String, Vec<Posting>>,
+    // `surfaces` maps each term to the word it was first normalized from.
+    surfaces: HashMap<String, String>,
+}
+
+impl SegmentWriter {
+    /// Create an empty SegmentWriter instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a document with the occurrences of its keywords, returning its number within the segment.
+    pub fn add_document(&mut self, doc: StoredDoc, keywords: HashMap<String, Occurrences>) -> u32 {
+        let doc = self.add_stored_document(doc);
+        for (keyword, occurrences) in keywords {
+            if let Some(surface) = occurrences.surface {
+                self.set_surface(&keyword, surface);
+            }
+            self.add_posting(keyword, Posting {
+                doc,
+                frequency: occurrences.positions.len() as u32,
+                title_frequency: occurrences.title.max(0) as u32,
+                heading_frequencies: occurrences.headings.map(|frequency| frequency.max(0) as u32),
+                description_frequency: occurrences.description.max(0) as u32,
+                url_frequency: occurrences.url.max(0) as u32,
+                positions: occurrences.positions.into_iter().map(|position| position.max(0) as u32).collect(),
+            });
+        }
+        doc
+    }
+
+    /// Add a document without its postings, returning its number within the segment.
+    pub fn add_stored_document(&mut self, doc: StoredDoc) -> u32 {
+        self.docs.push(doc);
+        (self.docs.len() - 1) as u32
+    }
+
+    /// Add a posting of an added document. Postings of a term must be added in doc order.
+    pub fn add_posting(&mut self, term: String, posting: Posting) {
+        self.terms.entry(term).or_default().push(posting);
+    }
+
+    /// Set the surface form of a term, unless it already has one.
+    pub fn set_surface(&mut self, term: &str, surface: String) {
+        if !self.surfaces.contains_key(term) {
+            self.surfaces.insert(term.to_string(), surface);
+        }
+    }
+
+    /// Find the surface form of a term.
+    pub fn surface(&self, term: &str) -> Option<&str> {
+        self.surfaces.get(term).map(String::as_str)
+    }
+
+    /// Number of documents added.
+    pub fn doc_count(&self) -> u32 {
+        self.docs.len() as u32
+    }
+
+    /// Whether no document was added.
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Find an added document.
+    pub fn doc(&self, doc: u32) -> Option<&StoredDoc> {
+        self.docs.get(doc as usize)
+    }
+
+    /// Find the postings of a term.
+    pub fn postings(&self, term: &str) -> Option<&[Posting]> {
+        self.terms.get(term).map(Vec::as_slice)
+    }
+
+    /// Every term with the number of documents containing it, in order.
+    pub fn terms(&self) -> impl Iterator<Item = (&String, u32)> {
+        self.terms.iter().map(|(term, postings)| (term, postings.len() as u32))
+    }
+
+    /// Write the segment to `path`. It is written next to it first and renamed, so a crash never leaves half a segment.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut buffer = vec![0; HEADER_LEN];
+
+        let mut postings_offsets = Vec::with_capacity(self.terms.len());
+        for postings in self.terms.values() {
+            postings_offsets.push(buffer.len() as u64);
+            encode_postings(&mut buffer, postings);
+        }
+
+        let docs_offset = buffer.len() as u64;
+        let docs_table = buffer.len();
+        buffer.resize(docs_table + self.docs.len() * 8, 0);
+        for (index, doc) in self.docs.iter().enumerate() {
+            let offset = buffer.len() as u64;
+            buffer[docs_table + index * 8..docs_table + index * 8 + 8].copy_from_slice(&offset.to_le_bytes());
+            buffer.extend_from_slice(doc.website_id.as_bytes());
+            put_u32(&mut buffer, doc.word_count);
+            put_u64(&mut buffer, doc.fingerprint as u64);
+            put_u64(&mut buffer, doc.simhash.unwrap_or(0) as u64);
+            let mut flags = 0;
+            if doc.removed {
+                flags |= REMOVED_FLAG;
+            }
+            if doc.simhash.is_some() {
+                flags |= SIMHASH_FLAG;
+            }
+            buffer.push(flags);
+            put_bytes(&mut buffer, doc.url.as_bytes());
+            put_bytes(&mut buffer, doc.content.as_bytes());
+        }
+
+        let terms_offset = buffer.len() as u64;
+        let terms_table = buffer.len();
+        buffer.resize(terms_table + self.terms.len() * 8, 0);
+        for (index, ((term, postings), postings_offset)) in self.terms.iter().zip(postings_offsets).enumerate() {
+            let offset = buffer.len() as u64;
+            buffer[terms_table + index * 8..terms_table + index * 8 + 8].copy_from_slice(&offset.to_le_bytes());
+            put_u64(&mut buffer, postings_offset);
+            put_u32(&mut buffer, postings.len() as u32);
+            put_bytes(&mut buffer, term.as_bytes());
+            // A term without surface form is shown as is.
+            put_bytes(&mut buffer, self.surface(term).unwrap_or(term).as_bytes());
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        put_u32(&mut header, FORMAT_VERSION);
+        put_u32(&mut header, self.docs.len() as u32);
+        put_u32(&mut header, self.terms.len() as u32);
+        put_u64(&mut header, docs_offset);
+        put_u64(&mut header, terms_offset);
+        buffer[..HEADER_LEN].copy_from_slice(&header);
+
+        let temporary_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(&buffer)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, path)
+    }
+}
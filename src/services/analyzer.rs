@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use rust_numerals::number_to_cardinal;
+
+/// A keyword and the byte range of the word in the source text it was normalized from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub keyword: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    /// The word of `text` the keyword was normalized from, lowercased and without the punctuation around it.
+    pub fn surface(&self, text: &str) -> String {
+        text[self.start..self.end]
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase()
+    }
+}
+
+/// Analyzer normalizes raw text into the keywords stored in the index.
+/// It is shared by the page parser and the search service so that documents and queries are normalized the same way.
+pub struct Analyzer {
+    // `lemmatizer_map` is a hashmap that stores the lemmatized words.
+    lemmatizer_map: HashMap<String, String>,
+    // `stemmer` is a stemmer instance.
+    stemmer: rust_stemmers::Stemmer,
+    // `stop_words` is a list of stopwords.
+    stop_words: Vec<String>,
+}
+
+impl Analyzer {
+    /// Create a new Analyzer instance.
+    pub fn new(lemmatizer_json_path: std::path::PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let lemmatizer_json = std::fs::read_to_string(lemmatizer_json_path)?;
+        let mut lemmatizer_json: HashMap<String, String> = serde_json::from_str(&lemmatizer_json)?;
+        let mut map = HashMap::new();
+        let keys: Vec<String> = lemmatizer_json.keys().cloned().collect();
+        for key in keys.iter() {
+            let (k, v) = match lemmatizer_json.remove_entry(key) {
+                Some((k, v)) => (k, v),
+                None => continue,
+            };
+            map.insert(k, v);
+        }
+        Ok(Self {
+            lemmatizer_map: map,
+            stemmer: rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::English),
+            stop_words: stop_words::get(stop_words::LANGUAGE::English),
+        })
+    }
+
+    /// Preprocess the text.
+    pub fn preprocess_text(&self, texts: Vec<String>) -> Vec<String> {
+        // lower
+        let texts = Self::parse_lower(texts);
+        let texts = texts.iter()
+            // split by whitespace
+            .flat_map(|text| text.split_whitespace().map(str::to_string))
+            // remove punctuation
+            .map(|text| Self::remove_punctuation(&text))
+            // remove apostrophes
+            .map(|text| Self::remove_apostrophes(&text))
+            // remove single characters
+            .filter(|text| text.len() > 1)
+            // remove length more than 50
+            .filter(|text| text.len() < 50)
+            // convert numbers to words
+            .map(|text| Self::convert_numbers_to_words(&text))
+            .collect();
+        // Remove stopwords
+        let texts = self.remove_stopwords(texts);
+        let texts = texts.iter()
+            // Stem the words
+            .map(|text| self.stem_word(text))
+            // Lemmatize the words
+            .map(|text| self.lemmatize_word(&text))
+            // remove punctuation again
+            .map(|text| Self::remove_punctuation(&text))
+            // convert numbers to words again
+            .map(|text| Self::convert_numbers_to_words(&text))
+            .collect();
+        texts
+    }
+
+    /// Normalize the text into keywords, keeping the span of the word each keyword comes from.
+    /// The keywords are the same as `preprocess_text` produces for the text.
+    pub fn tokenize(&self, text: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut word_start = None;
+        // A trailing space closes the last word.
+        for (index, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            match (word_start, c.is_whitespace()) {
+                (None, false) => word_start = Some(index),
+                (Some(start), true) => {
+                    // Every step of the preprocessing works word by word, a word gives at most one keyword.
+                    if let Some(keyword) = self.preprocess_text(vec![text[start..index].to_string()]).pop() {
+                        tokens.push(Token {
+                            keyword,
+                            start,
+                            end: index,
+                        });
+                    }
+                    word_start = None;
+                }
+                _ => {}
+            }
+        }
+        tokens
+    }
+
+    /// Parse all the words in the text and cast them to lowercase.
+    fn parse_lower(texts: Vec<String>) -> Vec<String> {
+        texts.iter()
+            .map(|text| text.to_lowercase())
+            .collect()
+    }
+
+    /// Punctuation marks to be removed from the text.
+    fn remove_punctuation(text: &str) -> String {
+        text.chars()
+            .filter(|c| !c.is_ascii_punctuation())
+            .collect()
+    }
+
+    /// Remove apostrophes from the text.
+    fn remove_apostrophes(text: &str) -> String {
+        text.chars()
+            .filter(|c| *c != '\'')
+            .collect()
+    }
+
+    /// Convert Numbers to Words
+    fn convert_numbers_to_words(text: &str) -> String {
+        // try to convert the number to a word
+        let num = text.parse::<i64>();
+        match num {
+            Ok(num) => {
+                number_to_cardinal(num)
+            }
+            Err(_) => text.to_string(),
+        }
+    }
+
+    /// Remove stopwords from the text.
+    fn remove_stopwords(&self, texts: Vec<String>) -> Vec<String> {
+        texts.iter()
+            .filter(|text| !self.stop_words.contains(text))
+            .cloned()
+            .collect()
+    }
+
+    /// Stem the word in the text.
+    fn stem_word(&self, word: &str) -> String {
+        self.stemmer.stem(word).to_string()
+    }
+
+    /// Lemmatize the word in the text.
+    fn lemmatize_word(&self, word: &str) -> String {
+        match self.lemmatizer_map.get(word) {
+            Some(lemma) => lemma.clone(),
+            None => word.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::path::PathBuf;
+    use super::*;
+    fn get_analyzer() -> Result<Analyzer, Box<dyn std::error::Error>> {
+        let lemmatizer_json_path = PathBuf::from("assets/lemmatizedMap.json");
+        Analyzer::new(lemmatizer_json_path)
+    }
+
+    // Remove punctuation
+    #[test]
+    fn can_remove_punctuation() {
+        let text = "Hello, World!";
+        let text = Analyzer::remove_punctuation(text);
+        assert_eq!(text, "Hello World");
+    }
+    // Remove apostrophes
+    #[test]
+    fn can_remove_apostrophes() {
+        let text = "Hello's World";
+        let text = Analyzer::remove_apostrophes(text);
+        assert_eq!(text, "Hellos World");
+    }
+
+    // Convert Numbers to Words
+    #[test]
+    fn can_convert_numbers_to_words() {
+        let text = "123";
+        let text = Analyzer::convert_numbers_to_words(text);
+        assert_eq!(text, "one hundred and twenty-three");
+    }
+    // Remove stopwords
+    #[test]
+    fn can_remove_stopwords() {
+        let analyzer = get_analyzer().unwrap();
+        let texts = vec!["the".to_string(), "quick".to_string(), "brown".to_string(), "fox".to_string()];
+        let texts = analyzer.remove_stopwords(texts);
+        assert_eq!(texts, vec!["quick".to_string(), "brown".to_string(), "fox".to_string()]);
+    }
+
+    // Stem the word
+    #[test]
+    fn can_stem_word() {
+        let analyzer = get_analyzer().unwrap();
+        let word = "running";
+        let word = analyzer.stem_word(word);
+        assert_eq!(word, "run");
+    }
+    // Lemmatize the word
+    #[test]
+    fn can_lemmatize_word() {
+        let analyzer = get_analyzer().unwrap();
+        let word = "running";
+        let word = analyzer.lemmatize_word(word);
+        assert_eq!(word, "run");
+    }
+
+    // Parse lower
+    #[test]
+    fn can_parse_lower() {
+        let texts = vec!["Hello".to_string(), "World".to_string()];
+        let texts = Analyzer::parse_lower(texts);
+        assert_eq!(texts, vec!["hello".to_string(), "world".to_string()]);
+    }
+    #[tokio::test]
+    async fn can_preprocess_text () -> Result<(), Box<dyn std::error::Error>> {
+        let texts = vec!["Hello, World!".to_string(),
+                            "The quick brown fox jumps over the lazy dog.".to_string(),
+                            "123".to_string(),
+                            "running".to_string(),
+                            "the".to_string(),
+                            "quick".to_string(),
+                            "brown".to_string(),
+                            "fox".to_string(),
+                            "jumps".to_string(),
+                            "over".to_string()
+        ];
+        let analyzer = get_analyzer()?;
+        let texts = analyzer.preprocess_text(texts);
+        assert_eq!(texts, vec!["quick".to_string(), "brown".to_string(), "fox".to_string(), "jump".to_string(), "lazi".to_string(), "dog".to_string(), "one hundred and twentythre".to_string(), "run".to_string(), "quick".to_string(), "brown".to_string(), "fox".to_string(), "jump".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_tokenize_with_spans() -> Result<(), Box<dyn std::error::Error>> {
+        let analyzer = get_analyzer()?;
+        let text = "The  Running fox, jumps";
+        let tokens = analyzer.tokenize(text);
+        let keywords: Vec<String> = tokens.iter().map(|token| token.keyword.clone()).collect();
+        assert_eq!(keywords, analyzer.preprocess_text(vec![text.to_string()]));
+        assert_eq!(&text[tokens[0].start..tokens[0].end], "Running");
+        assert_eq!(&text[tokens[1].start..tokens[1].end], "fox,");
+        assert_eq!(&text[tokens[2].start..tokens[2].end], "jumps");
+        Ok(())
+    }
+}
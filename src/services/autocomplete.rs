@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+use serde::Serialize;
+
+/// Where a completion comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionSource {
+    Query,
+    Keyword,
+}
+
+/// A completion of a typed prefix.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Completion {
+    pub text: String,
+    // `weight` is the number of searches for a query, or the number of websites containing a keyword.
+    pub weight: i64,
+    pub source: CompletionSource,
+}
+
+/// Autocomplete completes typed prefixes from past queries and indexed keywords.
+/// Past queries come first, then the last word being typed is completed from the keywords.
+#[derive(Default)]
+pub struct Autocomplete {
+    // `queries` holds past queries weighted by how often they were searched.
+    queries: Trie,
+    // `keywords` holds the surface forms of the indexed keywords weighted by document frequency, a stem is not always a word.
+    keywords: Trie,
+}
+
+impl Autocomplete {
+    /// Create an empty Autocomplete instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or update the surface form of a keyword with its document frequency.
+    pub fn insert_keyword(&mut self, surface: &str, document_frequency: i64) {
+        self.keywords.insert(surface, document_frequency);
+    }
+
+    /// Add or update a past query with the number of times it was searched.
+    pub fn insert_query(&mut self, query: &str, count: i64) {
+        self.queries.insert(&normalize_query(query), count);
+    }
+
+    /// Find the best `limit` completions of the prefix.
+    pub fn complete(&self, prefix: &str, limit: usize) -> Vec<Completion> {
+        let prefix = normalize_query(prefix);
+        let mut completions: Vec<Completion> = self.queries.top(&prefix, limit)
+            .into_iter()
+            .map(|(text, weight)| Completion {
+                text,
+                weight,
+                source: CompletionSource::Query,
+            })
+            .collect();
+
+        // Complete the last word from the keywords, keeping the words before it.
+        let lowercase = prefix.to_lowercase();
+        let (head, last) = match lowercase.rsplit_once(' ') {
+            Some((head, last)) => (format!("{} ", head), last),
+            None => (String::new(), lowercase.as_str()),
+        };
+        if last.is_empty() {
+            return completions;
+        }
+        for (keyword, weight) in self.keywords.top(last, limit) {
+            if completions.len() >= limit {
+                break;
+            }
+            let text = format!("{}{}", head, keyword);
+            if completions.iter().any(|completion| completion.text == text) {
+                continue;
+            }
+            completions.push(Completion {
+                text,
+                weight,
+                source: CompletionSource::Keyword,
+            });
+        }
+        completions
+    }
+}
+
+/// Collapse the whitespace of a query so that the same query is stored once.
+/// A trailing space is kept, it means the next word has not been started.
+fn normalize_query(query: &str) -> String {
+    let mut normalized = query.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if !normalized.is_empty() && query.ends_with(char::is_whitespace) {
+        normalized.push(' ');
+    }
+    normalized
+}
+
+/// Trie of weighted strings, each node knows the best weight below it so the top completions are found best first.
+#[derive(Default)]
+struct Trie {
+    root: TrieNode,
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: BTreeMap<char, TrieNode>,
+    // `weight` is set when a string ends at this node.
+    weight: Option<i64>,
+    // `best` is the highest weight of a string ending at or below this node.
+    best: i64,
+}
+
+impl Trie {
+    /// Set the weight of a string, replacing its previous weight.
+    fn insert(&mut self, text: &str, weight: i64) {
+        self.root.insert(&text.chars().collect::<Vec<char>>(), weight);
+    }
+
+    /// Find the `limit` heaviest strings starting with the prefix, heaviest first.
+    fn top(&self, prefix: &str, limit: usize) -> Vec<(String, i64)> {
+        let mut node = &self.root;
+        for c in prefix.chars() {
+            node = match node.children.get(&c) {
+                Some(child) => child,
+                None => return Vec::new(),
+            };
+        }
+        let mut results = Vec::new();
+        let mut heap = BinaryHeap::new();
+        heap.push(Candidate {
+            weight: node.best,
+            text: prefix.to_string(),
+            node: Some(node),
+        });
+        while let Some(candidate) = heap.pop() {
+            if results.len() >= limit {
+                break;
+            }
+            let node = match candidate.node {
+                Some(node) => node,
+                // A finished string outweighs everything left in the heap.
+                None => {
+                    results.push((candidate.text, candidate.weight));
+                    continue;
+                }
+            };
+            if let Some(weight) = node.weight {
+                heap.push(Candidate {
+                    weight,
+                    text: candidate.text.clone(),
+                    node: None,
+                });
+            }
+            for (c, child) in node.children.iter() {
+                let mut text = candidate.text.clone();
+                text.push(*c);
+                heap.push(Candidate {
+                    weight: child.best,
+                    text,
+                    node: Some(child),
+                });
+            }
+        }
+        results
+    }
+}
+
+impl TrieNode {
+    /// Set the weight of the string below this node, then recompute the best weight of the nodes on its path
+    /// so that a lowered weight is lowered in them too.
+    fn insert(&mut self, chars: &[char], weight: i64) {
+        match chars.split_first() {
+            Some((c, rest)) => self.children.entry(*c).or_default().insert(rest, weight),
+            None => self.weight = Some(weight),
+        }
+        self.best = self.children.values()
+            .map(|child| child.best)
+            .chain(self.weight)
+            .max()
+            .unwrap_or(0);
+    }
+}
+
+/// A subtree or a finished string waiting in the best first search.
+struct Candidate<'a> {
+    weight: i64,
+    text: String,
+    // `node` is `None` for a finished string.
+    node: Option<&'a TrieNode>,
+}
+
+impl Ord for Candidate<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Heaviest first, finished strings before subtrees of the same weight, then alphabetical.
+        self.weight.cmp(&other.weight)
+            .then_with(|| other.node.is_some().cmp(&self.node.is_some()))
+            .then_with(|| other.text.cmp(&self.text))
+    }
+}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate<'_> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_complete_by_weight() {
+        let mut autocomplete = Autocomplete::new();
+        autocomplete.insert_keyword("rust", 3);
+        autocomplete.insert_keyword("rustacean", 10);
+        autocomplete.insert_keyword("ruby", 50);
+        autocomplete.insert_keyword("python", 100);
+        let completions: Vec<String> = autocomplete.complete("ru", 2).into_iter().map(|completion| completion.text).collect();
+        assert_eq!(completions, vec!["ruby".to_string(), "rustacean".to_string()]);
+    }
+
+    #[test]
+    fn can_complete_queries_before_keywords() {
+        let mut autocomplete = Autocomplete::new();
+        autocomplete.insert_keyword("async", 10);
+        autocomplete.insert_keyword("asyncio", 5);
+        autocomplete.insert_query("rust  async   book", 2);
+        let completions = autocomplete.complete("rust as", 3);
+        assert_eq!(completions, vec![
+            Completion { text: "rust async book".to_string(), weight: 2, source: CompletionSource::Query },
+            Completion { text: "rust async".to_string(), weight: 10, source: CompletionSource::Keyword },
+            Completion { text: "rust asyncio".to_string(), weight: 5, source: CompletionSource::Keyword },
+        ]);
+    }
+
+    // Refreshing a weight overwrites it, even when it is lower
+    #[test]
+    fn can_lower_weight() {
+        let mut autocomplete = Autocomplete::new();
+        autocomplete.insert_keyword("rustacean", 10);
+        autocomplete.insert_keyword("ruby", 5);
+        autocomplete.insert_keyword("rustacean", 1);
+        let completions = autocomplete.complete("ru", 2);
+        assert_eq!(completions[0], Completion { text: "ruby".to_string(), weight: 5, source: CompletionSource::Keyword });
+        assert_eq!(completions[1].weight, 1);
+    }
+
+    #[test]
+    fn unknown_prefix_has_no_completions() {
+        let mut autocomplete = Autocomplete::new();
+        autocomplete.insert_keyword("rust", 3);
+        assert!(autocomplete.complete("go", 5).is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// How much of a site a crawl may cover, a limit that is `None` is not set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlBudget {
+    // `max_pages` is the number of pages of the site crawled at most.
+    pub max_pages: Option<u32>,
+    // `max_depth` is how many links away from the url crawled the crawl goes at most.
+    pub max_depth: Option<usize>,
+    // `max_bytes` is the size of the pages downloaded at most.
+    pub max_bytes: Option<u64>,
+    // `timeout_secs` is how long the crawl runs at most, in seconds.
+    pub timeout_secs: Option<u64>,
+    // `subdomains` is whether the pages of the subdomains of the site are crawled too.
+    pub subdomains: Option<bool>,
+}
+
+impl CrawlBudget {
+    /// Take the limits this budget does not set from another budget.
+    pub fn or(self, budget: &CrawlBudget) -> CrawlBudget {
+        CrawlBudget {
+            max_pages: self.max_pages.or(budget.max_pages),
+            max_depth: self.max_depth.or(budget.max_depth),
+            max_bytes: self.max_bytes.or(budget.max_bytes),
+            timeout_secs: self.timeout_secs.or(budget.timeout_secs),
+            subdomains: self.subdomains.or(budget.subdomains),
+        }
+    }
+
+    /// How long the crawl runs at most.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    /// How many more pages of the site may be fetched once a crawl fetched `pages` pages weighing `bytes`, `None` when
+    /// the budget does not limit them.
+    /// The pages left by size are estimated from the average size of the pages fetched.
+    pub fn pages_left(&self, pages: usize, bytes: u64) -> Option<usize> {
+        let by_pages = self.max_pages.map(|max_pages| (max_pages as usize).saturating_sub(pages));
+        let by_bytes = self.max_bytes.map(|max_bytes| {
+            let page_bytes = (bytes / pages.max(1) as u64).max(1);
+            (max_bytes.saturating_sub(bytes) / page_bytes) as usize
+        });
+        match (by_pages, by_bytes) {
+            (Some(by_pages), Some(by_bytes)) => Some(by_pages.min(by_bytes)),
+            (by_pages, by_bytes) => by_pages.or(by_bytes),
+        }
+    }
+}
+
+/// The crawl budget of every site, with the budgets of the domains that need other limits.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlBudgets {
+    // `default` is the budget of the sites whose domain has none.
+    pub default: CrawlBudget,
+    // `domains` are the budgets of domains, which apply to their subdomains too.
+    pub domains: HashMap<String, CrawlBudget>,
+}
+
+impl CrawlBudgets {
+    /// Load the crawl budgets from a JSON file.
+    pub fn load(crawl_budgets_json_path: std::path::PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let crawl_budgets_json = std::fs::read_to_string(&crawl_budgets_json_path)
+            .map_err(|e| format!("Error reading crawl budgets {:?}: {:?}", crawl_budgets_json_path, e))?;
+        let mut crawl_budgets: CrawlBudgets = serde_json::from_str(&crawl_budgets_json)
+            .map_err(|e| format!("Error parsing crawl budgets {:?}: {:?}", crawl_budgets_json_path, e))?;
+        crawl_budgets.domains = crawl_budgets.domains.into_iter()
+            .map(|(domain, budget)| (domain.trim_end_matches('.').to_lowercase(), budget))
+            .collect();
+        Ok(crawl_budgets)
+    }
+
+    /// The budget of a host.
+    /// The budget of the host is taken first, then the budgets of its parent domains from the closest, then the default
+    /// budget, each filling the limits the ones before it do not set.
+    pub fn budget_for(&self, host: &str) -> CrawlBudget {
+        let host = host.trim_end_matches('.').to_lowercase();
+        let mut budget = CrawlBudget::default();
+        let mut domain = host.as_str();
+        loop {
+            if let Some(domain_budget) = self.domains.get(domain) {
+                budget = budget.or(domain_budget);
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => break,
+            }
+        }
+        budget.or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn budgets() -> CrawlBudgets {
+        serde_json::from_str(r#"{
+            "default": { "max_pages": 1000, "max_depth": 5, "timeout_secs": 600, "subdomains": false },
+            "domains": {
+                "example.com": { "max_pages": 100, "subdomains": true },
+                "docs.example.com": { "max_pages": 10000, "max_bytes": 1048576 }
+            }
+        }"#).unwrap()
+    }
+
+    // The budget of a host takes the limits of the closest domain setting them, then the default ones
+    #[test]
+    fn can_find_budget_for_host() {
+        let budgets = budgets();
+        assert_eq!(budgets.budget_for("docs.example.com"), CrawlBudget {
+            max_pages: Some(10000),
+            max_depth: Some(5),
+            max_bytes: Some(1048576),
+            timeout_secs: Some(600),
+            subdomains: Some(true),
+        });
+        assert_eq!(budgets.budget_for("WWW.Example.com.").max_pages, Some(100));
+        assert_eq!(budgets.budget_for("notexample.com"), budgets.default);
+        assert_eq!(budgets.budget_for("other.org").timeout(), Some(Duration::from_secs(600)));
+    }
+
+    // The pages left are the fewest the page and size limits allow
+    #[test]
+    fn can_count_pages_left() {
+        let budgets = budgets();
+        let budget = budgets.budget_for("docs.example.com");
+        assert_eq!(budget.pages_left(10, 10 * 1024), Some(1014));
+        assert_eq!(budget.pages_left(10, 100 * 1024), Some(92));
+        assert_eq!(budget.pages_left(10, 2 * 1048576), Some(0));
+        assert_eq!(budgets.budget_for("example.com").pages_left(40, 40 * 1024), Some(60));
+        assert_eq!(budgets.budget_for("example.com").pages_left(150, 0), Some(0));
+        assert_eq!(CrawlBudget::default().pages_left(10, 1024), None);
+    }
+}
@@ -1,53 +1,328 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use futures::FutureExt;
+use spider::hashbrown::{HashMap, HashSet};
 use spider::page::Page;
 use spider::website::Website;
+use spider::CaseInsensitiveString;
+use tokio::sync::Notify;
+use crate::frontier::Site;
+use crate::models::frontier::{CrawlScope, FrontierUrl, StopReason};
+use crate::services::{CrawlBudget, CrawlBudgets, CrawlJob, CrawlOutcome, PolitenessPolicy, SitemapReader};
 
 pub struct Crawler {
     // `page_sender` is a mpsc channel sender that sends a page to the page pool.
     page_sender: crossbeam_channel::Sender<Page>,
 
-    // `url_reader` is a reader that reads the URL to crawl.
-    url_reader: crossbeam_channel::Receiver<url::Url>,
+    // `url_reader` is a reader that reads the URL leased from the frontier to crawl, with the delay between its requests.
+    url_reader: crossbeam_channel::Receiver<CrawlJob>,
+
+    // `outcome_sender` is a mpsc channel sender that reports the outcome of every crawl to the site pool.
+    outcome_sender: crossbeam_channel::Sender<CrawlOutcome>,
+
+    // `site_sender` is a mpsc channel sender that sends the URLs the sitemaps of a site list to the site pool.
+    site_sender: crossbeam_channel::Sender<Site>,
+
+    // `sitemaps` reads the sitemaps of the sites crawled.
+    sitemaps: SitemapReader,
+
+    // `budgets` limit how much of every site is crawled, shared by every crawler.
+    budgets: Arc<CrawlBudgets>,
+
+    // `policy` keeps the Crawl-delay of the robots.txt of the sites within bounds.
+    policy: PolitenessPolicy,
 }
 
 impl Crawler {
     /// Create a new Crawler instance.
-    pub fn new(page_sender: crossbeam_channel::Sender<Page>, url_reader:crossbeam_channel::Receiver<url::Url>) -> Self {
+    pub fn new(page_sender: crossbeam_channel::Sender<Page>, url_reader: crossbeam_channel::Receiver<CrawlJob>, outcome_sender: crossbeam_channel::Sender<CrawlOutcome>, site_sender: crossbeam_channel::Sender<Site>, budgets: Arc<CrawlBudgets>, policy: PolitenessPolicy) -> Self {
         Self {
             page_sender,
             url_reader,
+            outcome_sender,
+            site_sender,
+            sitemaps: SitemapReader::new(),
+            budgets,
+            policy,
         }
     }
 
     /// Start the crawler in background.
     pub async fn start(self) {
-        while let Ok(url) = self.url_reader.recv() {
-
-            // Start the crawler in background.
-            // Initialize Website instance with Amazon's URL
-            let mut website: Website = Website::new(&url.to_string());
-            website.configuration.respect_robots_txt = true;
-            // Subscribe to receive pages. Adjust the channel size as needed.
-            let mut rx = website.subscribe(3).unwrap();
-            let mut rx_guard = website.subscribe_guard().unwrap();
-            let page_sender = self.page_sender.clone();
-            // Spawn a task to handle received pages
-            tokio::spawn(async move {
-                while let Ok(page) = rx.recv().await {
-                    println!("Page URL: {:?}", page.get_url());
-                    // Send the page to the page pool.
-                    match page_sender.send(page) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("Error sending page to page pool: {:?}", e);
-                        }
+        loop {
+            // Wait for a url on a blocking thread, so the idle crawlers don't hold the workers the site pool needs to
+            // lease urls from the frontier.
+            let url_reader = self.url_reader.clone();
+            let crawl_job = match tokio::task::spawn_blocking(move || url_reader.recv()).await {
+                Ok(Ok(crawl_job)) => crawl_job,
+                _ => break,
+            };
+            let id = crawl_job.frontier_url.id;
+            // A crawl that panics fails its url, instead of taking the crawler down with the url and its slot held.
+            let outcome = match AssertUnwindSafe(self.crawl(crawl_job)).catch_unwind().await {
+                Ok(outcome) => outcome,
+                Err(_) => CrawlOutcome { id, error: Some("Crawl panicked".to_string()), stop_reason: None, crawl_delay: None },
+            };
+            match self.outcome_sender.send(outcome) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error sending crawl outcome to site pool: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Crawl a leased url, within the budget of its domain.
+    async fn crawl(&self, crawl_job: CrawlJob) -> CrawlOutcome {
+        let frontier_url = crawl_job.frontier_url;
+        let delay = crawl_job.delay.as_millis() as u64;
+        let budget = self.budget_for(&frontier_url);
+        let mut website = website(&frontier_url, delay, &budget);
+        let crawl_delay = self.read_robots(&mut website, delay).await;
+        // Subscribe to receive pages. Adjust the channel size as needed.
+        let mut rx = website.subscribe(3).unwrap();
+        let mut rx_guard = website.subscribe_guard().unwrap();
+        let page_sender = self.page_sender.clone();
+        let max_bytes = budget.max_bytes;
+        let max_bytes_reached = Arc::new(Notify::new());
+        let bytes_notifier = max_bytes_reached.clone();
+        // `bytes` is the size of the pages received so far, spider has no budget for it.
+        let bytes = Arc::new(AtomicU64::new(0));
+        let received_bytes = bytes.clone();
+        // Spawn a task to handle received pages
+        tokio::spawn(async move {
+            while let Ok(page) = rx.recv().await {
+                println!("Page URL: {:?}", page.get_url());
+                let page_bytes = page.get_html().len() as u64;
+                let bytes = received_bytes.fetch_add(page_bytes, Ordering::Relaxed) + page_bytes;
+                // Send the page to the page pool.
+                match page_sender.send(page) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("Error sending page to page pool: {:?}", e);
                     }
-                    // Here you can process the page further, e.g., take a screenshot or scrape specific data
-                    rx_guard.inc();
                 }
-            });
+                // Here you can process the page further, e.g., take a screenshot or scrape specific data
+                rx_guard.inc();
+                if max_bytes.is_some_and(|max_bytes| bytes >= max_bytes) {
+                    bytes_notifier.notify_one();
+                }
+            }
+        });
+
+        // Start crawling the website, until it is done or it runs out of time or bytes.
+        let stop_reason = tokio::select! {
+            _ = website.crawl() => None,
+            _ = expire(budget.timeout()) => Some(StopReason::Timeout),
+            _ = max_bytes_reached.notified() => Some(StopReason::MaxBytes),
+        };
+        // Spider stops quietly once it crawled as many pages as its budget allows.
+        let stop_reason = stop_reason.or_else(|| {
+            let max_pages = budget.max_pages.filter(|_| frontier_url.scope == CrawlScope::Site)?;
+            (website.get_links().len() >= max_pages as usize).then_some(StopReason::MaxPages)
+        });
+        if let Some(stop_reason) = stop_reason {
+            println!("Crawl of {} stopped by its budget: {:?}", frontier_url.url, stop_reason);
+        }
+
+        // A site none of whose pages could be fetched is retried later.
+        let error = website.get_links().is_empty()
+            .then(|| format!("No page of {} could be crawled", frontier_url.url));
+        // The pages the sitemaps of the site list are queued too, the ones the crawl did not reach are fetched alone.
+        // The sitemaps are read while the crawler still holds the host, as politely as its pages were. Only as many
+        // of the pages they list as the budget of the domain has left are queued.
+        if frontier_url.scope == CrawlScope::Site && error.is_none() {
+            let pages_left = budget.pages_left(website.get_links().len(), bytes.load(Ordering::Relaxed));
+            if pages_left != Some(0) {
+                self.queue_sitemap_urls(&frontier_url, Duration::from_millis(website.configuration.delay), website.get_links(), pages_left).await;
+            }
+        }
+        CrawlOutcome { id: frontier_url.id, error, stop_reason, crawl_delay }
+    }
+
+    /// Read the robots.txt of the site before the crawl, so the crawl does not read it again, and wait between the
+    /// requests of the crawl as long as its Crawl-delay asks within the policy. The Crawl-delay is returned for the site
+    /// pool to space the next crawls of the host by it, `None` when the robots.txt asks for none.
+    async fn read_robots(&self, website: &mut Website, delay: u64) -> Option<Duration> {
+        let client = website.configure_http_client();
+        website.configure_robots_parser(client).await;
+        // Spider replaces the delay with the Crawl-delay as it is.
+        let crawl_delay = (website.configuration.delay != delay)
+            .then(|| self.policy.delay(Some(Duration::from_millis(website.configuration.delay))));
+        if let Some(crawl_delay) = crawl_delay {
+            website.configuration.delay = crawl_delay.as_millis() as u64;
+        }
+        crawl_delay
+    }
+
+    /// The crawl budget of the host of a url, the default budget when the url can't be parsed.
+    fn budget_for(&self, frontier_url: &FrontierUrl) -> CrawlBudget {
+        match url::Url::parse(&frontier_url.url) {
+            Ok(url) => self.budgets.budget_for(url.host_str().unwrap_or_default()),
+            Err(_) => self.budgets.default.clone(),
+        }
+    }
 
-            // Start crawling the website
-            website.crawl().await;
+    /// Send the URLs the sitemaps of a site list to the site pool, with the rank of the site.
+    /// The sitemaps are fetched `delay` apart, the delay between the requests of the crawl. The pages already crawled
+    /// are left out, and at most `max_urls` of the others are sent, the ones with the highest priority first.
+    async fn queue_sitemap_urls(&self, frontier_url: &FrontierUrl, delay: Duration, crawled: &HashSet<CaseInsensitiveString>, max_urls: Option<usize>) {
+        let site_url = match url::Url::parse(&frontier_url.url) {
+            Ok(site_url) => site_url,
+            Err(e) => {
+                eprintln!("Error parsing URL: {:?}", e);
+                return;
+            }
+        };
+        let mut sitemap_urls = self.sitemaps.read_site(&site_url, delay).await;
+        if !sitemap_urls.is_empty() {
+            println!("Sitemaps of {} list {} URLs.", site_url, sitemap_urls.len());
+        }
+        sitemap_urls.retain(|sitemap_url| !crawled.contains(&CaseInsensitiveString::from(sitemap_url.url.as_str())));
+        // A page without a priority has the default priority of the sitemap protocol.
+        sitemap_urls.sort_by(|a, b| b.priority.unwrap_or(0.5).total_cmp(&a.priority.unwrap_or(0.5)));
+        for sitemap_url in sitemap_urls.into_iter().take(max_urls.unwrap_or(usize::MAX)) {
+            let site = Site {
+                url: sitemap_url.url,
+                rank: frontier_url.rank,
+                scope: CrawlScope::Page,
+                lastmod: sitemap_url.lastmod,
+                priority: sitemap_url.priority,
+            };
+            if let Err(e) = self.site_sender.send(site) {
+                eprintln!("Error sending URL to site pool: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Set up the crawl of a leased url, waiting `delay` milliseconds between requests and within the budget of its domain.
+fn website(frontier_url: &FrontierUrl, delay: u64, budget: &CrawlBudget) -> Website {
+    let mut website: Website = Website::new(&frontier_url.url);
+    website.configuration.respect_robots_txt = true;
+    // Wait between requests as long as the site pool asks, the larger of the minimum delay and the Crawl-delay.
+    website.configuration.delay = delay;
+    // Limit the crawl to the budget of the domain, so a giant site can't hold a crawler for hours.
+    website.with_subdomains(budget.subdomains.unwrap_or(false));
+    if let Some(max_depth) = budget.max_depth {
+        website.with_depth(max_depth);
+    }
+    // A page crawled again or listed in a sitemap is fetched alone.
+    let max_pages = match frontier_url.scope {
+        CrawlScope::Site => budget.max_pages,
+        CrawlScope::Page => Some(1),
+    };
+    if let Some(max_pages) = max_pages {
+        website.with_budget(Some(HashMap::from([("*", max_pages)])));
+    }
+    website
+}
+
+/// Wait until the crawl runs out of time, forever when it has no timeout.
+async fn expire(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::OffsetDateTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::models::frontier::FrontierState;
+
+    // Serve a site whose every page links to ten pages, with a robots.txt when it is not empty, return its url.
+    async fn serve_site(robots_txt: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let _ = stream.read(&mut request).await;
+                    let response = match (request.starts_with(b"GET /robots.txt"), request.starts_with(b"GET /sitemap.xml")) {
+                        (true, _) if !robots_txt.is_empty() => format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", robots_txt.len(), robots_txt),
+                        (true, _) | (_, true) => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                        _ => {
+                            let links: String = (0..10).map(|page| format!("<a href=\"/page{}\">Page {}</a>", page, page)).collect();
+                            let body = format!("<html><body>{}</body></html>", links);
+                            format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+                        }
+                    };
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{}/", address)
+    }
+
+    fn frontier_url(url: &str, scope: CrawlScope) -> FrontierUrl {
+        let now = OffsetDateTime::now_utc();
+        FrontierUrl {
+            id: uuid::Uuid::new_v4(),
+            url: url.to_string(),
+            state: FrontierState::InFlight,
+            attempts: 1,
+            last_error: None,
+            rank: None,
+            scope,
+            crawl_count: 0,
+            change_count: 0,
+            crawled_at: None,
+            next_crawl_at: None,
+            lastmod: None,
+            priority: None,
+            stop_reason: None,
+            leased_at: Some(now),
+            created_at: now,
+            updated_at: now,
         }
     }
-}
\ No newline at end of file
+
+    // A site is crawled up to the pages its budget allows, a page alone
+    // Spider fetches the pages with a browser, which the tests have none of, the plain crawl applies the same budget.
+    #[tokio::test]
+    async fn can_crawl_within_budget() {
+        let url = serve_site("").await;
+        let budget = CrawlBudget { max_pages: Some(3), ..Default::default() };
+        let mut site = website(&frontier_url(&url, CrawlScope::Site), 0, &budget);
+        site.crawl_raw().await;
+        assert_eq!(site.get_links().len(), 3);
+
+        // A page is fetched alone even when its domain has no page limit.
+        let mut page = website(&frontier_url(&format!("{}page4", url), CrawlScope::Page), 0, &CrawlBudget::default());
+        page.crawl_raw().await;
+        assert_eq!(page.get_links().len(), 1);
+        assert!(page.get_links().contains(&CaseInsensitiveString::from(format!("{}page4", url))));
+    }
+
+    // The Crawl-delay of a robots.txt is kept within the policy, for the crawl and for the site pool
+    #[tokio::test]
+    async fn can_bound_crawl_delay() {
+        let (page_sender, _) = crossbeam_channel::unbounded();
+        let (_, url_reader) = crossbeam_channel::unbounded();
+        let (outcome_sender, _) = crossbeam_channel::unbounded();
+        let (site_sender, _) = crossbeam_channel::unbounded();
+        let policy = PolitenessPolicy {
+            min_delay: Duration::from_millis(20),
+            max_crawl_delay: Duration::from_millis(100),
+            max_per_host: 1,
+            max_per_ip: 1,
+        };
+        let crawler = Crawler::new(page_sender, url_reader, outcome_sender, site_sender, Arc::new(CrawlBudgets::default()), policy);
+        for (robots_txt, crawl_delay, delay) in [
+            ("User-agent: *\nCrawl-delay: 3600\n", Some(Duration::from_millis(100)), 100),
+            ("User-agent: *\nCrawl-delay: 0\n", Some(Duration::from_millis(20)), 20),
+            ("User-agent: *\nDisallow: /private\n", None, 50),
+        ] {
+            let url = serve_site(robots_txt).await;
+            let mut website = website(&frontier_url(&url, CrawlScope::Site), 50, &CrawlBudget::default());
+            assert_eq!(crawler.read_robots(&mut website, 50).await, crawl_delay);
+            assert_eq!(website.configuration.delay, delay);
+        }
+    }
+}
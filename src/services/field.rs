@@ -0,0 +1,158 @@
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::models::website_keywords::WebsiteKeywords;
+
+/// Number of heading levels, from `<h1>` to `<h6>`.
+pub const HEADING_LEVELS: usize = 6;
+
+/// The part of a page a keyword was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    /// The `<title>` of the page.
+    Title,
+    /// The headings of a level, from 1 for `<h1>` to 6 for `<h6>`.
+    Heading(u8),
+    /// The `<meta name="description">` content.
+    Description,
+    /// The words of the page url.
+    Url,
+    /// The visible text of the body.
+    Body,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(field: &str) -> Result<Self, Self::Err> {
+        match field {
+            "title" => Ok(Field::Title),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Ok(Field::Heading(field.as_bytes()[1] - b'0')),
+            "description" => Ok(Field::Description),
+            "url" => Ok(Field::Url),
+            "body" => Ok(Field::Body),
+            _ => Err(format!("Unknown field: {}", field)),
+        }
+    }
+}
+
+/// How much an occurrence of a keyword in each field counts towards its term frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FieldBoosts {
+    pub title: f64,
+    // `headings` are the boosts of the heading levels, `<h1>` first.
+    pub headings: [f64; HEADING_LEVELS],
+    pub description: f64,
+    pub url: f64,
+    pub body: f64,
+}
+
+impl Default for FieldBoosts {
+    fn default() -> Self {
+        Self {
+            title: 3.0,
+            headings: [2.5, 2.0, 1.75, 1.5, 1.25, 1.25],
+            description: 1.5,
+            url: 2.0,
+            body: 1.0,
+        }
+    }
+}
+
+impl FieldBoosts {
+    /// Set the boost of a field.
+    pub fn set(&mut self, field: Field, boost: f64) {
+        match field {
+            Field::Title => self.title = boost,
+            Field::Heading(level) => {
+                if let Some(index) = heading_index(level) {
+                    self.headings[index] = boost;
+                }
+            }
+            Field::Description => self.description = boost,
+            Field::Url => self.url = boost,
+            Field::Body => self.body = boost,
+        }
+    }
+
+    /// The term frequency of a posting with every field weighted by its boost.
+    pub fn weighted_frequency(&self, posting: &WebsiteKeywords) -> f64 {
+        let headings: f64 = self.headings.iter()
+            .zip(posting.heading_frequencies.iter())
+            .map(|(boost, frequency)| boost * *frequency as f64)
+            .sum();
+        self.title * posting.title_frequency as f64
+            + headings
+            + self.description * posting.description_frequency as f64
+            + self.url * posting.url_frequency as f64
+            + self.body * posting.frequency as f64
+    }
+}
+
+impl FromStr for FieldBoosts {
+    type Err = String;
+
+    /// Parse boosts written as `title:3,h1:2`, the fields left out keep their default boost.
+    /// `heading` sets the boost of every heading level.
+    fn from_str(boosts: &str) -> Result<Self, Self::Err> {
+        let mut field_boosts = FieldBoosts::default();
+        for boost in boosts.split(',').map(str::trim).filter(|boost| !boost.is_empty()) {
+            let (field, value) = boost.split_once(':').ok_or(format!("Expected field:boost but found {}", boost))?;
+            let value: f64 = value.trim().parse().map_err(|_| format!("Invalid boost for {}: {}", field, value))?;
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("Boost for {} must be non-negative", field));
+            }
+            match field.trim() {
+                "heading" => field_boosts.headings = [value; HEADING_LEVELS],
+                field => field_boosts.set(field.parse()?, value),
+            }
+        }
+        Ok(field_boosts)
+    }
+}
+
+/// The index of a heading level in the per level frequencies and boosts, `None` for a level that does not exist.
+pub fn heading_index(level: u8) -> Option<usize> {
+    (1..=HEADING_LEVELS).contains(&(level as usize)).then(|| level as usize - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_parse_boosts() {
+        let boosts: FieldBoosts = "title:5, body:0.5".parse().unwrap();
+        assert_eq!(boosts.title, 5.0);
+        assert_eq!(boosts.body, 0.5);
+        assert_eq!(boosts.headings, FieldBoosts::default().headings);
+        let boosts: FieldBoosts = "heading:1, h1:4".parse().unwrap();
+        assert_eq!(boosts.headings, [4.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+        assert!("h7:2".parse::<FieldBoosts>().is_err());
+        assert!("title".parse::<FieldBoosts>().is_err());
+        assert!("footer:2".parse::<FieldBoosts>().is_err());
+        assert!("title:-1".parse::<FieldBoosts>().is_err());
+    }
+
+    // A keyword in a `<h1>` counts more than in a `<h6>`
+    #[test]
+    fn weighs_heading_levels() {
+        let posting = |heading_frequencies: Vec<i32>| WebsiteKeywords {
+            id: uuid::Uuid::nil(),
+            keyword_id: uuid::Uuid::nil(),
+            website_id: uuid::Uuid::nil(),
+            frequency: 1,
+            positions: vec![0],
+            title_frequency: 0,
+            heading_frequencies,
+            description_frequency: 0,
+            url_frequency: 0,
+            created_at: time::OffsetDateTime::UNIX_EPOCH,
+            updated_at: time::OffsetDateTime::UNIX_EPOCH,
+        };
+        let boosts = FieldBoosts::default();
+        assert_eq!(boosts.weighted_frequency(&posting(vec![1, 0, 0, 0, 0, 0])), 3.5);
+        assert!(boosts.weighted_frequency(&posting(vec![1, 0, 0, 0, 0, 0])) > boosts.weighted_frequency(&posting(vec![0, 0, 0, 0, 0, 1])));
+        assert_eq!(boosts.weighted_frequency(&posting(Vec::new())), 1.0);
+    }
+}
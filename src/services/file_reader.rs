@@ -5,6 +5,8 @@ use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio_stream::StreamExt as TokioStreamExt;
 use url::ParseError;
+use crate::frontier::Site;
+use crate::models::frontier::CrawlScope;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WebsiteData {
@@ -16,12 +18,12 @@ pub struct FileReader {
     // Path to the file to read
     pub file: File,
 
-    // `url_sender` is a mpsc channel sender that sends a URL to the site pool.
-    pub url_sender: crossbeam_channel::Sender<url::Url>,
+    // `url_sender` is a mpsc channel sender that sends a URL and the rank of its domain to the site pool.
+    pub url_sender: crossbeam_channel::Sender<Site>,
 }
 
 impl FileReader {
-    pub async fn new(path_buf: PathBuf, url_sender: crossbeam_channel::Sender<url::Url>) -> Result<Self, Box<dyn Error>> {
+    pub async fn new(path_buf: PathBuf, url_sender: crossbeam_channel::Sender<Site>) -> Result<Self, Box<dyn Error>> {
         let file = File::open(path_buf).await?;
         Ok(Self {
             file,
@@ -58,8 +60,8 @@ impl FileReader {
                             continue;
                         }
                     };
-                    // Send the URL to the site pool.
-                    match self.url_sender.send(url) {
+                    // Send the URL to the site pool, the rank decides how soon it is crawled.
+                    match self.url_sender.send(Site { url, rank: Some(data.rank), scope: CrawlScope::Site, lastmod: None, priority: None }) {
                         Ok(_) => {}
                         Err(e) => {
                             eprintln!("Error sending URL to site pool: {:?}", e);
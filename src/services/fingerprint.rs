@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use crate::services::Field;
+
+// FNV-1a parameters, the hash must stay the same across builds since fingerprints are stored.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+// Fields hashed after the body, in a fixed order. Splitting the headings by level changed every fingerprint, so the
+// pages indexed before are reindexed with their heading levels the next time they are crawled.
+const FINGERPRINT_FIELDS: [Field; 9] = [
+    Field::Title,
+    Field::Heading(1),
+    Field::Heading(2),
+    Field::Heading(3),
+    Field::Heading(4),
+    Field::Heading(5),
+    Field::Heading(6),
+    Field::Description,
+    Field::Url,
+];
+// Separates tokens so that `ab c` and `a bc` hash differently, no keyword holds this byte.
+const TOKEN_SEPARATOR: u8 = 0xff;
+// Number of consecutive tokens hashed together by the simhash, so that reordered pages are not near duplicates.
+const SHINGLE_LEN: usize = 3;
+// Number of bands a simhash is split into, near duplicates share at least one band.
+pub const SIMHASH_BANDS: usize = 4;
+/// Largest number of differing simhash bits between near duplicates, below [`SIMHASH_BANDS`] so they share a band.
+pub const NEAR_DUPLICATE_DISTANCE: u32 = 3;
+
+/// Fingerprint the normalized token stream of a page, its body keywords followed by the keywords of every other field.
+/// Pages with the same fingerprint produce the same postings.
+pub fn fingerprint(texts: &[String], fields: &HashMap<Field, Vec<String>>) -> i64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    };
+    for text in texts {
+        write(text.as_bytes());
+        write(&[TOKEN_SEPARATOR]);
+    }
+    for (tag, field) in FINGERPRINT_FIELDS.iter().enumerate() {
+        // Tag every field so a keyword moving from one field to the next changes the fingerprint.
+        write(&[TOKEN_SEPARATOR, tag as u8]);
+        for keyword in fields.get(field).map(Vec::as_slice).unwrap_or_default() {
+            write(keyword.as_bytes());
+            write(&[TOKEN_SEPARATOR]);
+        }
+    }
+    hash as i64
+}
+
+/// Compute the 64 bit SimHash of the body tokens of a page, `None` for a page without tokens.
+/// Every bit is the majority vote of that bit over the hashes of the shingles, so similar pages get simhashes
+/// differing in few bits.
+pub fn simhash(texts: &[String]) -> Option<i64> {
+    if texts.is_empty() {
+        return None;
+    }
+    let mut votes = [0i64; 64];
+    // A page shorter than a shingle is a single shingle.
+    for shingle in texts.windows(SHINGLE_LEN.min(texts.len())) {
+        let hash = hash_shingle(shingle);
+        for (bit, vote) in votes.iter_mut().enumerate() {
+            if hash >> bit & 1 == 1 {
+                *vote += 1;
+            } else {
+                *vote -= 1;
+            }
+        }
+    }
+    let simhash = votes.iter()
+        .enumerate()
+        .filter(|(_, vote)| **vote > 0)
+        .fold(0u64, |simhash, (bit, _)| simhash | 1 << bit);
+    Some(simhash as i64)
+}
+
+/// Number of bits differing between two simhashes.
+pub fn hamming_distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Split a simhash into its 16 bit bands, most significant first.
+/// Must match the band indexes of the `websites` table.
+pub fn simhash_bands(simhash: i64) -> [i64; SIMHASH_BANDS] {
+    [(simhash >> 48) & 0xffff, (simhash >> 32) & 0xffff, (simhash >> 16) & 0xffff, simhash & 0xffff]
+}
+
+/// Hash a shingle with FNV-1a, then mix the bits since every simhash bit is voted on separately.
+fn hash_shingle(shingle: &[String]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for text in shingle {
+        for byte in text.as_bytes().iter().chain(std::iter::once(&TOKEN_SEPARATOR)) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    // Finalizer of splitmix64.
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::fixtures::near_duplicate_words;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn can_fingerprint_token_stream() {
+        let mut fields = HashMap::new();
+        fields.insert(Field::Title, strings(&["rust"]));
+        let body = strings(&["safe", "program"]);
+        assert_eq!(fingerprint(&body, &fields), fingerprint(&body, &fields.clone()));
+        assert_ne!(fingerprint(&body, &fields), fingerprint(&strings(&["program", "safe"]), &fields));
+        assert_ne!(fingerprint(&strings(&["ab", "c"]), &fields), fingerprint(&strings(&["a", "bc"]), &fields));
+
+        let mut moved = HashMap::new();
+        moved.insert(Field::Heading(1), strings(&["rust"]));
+        assert_ne!(fingerprint(&body, &fields), fingerprint(&body, &moved));
+    }
+
+    #[test]
+    fn can_simhash_near_duplicates() {
+        let (words, templated) = near_duplicate_words("changed");
+        let other: Vec<String> = (0..200).map(|n| format!("other{}", n)).collect();
+        let simhash_words = simhash(&words).unwrap();
+        assert!(hamming_distance(simhash_words, simhash(&templated).unwrap()) <= NEAR_DUPLICATE_DISTANCE);
+        assert!(hamming_distance(simhash_words, simhash(&other).unwrap()) > NEAR_DUPLICATE_DISTANCE);
+        assert_eq!(simhash(&[]), None);
+        assert_eq!(simhash(&strings(&["rust"])), simhash(&strings(&["rust"])));
+    }
+
+    #[test]
+    fn can_split_simhash_bands() {
+        assert_eq!(simhash_bands(0x0001_0002_0003_0004), [1, 2, 3, 4]);
+        assert_eq!(simhash_bands(-1), [0xffff; SIMHASH_BANDS]);
+    }
+}
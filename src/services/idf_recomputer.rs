@@ -0,0 +1,75 @@
+use std::time::Duration;
+use uuid::Uuid;
+use crate::models;
+
+// Number of keywords whose tfidf rows are rewritten at once, so the pages indexed meanwhile only wait on a batch.
+const KEYWORDS_PER_BATCH: i64 = 1_000;
+
+/// The outcome of a recompute.
+#[derive(Debug, Clone, Copy)]
+pub struct IdfRecompute {
+    // `version` is the corpus version the idf was recomputed at.
+    pub version: i64,
+    // `rows` is the number of tfidf rows rewritten.
+    pub rows: u64,
+}
+
+/// IdfRecomputer rewrites the stored idf and tfidf of every keyword from the current document frequencies.
+/// The text pool computes idf when a posting is written, so older rows drift as the corpus grows.
+pub struct IdfRecomputer {
+    // `db` is a postgres connection pool.
+    db: sqlx::PgPool,
+}
+
+impl IdfRecomputer {
+    /// Create a new IdfRecomputer instance.
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self {
+            db,
+        }
+    }
+
+    /// Recompute the idf of every row, returning `None` when it is already up to date.
+    pub async fn recompute_if_stale(&self) -> Result<Option<IdfRecompute>, Box<dyn std::error::Error>> {
+        let corpus_stats = models::corpus_stats::CorpusStats::get(&self.db).await.map_err(|e| format!("Error getting corpus stats: {:?}", e))?;
+        if !corpus_stats.is_idf_stale() {
+            return Ok(None);
+        }
+        self.recompute().await.map(Some)
+    }
+
+    /// Recompute the idf of every row, a batch of keywords at a time.
+    pub async fn recompute(&self) -> Result<IdfRecompute, Box<dyn std::error::Error>> {
+        // Read the version and the document count first, pages indexed while recomputing leave the scores stale for
+        // the next run.
+        let corpus_stats = models::corpus_stats::CorpusStats::get(&self.db).await.map_err(|e| format!("Error getting corpus stats: {:?}", e))?;
+        let mut rows = 0;
+        let mut after = Uuid::nil();
+        loop {
+            let (batch_rows, last_keyword_id) = models::website_keyword_tfidf::WebsiteKeywordTfidf::recompute_idf(&self.db, corpus_stats.document_count, after, KEYWORDS_PER_BATCH).await.map_err(|e| format!("Error recomputing idf: {:?}", e))?;
+            rows += batch_rows;
+            match last_keyword_id {
+                Some(last_keyword_id) => after = last_keyword_id,
+                None => break,
+            }
+        }
+        models::corpus_stats::CorpusStats::mark_idf_recomputed(&self.db, corpus_stats.version).await.map_err(|e| format!("Error marking idf recomputed: {:?}", e))?;
+        Ok(IdfRecompute {
+            version: corpus_stats.version,
+            rows,
+        })
+    }
+
+    /// Recompute the idf every `interval` whenever the corpus changed.
+    pub async fn start(self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match self.recompute_if_stale().await {
+                Ok(Some(recompute)) => println!("Recomputed idf of {} rows at corpus version {}.", recompute.rows, recompute.version),
+                Ok(None) => (),
+                Err(e) => eprintln!("Error recomputing idf: {:?}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// KeywordCache maps keywords to their ids so that frequent keywords are resolved without a query.
+/// It holds at most `capacity` keywords and is shared by every text pool worker.
+/// Only ids of committed keywords may be inserted, an id from a rolled back transaction would not exist.
+pub struct KeywordCache {
+    inner: Mutex<Clock>,
+}
+
+impl KeywordCache {
+    /// Create an empty KeywordCache holding at most `capacity` keywords.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Clock::new(capacity)),
+        }
+    }
+
+    /// Look up the ids of the keywords, returning the ones found and the keywords missing from the cache.
+    pub fn get_many(&self, keywords: &[String]) -> (HashMap<String, Uuid>, Vec<String>) {
+        let mut clock = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        for keyword in keywords {
+            match clock.get(keyword) {
+                Some(id) => {
+                    found.insert(keyword.clone(), id);
+                }
+                None => missing.push(keyword.clone()),
+            }
+        }
+        (found, missing)
+    }
+
+    /// Remember the ids of committed keywords.
+    pub fn insert_many(&self, keywords: HashMap<String, Uuid>) {
+        let mut clock = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        for (keyword, id) in keywords {
+            clock.insert(keyword, id);
+        }
+    }
+
+    /// Number of keywords in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Second chance (CLOCK) eviction: a keyword looked up since the hand last passed it is kept for another round,
+/// so hot keywords stay while keywords seen once are evicted first.
+struct Clock {
+    capacity: usize,
+    // `entries` maps each keyword to its id and whether it was looked up since the hand last passed it.
+    entries: HashMap<String, (Uuid, bool)>,
+    // `hand` holds the keywords in eviction order.
+    hand: VecDeque<String>,
+}
+
+impl Clock {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            hand: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, keyword: &str) -> Option<Uuid> {
+        let (id, referenced) = self.entries.get_mut(keyword)?;
+        *referenced = true;
+        Some(*id)
+    }
+
+    fn insert(&mut self, keyword: String, id: Uuid) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(entry) = self.entries.get_mut(&keyword) {
+            *entry = (id, true);
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.entries.insert(keyword.clone(), (id, false));
+        self.hand.push_back(keyword);
+    }
+
+    /// Evict the first keyword not looked up since the hand last passed it.
+    fn evict(&mut self) {
+        while let Some(keyword) = self.hand.pop_front() {
+            match self.entries.get_mut(&keyword) {
+                Some((_, referenced)) if *referenced => {
+                    *referenced = false;
+                    self.hand.push_back(keyword);
+                }
+                Some(_) => {
+                    self.entries.remove(&keyword);
+                    return;
+                }
+                None => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_find_cached_keywords() {
+        let cache = KeywordCache::new(10);
+        let id = Uuid::new_v4();
+        cache.insert_many(HashMap::from([("rust".to_string(), id)]));
+        let (found, missing) = cache.get_many(&["rust".to_string(), "tokio".to_string()]);
+        assert_eq!(found.get("rust"), Some(&id));
+        assert_eq!(missing, vec!["tokio".to_string()]);
+    }
+
+    // Keywords looked up since they were cached survive eviction
+    #[test]
+    fn keeps_hot_keywords() {
+        let cache = KeywordCache::new(2);
+        cache.insert_many(HashMap::from([("rust".to_string(), Uuid::new_v4())]));
+        cache.insert_many(HashMap::from([("tokio".to_string(), Uuid::new_v4())]));
+        cache.get_many(&["rust".to_string()]);
+        cache.insert_many(HashMap::from([("axum".to_string(), Uuid::new_v4())]));
+        assert_eq!(cache.len(), 2);
+        let (_, missing) = cache.get_many(&["rust".to_string(), "tokio".to_string(), "axum".to_string()]);
+        assert_eq!(missing, vec!["tokio".to_string()]);
+    }
+}
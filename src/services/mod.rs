@@ -3,9 +3,40 @@ mod site_pool;
 mod page_parser;
 mod file_reader;
 mod text_pool;
+mod analyzer;
+mod search;
+mod ranking;
+mod query_parser;
+mod snippet;
+mod spelling;
+mod autocomplete;
+mod field;
+mod idf_recomputer;
+mod keyword_cache;
+mod fingerprint;
+mod robots;
+mod politeness;
+mod recrawl;
+mod sitemap;
+mod budget;
 
 pub use crawler::Crawler;
-pub use site_pool::SitePool;
-pub use page_parser::PageParser;
+pub use site_pool::{CrawlOutcome, DnsResolver, Resolver, SitePool};
+pub use page_parser::{PageParser, ParsedPage, Removal};
 pub use file_reader::FileReader;
 pub use text_pool::TextPool;
+pub use analyzer::{Analyzer, Token};
+pub use search::{SearchQuery, SearchResult, SearchService, TermScore};
+pub use ranking::{Bm25Params, Ranking};
+pub use query_parser::{ParseError, QueryNode};
+pub use snippet::snippet;
+pub use spelling::SpellingCorrector;
+pub use autocomplete::{Autocomplete, Completion, CompletionSource};
+pub use field::{heading_index, Field, FieldBoosts, HEADING_LEVELS};
+pub use idf_recomputer::{IdfRecompute, IdfRecomputer};
+pub use keyword_cache::KeywordCache;
+pub use fingerprint::{fingerprint, hamming_distance, simhash, simhash_bands, NEAR_DUPLICATE_DISTANCE};
+pub use politeness::{CrawlJob, HostScheduler, PolitenessPolicy};
+pub use recrawl::{CrawlReport, RecrawlPolicy, RecrawlScheduler};
+pub use sitemap::{parse_sitemap, Sitemap, SitemapReader, SitemapUrl};
+pub use budget::{CrawlBudget, CrawlBudgets};
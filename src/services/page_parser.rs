@@ -1,41 +1,68 @@
 use std::collections::HashMap;
-use rust_numerals::number_to_cardinal;
 use scraper::Selector;
 use spider::page::Page;
+use crate::services::{heading_index, Analyzer, Field, HEADING_LEVELS};
+
+// Elements whose text is never shown to a visitor.
+const INVISIBLE_ELEMENTS: [&str; 5] = ["script", "style", "noscript", "template", "head"];
+// Status codes telling the page no longer exists.
+const GONE_STATUS_CODES: [u16; 2] = [404, 410];
+
+/// Why a crawled page is removed from the index instead of being indexed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Removal {
+    // `Gone` is the status code of a page that no longer exists.
+    Gone(u16),
+    // `NoIndex` is a robots meta tag asking not to index the page.
+    NoIndex,
+}
+
+/// ParsedPage is a page with its visible text and the keywords normalized from it.
+pub struct ParsedPage {
+    pub page: Page,
+    // `content` is the visible text of the page with whitespace collapsed, kept for result snippets.
+    pub content: String,
+    // `texts` are the keywords of the body in document order, so the index of a keyword is its position in the page.
+    pub texts: Vec<String>,
+    // `surfaces` maps each keyword of the body to the first word it was normalized from, shown in suggestions.
+    pub surfaces: HashMap<String, String>,
+    // `fields` are the keywords of the title, headings, description and url.
+    pub fields: HashMap<Field, Vec<String>>,
+    // `removal` is set when the page must be removed from the index, its text is then left empty.
+    pub removal: Option<Removal>,
+}
 
 pub struct PageParser {
     // `page_rx` is a mpsc channel receiver that receives a page from the page pool.
     page_rx: crossbeam_channel::Receiver<Page>,
-    // `text_sender` is a mpsc channel sender that sends a vector of processed texts to the text pool.
-    text_tx: crossbeam_channel::Sender<(Page, Vec<String>)>,
-    // `lemmatizer_map` is a hashmap that stores the lemmatized words.
-    lemmatizer_map: HashMap<String, String>,
-    // `stemmer` is a stemmer instance.
-    stemmer: rust_stemmers::Stemmer,
-    // `stop_words` is a list of stopwords.
-    stop_words: Vec<String>,
+    // `text_sender` is a mpsc channel sender that sends a parsed page to the text pool.
+    text_tx: crossbeam_channel::Sender<ParsedPage>,
+    // `analyzer` normalizes the page text into keywords.
+    analyzer: Analyzer,
+    // `body_selector` selects the body of the page.
+    body_selector: Selector,
+    // `title_selector` selects the title of the page.
+    title_selector: Selector,
+    // `heading_selector` selects the headings of the page.
+    heading_selector: Selector,
+    // `description_selector` selects the meta description of the page.
+    description_selector: Selector,
+    // `robots_selector` selects the robots meta tags of the page.
+    robots_selector: Selector,
 }
 
 impl PageParser {
     /// Create a new PageParser instance.
-    pub fn new(page_rx: crossbeam_channel::Receiver<Page>, text_tx: crossbeam_channel::Sender<(Page, Vec<String>)>, lemmatizer_json_path: std::path::PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
-        let lemmatizer_json = std::fs::read_to_string(lemmatizer_json_path)?;
-        let mut lemmatizer_json: HashMap<String, String> = serde_json::from_str(&lemmatizer_json)?;
-        let mut map = HashMap::new();
-        let keys: Vec<String> = lemmatizer_json.keys().cloned().collect();
-        for key in keys.iter() {
-            let (k, v) = match lemmatizer_json.remove_entry(key) {
-                Some((k, v)) => (k, v),
-                None => continue,
-            };
-            map.insert(k, v);
-        }
+    pub fn new(page_rx: crossbeam_channel::Receiver<Page>, text_tx: crossbeam_channel::Sender<ParsedPage>, lemmatizer_json_path: std::path::PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Self {
             page_rx,
             text_tx,
-            lemmatizer_map: map,
-            stemmer: rust_stemmers::Stemmer::create(rust_stemmers::Algorithm::English),
-            stop_words: stop_words::get(stop_words::LANGUAGE::English),
+            analyzer: Analyzer::new(lemmatizer_json_path)?,
+            body_selector: Selector::parse("body").map_err(|e| format!("Error parsing selector: {:?}", e))?,
+            title_selector: Selector::parse("title").map_err(|e| format!("Error parsing selector: {:?}", e))?,
+            heading_selector: Selector::parse("h1, h2, h3, h4, h5, h6").map_err(|e| format!("Error parsing selector: {:?}", e))?,
+            description_selector: Selector::parse(r#"meta[name="description"]"#).map_err(|e| format!("Error parsing selector: {:?}", e))?,
+            robots_selector: Selector::parse(r#"meta[name="robots"]"#).map_err(|e| format!("Error parsing selector: {:?}", e))?,
         })
     }
 
@@ -43,202 +70,119 @@ impl PageParser {
     pub async fn start(self) {
         // Loop to receive pages from the page receiver.
         while let Ok(page) = self.page_rx.recv() {
-            let html = page.get_html();
-            let document = scraper::Html::parse_document(&html);
-            // Create a wildcard selector to select all elements
-            let selector = match Selector::parse("*") {
-                Ok(selector) => selector,
-                Err(e) => {
-                    eprintln!("Error parsing selector: {:?}", e);
-                    continue;
-                }
-            };
-
-            // Iterate over all elements and collect their text content
-            let texts: Vec<String> = document.select(&selector)
-                .flat_map(|el| el.text())
-                .flat_map(|text| text.split_whitespace().map(str::to_string))
-                .collect();
-            let texts = self.preprocess_text(texts);
-            match self.text_tx.send((page, texts)) {
+            let parsed_page = self.parse(page);
+            match self.text_tx.send(parsed_page) {
                 Ok(_) => (),
                 Err(e) => eprintln!("Error sending texts to text pool: {:?}", e),
             }
         }
     }
 
-    /// Preprocess the text.
-    fn preprocess_text(&self, texts: Vec<String>) -> Vec<String> {
-        // lower
-        let texts = Self::parse_lower(texts);
-        let texts = texts.iter()
-            // split by whitespace
-            .flat_map(|text| text.split_whitespace().map(str::to_string))
-            // remove punctuation
-            .map(|text| Self::remove_punctuation(&text))
-            // remove apostrophes
-            .map(|text| Self::remove_apostrophes(&text))
-            // remove single characters
-            .filter(|text| text.len() > 1)
-            // remove length more than 50
-            .filter(|text| text.len() < 50)
-            // convert numbers to words
-            .map(|text| Self::convert_numbers_to_words(&text))
-            .collect();
-        // Remove stopwords
-        let texts = self.remove_stopwords(texts);
-        let texts = texts.iter()
-            // Stem the words
-            .map(|text| self.stem_word(text))
-            // Lemmatize the words
-            .map(|text| self.lemmatize_word(&text))
-            // remove punctuation again
-            .map(|text| Self::remove_punctuation(&text))
-            // convert numbers to words again
-            .map(|text| Self::convert_numbers_to_words(&text))
+    /// Parse the page, or tell why it must be removed from the index.
+    fn parse(&self, page: Page) -> ParsedPage {
+        let status_code = page.status_code.as_u16();
+        if GONE_STATUS_CODES.contains(&status_code) {
+            return ParsedPage { page, content: String::new(), texts: Vec::new(), surfaces: HashMap::new(), fields: HashMap::new(), removal: Some(Removal::Gone(status_code)) };
+        }
+        let html = page.get_html();
+        let document = scraper::Html::parse_document(&html);
+        let noindex = document.select(&self.robots_selector)
+            .filter_map(|element| element.value().attr("content"))
+            .any(is_noindex);
+        if noindex {
+            return ParsedPage { page, content: String::new(), texts: Vec::new(), surfaces: HashMap::new(), fields: HashMap::new(), removal: Some(Removal::NoIndex) };
+        }
+        let content = self.visible_text(&document);
+        let tokens = self.analyzer.tokenize(&content);
+        let mut surfaces = HashMap::new();
+        for token in tokens.iter() {
+            if !surfaces.contains_key(&token.keyword) {
+                surfaces.insert(token.keyword.clone(), token.surface(&content));
+            }
+        }
+        let texts = tokens.into_iter()
+            .map(|token| token.keyword)
             .collect();
-        texts
-    }
-
-
-    /// Parse all the words in the text and cast them to lowercase.
-    fn parse_lower(texts: Vec<String>) -> Vec<String> {
-        texts.iter()
-            .map(|text| text.to_lowercase())
-            .collect()
-    }
-
-    /// Punctuation marks to be removed from the text.
-    fn remove_punctuation(text: &str) -> String {
-        text.chars()
-            .filter(|c| !c.is_ascii_punctuation())
-            .collect()
-    }
-
-    /// Remove apostrophes from the text.
-    fn remove_apostrophes(text: &str) -> String {
-        text.chars()
-            .filter(|c| *c != '\'')
-            .collect()
+        let fields = self.fields(&document, page.get_url());
+        ParsedPage { page, content, texts, surfaces, fields, removal: None }
     }
 
-    /// Convert Numbers to Words
-    fn convert_numbers_to_words(text: &str) -> String {
-        // try to convert the number to a word
-        let num = text.parse::<i64>();
-        match num {
-            Ok(num) => {
-                number_to_cardinal(num)
+    /// Collect the keywords of every field other than the body.
+    fn fields(&self, document: &scraper::Html, url: &str) -> HashMap<Field, Vec<String>> {
+        let title: Vec<String> = document.select(&self.title_selector)
+            .flat_map(|element| element.text())
+            .map(str::to_string)
+            .collect();
+        // Keep the headings of every level apart, they weigh differently.
+        let mut headings: Vec<Vec<String>> = vec![Vec::new(); HEADING_LEVELS];
+        for element in document.select(&self.heading_selector) {
+            let level = element.value().name()[1..].parse().ok().and_then(heading_index);
+            if let Some(index) = level {
+                headings[index].extend(element.text().map(str::to_string));
             }
-            Err(_) => text.to_string(),
         }
-    }
-
-    /// Remove stopwords from the text.
-    fn remove_stopwords(&self, texts: Vec<String>) -> Vec<String> {
-        texts.iter()
-            .filter(|text| !self.stop_words.contains(text))
-            .map(|text| text.clone())
-            .collect()
-    }
+        let description: Vec<String> = document.select(&self.description_selector)
+            .filter_map(|element| element.value().attr("content"))
+            .map(str::to_string)
+            .collect();
+        // Split the host and path of the url into words, the scheme says nothing about the page.
+        let url_words = match url::Url::parse(url) {
+            Ok(url) => format!("{} {}", url.host_str().unwrap_or_default(), url.path()),
+            Err(_) => url.to_string(),
+        };
+        let url_words: String = url_words.chars()
+            .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+            .collect();
 
-    /// Stem the word in the text.
-    fn stem_word(&self, word: &str) -> String {
-        self.stemmer.stem(word).to_string()
+        let mut fields = HashMap::new();
+        fields.insert(Field::Title, self.analyzer.preprocess_text(title));
+        for (index, headings) in headings.into_iter().enumerate() {
+            fields.insert(Field::Heading(index as u8 + 1), self.analyzer.preprocess_text(headings));
+        }
+        fields.insert(Field::Description, self.analyzer.preprocess_text(description));
+        fields.insert(Field::Url, self.analyzer.preprocess_text(vec![url_words]));
+        fields
     }
 
-    /// Lemmatize the word in the text.
-    fn lemmatize_word(&self, word: &str) -> String {
-        match self.lemmatizer_map.get(word) {
-            Some(lemma) => lemma.clone(),
-            None => word.to_string(),
+    /// Collect the text of the body that a visitor can see, separated by single spaces.
+    fn visible_text(&self, document: &scraper::Html) -> String {
+        let root = document.select(&self.body_selector).next().unwrap_or_else(|| document.root_element());
+        let mut words: Vec<&str> = Vec::new();
+        for node in root.descendants() {
+            let text = match node.value().as_text() {
+                Some(text) => text,
+                None => continue,
+            };
+            let invisible = node.ancestors().any(|ancestor| {
+                ancestor.value().as_element()
+                    .map(|element| INVISIBLE_ELEMENTS.contains(&element.name()))
+                    .unwrap_or(false)
+            });
+            if !invisible {
+                words.extend(text.split_whitespace());
+            }
         }
+        words.join(" ")
     }
 }
 
+/// Whether the content of a robots meta tag, e.g. `noindex, follow`, forbids indexing the page.
+fn is_noindex(content: &str) -> bool {
+    content.split(',')
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .any(|directive| directive == "noindex" || directive == "none")
+}
+
 #[cfg(test)]
-mod test{
-    use std::path::PathBuf;
-    use crossbeam_channel::unbounded;
+mod test {
     use super::*;
-    fn get_page_parser() -> Result<PageParser, Box<dyn std::error::Error>> {
-        let (_, page_receiver) = unbounded();
-        let (text_sender, _) = unbounded();
-        let lemmatizer_json_path = PathBuf::from("assets/lemmatizedMap.json");
-        PageParser::new(page_receiver, text_sender, lemmatizer_json_path)
-    } 
-    
-    // Remove punctuation
-    #[test]
-    fn can_remove_punctuation() {
-        let text = "Hello, World!";
-        let text = PageParser::remove_punctuation(text);
-        assert_eq!(text, "Hello World");
-    }
-    // Remove apostrophes
-    #[test]
-    fn can_remove_apostrophes() {
-        let text = "Hello's World";
-        let text = PageParser::remove_apostrophes(text);
-        assert_eq!(text, "Hellos World");
-    }
-    
-    // Convert Numbers to Words
-    #[test]
-    fn can_convert_numbers_to_words() {
-        let text = "123";
-        let text = PageParser::convert_numbers_to_words(text);
-        assert_eq!(text, "one hundred and twenty-three");
-    }
-    // Remove stopwords
-    #[test]
-    fn can_remove_stopwords() {
-        let page_parser = get_page_parser().unwrap();
-        let texts = vec!["the".to_string(), "quick".to_string(), "brown".to_string(), "fox".to_string()];
-        let texts = page_parser.remove_stopwords(texts);
-        assert_eq!(texts, vec!["quick".to_string(), "brown".to_string(), "fox".to_string()]);
-    }
-    
-    // Stem the word
-    #[test]
-    fn can_stem_word() {
-        let page_parser = get_page_parser().unwrap();
-        let word = "running";
-        let word = page_parser.stem_word(word);
-        assert_eq!(word, "run");
-    }
-    // Lemmatize the word
-    #[test]
-    fn can_lemmatize_word() {
-        let page_parser = get_page_parser().unwrap();
-        let word = "running";
-        let word = page_parser.lemmatize_word(word);
-        assert_eq!(word, "run");
-    }
-    
-    // Parse lower
+
     #[test]
-    fn can_parse_lower() {
-        let texts = vec!["Hello".to_string(), "World".to_string()];
-        let texts = PageParser::parse_lower(texts);
-        assert_eq!(texts, vec!["hello".to_string(), "world".to_string()]);
-    }
-    #[tokio::test]
-    async fn can_preprocess_text () -> Result<(), Box<dyn std::error::Error>> {
-        let texts = vec!["Hello, World!".to_string(),
-                            "The quick brown fox jumps over the lazy dog.".to_string(),
-                            "123".to_string(),
-                            "running".to_string(),
-                            "the".to_string(),
-                            "quick".to_string(),
-                            "brown".to_string(),
-                            "fox".to_string(),
-                            "jumps".to_string(),
-                            "over".to_string()
-        ];
-        let page_parser = get_page_parser()?;
-        let texts = page_parser.preprocess_text(texts);
-        assert_eq!(texts, vec!["quick".to_string(), "brown".to_string(), "fox".to_string(), "jump".to_string(), "lazi".to_string(), "dog".to_string(), "one hundred and twentythre".to_string(), "run".to_string(), "quick".to_string(), "brown".to_string(), "fox".to_string(), "jump".to_string()]);
-        Ok(())
+    fn can_detect_noindex() {
+        assert!(is_noindex("noindex"));
+        assert!(is_noindex("NOINDEX, follow"));
+        assert!(is_noindex("none"));
+        assert!(!is_noindex("index, nofollow"));
+        assert!(!is_noindex(""));
     }
-}
\ No newline at end of file
+}
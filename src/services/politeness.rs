@@ -0,0 +1,316 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use crate::models::frontier::FrontierUrl;
+
+/// The politeness rules every host is crawled with.
+#[derive(Debug, Clone, Copy)]
+pub struct PolitenessPolicy {
+    // `min_delay` is the shortest time between two requests to a host, and between two crawls starting on an IP address.
+    pub min_delay: Duration,
+    // `max_crawl_delay` caps the Crawl-delay a robots.txt asks for, so a host can't stall its urls forever.
+    pub max_crawl_delay: Duration,
+    // `max_per_host` is the largest number of crawls of a host at once.
+    pub max_per_host: usize,
+    // `max_per_ip` is the largest number of crawls of the hosts sharing an IP address at once.
+    pub max_per_ip: usize,
+}
+
+impl PolitenessPolicy {
+    /// The delay between two requests to a host whose robots.txt asks for `crawl_delay`, within the policy.
+    pub fn delay(&self, crawl_delay: Option<Duration>) -> Duration {
+        crawl_delay.unwrap_or_default().min(self.max_crawl_delay).max(self.min_delay)
+    }
+}
+
+/// A url ready to be crawled.
+#[derive(Debug, Clone)]
+pub struct CrawlJob {
+    pub frontier_url: FrontierUrl,
+    // `delay` is the time to wait between two requests of the crawl, the larger of the minimum and the Crawl-delay.
+    pub delay: Duration,
+}
+
+/// HostScheduler queues the urls to crawl per host and per IP address, and hands them out as politeness allows.
+///
+/// A host is crawled at most `max_per_host` times at once, the hosts sharing an IP address at most `max_per_ip` times,
+/// and crawls of a host or IP address start at least a delay apart. A host is forgotten once it has nothing queued or
+/// running, its next url waits for a new lookup.
+pub struct HostScheduler {
+    policy: PolitenessPolicy,
+    hosts: HashMap<String, HostQueue>,
+    ips: HashMap<IpAddr, Slot>,
+    // `running` maps the id of every url handed out to its host.
+    running: HashMap<Uuid, String>,
+    // `queued` is the number of urls waiting in the host queues.
+    queued: usize,
+}
+
+struct HostQueue {
+    urls: VecDeque<FrontierUrl>,
+    // `rank` is the best Tranco rank of the urls queued for the host, `None` when none is ranked.
+    rank: Option<i32>,
+    // `ip` is the address the host resolved to, `None` when it could not be resolved.
+    ip: Option<IpAddr>,
+    delay: Duration,
+    slot: Slot,
+}
+
+/// The crawls running on a host or IP address and when the next one may start.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    running: usize,
+    next_at: Instant,
+}
+
+impl HostScheduler {
+    /// Create a new HostScheduler instance.
+    pub fn new(policy: PolitenessPolicy) -> Self {
+        Self {
+            policy,
+            hosts: HashMap::new(),
+            ips: HashMap::new(),
+            running: HashMap::new(),
+            queued: 0,
+        }
+    }
+
+    /// Whether the host was looked up and is still known.
+    pub fn knows_host(&self, host: &str) -> bool {
+        self.hosts.contains_key(host)
+    }
+
+    /// Register a host with the address it resolved to and the Crawl-delay of its robots.txt.
+    pub fn add_host(&mut self, host: &str, ip: Option<IpAddr>, crawl_delay: Option<Duration>, now: Instant) {
+        let delay = self.policy.delay(crawl_delay);
+        self.hosts.entry(host.to_string()).or_insert_with(|| HostQueue {
+            urls: VecDeque::new(),
+            rank: None,
+            ip,
+            delay,
+            slot: Slot {
+                running: 0,
+                next_at: now,
+            },
+        });
+    }
+
+    /// Space the next crawls of a known host by the Crawl-delay its robots.txt asks for.
+    pub fn set_crawl_delay(&mut self, host: &str, crawl_delay: Duration) {
+        let delay = self.policy.delay(Some(crawl_delay));
+        if let Some(host_queue) = self.hosts.get_mut(host) {
+            host_queue.delay = delay;
+        }
+    }
+
+    /// Queue a url of a host, registering the host with the default delay if it was not added.
+    pub fn push(&mut self, host: &str, frontier_url: FrontierUrl, now: Instant) {
+        self.add_host(host, None, None, now);
+        if let Some(host_queue) = self.hosts.get_mut(host) {
+            host_queue.rank = match (host_queue.rank, frontier_url.rank) {
+                (Some(rank), Some(url_rank)) => Some(rank.min(url_rank)),
+                (rank, url_rank) => rank.or(url_rank),
+            };
+            host_queue.urls.push_back(frontier_url);
+            self.queued += 1;
+        }
+    }
+
+    /// Hand out the next url whose host and IP address allow a crawl to start at `now`.
+    /// The highest-ranked host goes first, then the host that has been ready the longest.
+    pub fn pop(&mut self, now: Instant) -> Option<CrawlJob> {
+        let host = self.hosts.iter()
+            .filter(|(_, host_queue)| !host_queue.urls.is_empty())
+            .filter_map(|(host, host_queue)| Some((self.ready_at(host_queue)?, host_queue.rank, host)))
+            .filter(|(ready_at, _, _)| *ready_at <= now)
+            .min_by_key(|(ready_at, rank, host)| (rank.unwrap_or(i32::MAX), *ready_at, *host))
+            .map(|(_, _, host)| host.clone())?;
+        let host_queue = self.hosts.get_mut(&host)?;
+        let frontier_url = host_queue.urls.pop_front()?;
+        host_queue.slot.running += 1;
+        host_queue.slot.next_at = now + host_queue.delay;
+        let delay = host_queue.delay;
+        if let Some(ip) = host_queue.ip {
+            let slot = self.ips.entry(ip).or_insert(Slot {
+                running: 0,
+                next_at: now,
+            });
+            slot.running += 1;
+            slot.next_at = now + self.policy.min_delay;
+        }
+        self.queued -= 1;
+        self.running.insert(frontier_url.id, host);
+        Some(CrawlJob {
+            frontier_url,
+            delay,
+        })
+    }
+
+    /// Record that the crawl of a url handed out is over, returning whether the url was running.
+    pub fn finish(&mut self, id: Uuid) -> bool {
+        let host = match self.running.remove(&id) {
+            Some(host) => host,
+            None => return false,
+        };
+        let host_queue = match self.hosts.get_mut(&host) {
+            Some(host_queue) => host_queue,
+            None => return true,
+        };
+        host_queue.slot.running -= 1;
+        let ip = host_queue.ip;
+        let idle = host_queue.slot.running == 0 && host_queue.urls.is_empty();
+        if idle {
+            self.hosts.remove(&host);
+        }
+        if let Some(ip) = ip {
+            if let Some(slot) = self.ips.get_mut(&ip) {
+                slot.running -= 1;
+                if slot.running == 0 && !self.hosts.values().any(|host_queue| host_queue.ip == Some(ip)) {
+                    self.ips.remove(&ip);
+                }
+            }
+        }
+        true
+    }
+
+    /// When the next url can be handed out, `None` when nothing is queued or every host with urls is at capacity.
+    pub fn next_ready_at(&self) -> Option<Instant> {
+        self.hosts.values()
+            .filter(|host_queue| !host_queue.urls.is_empty())
+            .filter_map(|host_queue| self.ready_at(host_queue))
+            .min()
+    }
+
+    /// Number of urls waiting in the host queues.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// The host of a url handed out and not finished.
+    pub fn running_host(&self, id: Uuid) -> Option<&str> {
+        self.running.get(&id).map(String::as_str)
+    }
+
+    /// The ids of the urls queued or handed out and not finished.
+    pub fn leased_ids(&self) -> Vec<Uuid> {
+        self.hosts.values()
+            .flat_map(|host_queue| host_queue.urls.iter().map(|frontier_url| frontier_url.id))
+            .chain(self.running.keys().copied())
+            .collect()
+    }
+
+    /// Number of urls handed out and not finished.
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// When a crawl of the host can start, `None` while the host or its IP address is at capacity.
+    fn ready_at(&self, host_queue: &HostQueue) -> Option<Instant> {
+        if host_queue.slot.running >= self.policy.max_per_host {
+            return None;
+        }
+        let ip_slot = host_queue.ip.and_then(|ip| self.ips.get(&ip));
+        match ip_slot {
+            Some(slot) if slot.running >= self.policy.max_per_ip => None,
+            Some(slot) => Some(host_queue.slot.next_at.max(slot.next_at)),
+            None => Some(host_queue.slot.next_at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use time::OffsetDateTime;
+    use crate::models::frontier::{CrawlScope, FrontierState};
+
+    fn frontier_url(url: &str, rank: Option<i32>) -> FrontierUrl {
+        let now = OffsetDateTime::now_utc();
+        FrontierUrl {
+            id: Uuid::new_v4(),
+            url: url.to_string(),
+            state: FrontierState::InFlight,
+            attempts: 1,
+            last_error: None,
+            rank,
+            scope: CrawlScope::Site,
+            crawl_count: 0,
+            change_count: 0,
+            crawled_at: None,
+            next_crawl_at: None,
+            lastmod: None,
+            priority: None,
+            stop_reason: None,
+            leased_at: Some(now),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // Urls of a host wait for the delay and for a free slot, hosts sharing an IP address share its slots
+    #[test]
+    fn can_schedule_politely() {
+        let policy = PolitenessPolicy {
+            min_delay: Duration::from_secs(1),
+            max_crawl_delay: Duration::from_secs(10),
+            max_per_host: 2,
+            max_per_ip: 1,
+        };
+        let mut scheduler = HostScheduler::new(policy);
+        let now = Instant::now();
+        let ip = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        scheduler.add_host("a.example", None, Some(Duration::from_secs(60)), now);
+        scheduler.add_host("b.example", ip, None, now);
+        scheduler.add_host("c.example", ip, None, now);
+        scheduler.push("a.example", frontier_url("https://a.example/1", None), now);
+        scheduler.push("a.example", frontier_url("https://a.example/2", None), now);
+        scheduler.push("b.example", frontier_url("https://b.example/", None), now);
+        scheduler.push("c.example", frontier_url("https://c.example/", None), now);
+
+        let mut first = [scheduler.pop(now).unwrap(), scheduler.pop(now).unwrap()];
+        first.sort_by(|a, b| a.frontier_url.url.cmp(&b.frontier_url.url));
+        assert_eq!(first[0].frontier_url.url, "https://a.example/1");
+        // The Crawl-delay is capped.
+        assert_eq!(first[0].delay, Duration::from_secs(10));
+        assert!(first[1].frontier_url.url == "https://b.example/" || first[1].frontier_url.url == "https://c.example/");
+        assert!(scheduler.pop(now).is_none());
+
+        // The other host of the IP address waits for the crawl running on it to finish.
+        let later = now + Duration::from_secs(5);
+        assert!(scheduler.pop(later).is_none());
+        assert!(scheduler.finish(first[1].frontier_url.id));
+        assert!(scheduler.pop(later).is_some());
+        assert_eq!(scheduler.next_ready_at(), Some(now + Duration::from_secs(10)));
+        assert_eq!(scheduler.pop(now + Duration::from_secs(10)).unwrap().frontier_url.url, "https://a.example/2");
+        assert_eq!(scheduler.queued(), 0);
+        assert_eq!(scheduler.running(), 3);
+        assert_eq!(scheduler.leased_ids().len(), 3);
+
+        // A Crawl-delay learned from a crawl spaces the next crawls of the host.
+        assert_eq!(scheduler.running_host(first[0].frontier_url.id), Some("a.example"));
+        scheduler.set_crawl_delay("a.example", Duration::from_secs(2));
+        scheduler.push("a.example", frontier_url("https://a.example/3", None), now);
+        assert!(scheduler.finish(first[0].frontier_url.id));
+        assert_eq!(scheduler.pop(now + Duration::from_secs(20)).unwrap().delay, Duration::from_secs(2));
+    }
+
+    // Of the hosts ready, the highest-ranked goes first
+    #[test]
+    fn can_schedule_by_rank() {
+        let policy = PolitenessPolicy {
+            min_delay: Duration::from_secs(1),
+            max_crawl_delay: Duration::from_secs(10),
+            max_per_host: 1,
+            max_per_ip: 1,
+        };
+        let mut scheduler = HostScheduler::new(policy);
+        let now = Instant::now();
+        scheduler.push("a.example", frontier_url("https://a.example/", None), now);
+        scheduler.push("b.example", frontier_url("https://b.example/", Some(200)), now);
+        scheduler.push("c.example", frontier_url("https://c.example/", Some(100)), now);
+        let order: Vec<String> = std::iter::from_fn(|| scheduler.pop(now)).map(|crawl_job| crawl_job.frontier_url.url).collect();
+        assert_eq!(order, vec!["https://c.example/", "https://b.example/", "https://a.example/"]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::fmt;
+use crate::services::Analyzer;
+
+/// QueryNode is the syntax tree of a search query, e.g. `rust AND (tokio OR async) -java`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryNode {
+    /// A single word, or a single keyword once analyzed.
+    Term(String),
+    /// Words that must appear one after another, written in quotes.
+    Phrase(Vec<String>),
+    /// Every child must match and no negated child may match. Adjacent terms are combined with AND.
+    And(Vec<QueryNode>),
+    /// At least one child must match.
+    Or(Vec<QueryNode>),
+    /// The child must not match, written as `NOT term` or `-term`. Only allowed next to a term it excludes from.
+    Not(Box<QueryNode>),
+}
+
+/// ParseError is a syntax error in a query, `position` is the byte offset in the query where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, position: usize) -> Self {
+        Self {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Phrase(String),
+    And,
+    Or,
+    Not,
+    LeftParen,
+    RightParen,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+impl QueryNode {
+    /// Parse a query string into its syntax tree. The leaves hold the words as typed.
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            end: input.len(),
+        };
+        if parser.peek().is_none() {
+            return Err(ParseError::new("Empty query", 0));
+        }
+        let node = parser.parse_or()?;
+        // Only an unmatched closing parenthesis can stop the parser early.
+        if let Some(token) = parser.peek() {
+            return Err(ParseError::new("Unexpected ')'", token.position));
+        }
+        Ok(node)
+    }
+
+    /// Run every leaf through the analyzer so that it holds index keywords.
+    /// Leaves that normalize to nothing, such as stopwords, are dropped, `None` means nothing is left.
+    pub fn analyze(self, analyzer: &Analyzer) -> Option<Self> {
+        match self {
+            QueryNode::Term(word) => analyzer.preprocess_text(vec![word]).into_iter().next().map(QueryNode::Term),
+            QueryNode::Phrase(words) => {
+                let mut keywords = analyzer.preprocess_text(words);
+                match keywords.len() {
+                    0 => None,
+                    1 => keywords.pop().map(QueryNode::Term),
+                    _ => Some(QueryNode::Phrase(keywords)),
+                }
+            }
+            QueryNode::And(children) => {
+                let children = children.into_iter().filter_map(|child| child.analyze(analyzer)).collect();
+                Self::group(children, QueryNode::And)
+            }
+            QueryNode::Or(children) => {
+                let children = children.into_iter().filter_map(|child| child.analyze(analyzer)).collect();
+                Self::group(children, QueryNode::Or)
+            }
+            QueryNode::Not(child) => child.analyze(analyzer).map(|child| QueryNode::Not(Box::new(child))),
+        }
+    }
+
+    /// Every keyword in the query, including negated ones.
+    pub fn keywords(&self) -> Vec<String> {
+        let mut keywords = Vec::new();
+        self.collect_keywords(true, &mut keywords);
+        keywords
+    }
+
+    /// The keywords that contribute to the score, negated keywords are left out.
+    pub fn positive_keywords(&self) -> Vec<String> {
+        let mut keywords = Vec::new();
+        self.collect_keywords(false, &mut keywords);
+        keywords
+    }
+
+    /// The keywords every matching website contains, `None` when a website can match without them, through an OR.
+    pub fn required_keywords(&self) -> Option<Vec<String>> {
+        match self {
+            QueryNode::Term(_) | QueryNode::Phrase(_) => Some(self.positive_keywords()),
+            QueryNode::And(children) => {
+                let mut keywords = Vec::new();
+                for child in children.iter().filter(|child| !matches!(child, QueryNode::Not(_))) {
+                    for keyword in child.required_keywords()? {
+                        if !keywords.contains(&keyword) {
+                            keywords.push(keyword);
+                        }
+                    }
+                }
+                (!keywords.is_empty()).then_some(keywords)
+            }
+            QueryNode::Or(_) | QueryNode::Not(_) => None,
+        }
+    }
+
+    fn collect_keywords(&self, include_negated: bool, keywords: &mut Vec<String>) {
+        match self {
+            QueryNode::Term(keyword) => {
+                if !keywords.contains(keyword) {
+                    keywords.push(keyword.clone());
+                }
+            }
+            QueryNode::Phrase(phrase) => {
+                for keyword in phrase {
+                    if !keywords.contains(keyword) {
+                        keywords.push(keyword.clone());
+                    }
+                }
+            }
+            QueryNode::And(children) | QueryNode::Or(children) => {
+                for child in children {
+                    child.collect_keywords(include_negated, keywords);
+                }
+            }
+            QueryNode::Not(child) => {
+                if include_negated {
+                    child.collect_keywords(include_negated, keywords);
+                }
+            }
+        }
+    }
+
+    /// Collapse a group with a single child, unless the child is a negation that needs the group around it.
+    fn group(mut children: Vec<Self>, group: fn(Vec<Self>) -> Self) -> Option<Self> {
+        match children.len() {
+            0 => None,
+            1 if !matches!(children[0], QueryNode::Not(_)) => children.pop(),
+            _ => Some(group(children)),
+        }
+    }
+}
+
+/// Split the query into tokens.
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let kind = match c {
+            '(' => {
+                chars.next();
+                TokenKind::LeftParen
+            }
+            ')' => {
+                chars.next();
+                TokenKind::RightParen
+            }
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                    phrase.push(c);
+                }
+                if !closed {
+                    return Err(ParseError::new("Unterminated quote", position));
+                }
+                TokenKind::Phrase(phrase)
+            }
+            // A leading minus negates the term after it, e.g. `-java`.
+            '-' => {
+                chars.next();
+                TokenKind::Not
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                // Operators are only recognized in upper case, so `and` stays an ordinary word.
+                match word.as_str() {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    _ => TokenKind::Word(word),
+                }
+            }
+        };
+        tokens.push(Token { kind, position });
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, from the loosest binding operator to the tightest:
+/// `or := and ("OR" and)*`, `and := unary (["AND"] unary)*`, `unary := ("NOT" | "-") unary | primary`,
+/// `primary := "(" or ")" | phrase | word`.
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    // `end` is the length of the query, reported when it ends too early.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.index).cloned();
+        self.index += 1;
+        token
+    }
+
+    fn position(&self) -> usize {
+        self.peek().map(|token| token.position).unwrap_or(self.end)
+    }
+
+    fn parse_or(&mut self) -> Result<QueryNode, ParseError> {
+        let mut children = vec![self.parse_and()?];
+        while matches!(self.peek().map(|token| &token.kind), Some(TokenKind::Or)) {
+            self.index += 1;
+            children.push(self.parse_and()?);
+        }
+        Ok(match children.len() {
+            1 => children.remove(0),
+            _ => QueryNode::Or(children),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<QueryNode, ParseError> {
+        let position = self.position();
+        let mut children = vec![self.parse_unary()?];
+        loop {
+            match self.peek().map(|token| &token.kind) {
+                Some(TokenKind::And) => {
+                    self.index += 1;
+                    children.push(self.parse_unary()?);
+                }
+                // Adjacent terms are combined with AND.
+                Some(TokenKind::Word(_)) | Some(TokenKind::Phrase(_)) | Some(TokenKind::Not) | Some(TokenKind::LeftParen) => {
+                    children.push(self.parse_unary()?);
+                }
+                _ => break,
+            }
+        }
+        if children.iter().all(|child| matches!(child, QueryNode::Not(_))) {
+            return Err(ParseError::new("NOT must be combined with a term to exclude from", position));
+        }
+        Ok(match children.len() {
+            1 => children.remove(0),
+            _ => QueryNode::And(children),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<QueryNode, ParseError> {
+        match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Not) => {
+                self.index += 1;
+                // A double negation cancels out.
+                match self.parse_unary()? {
+                    QueryNode::Not(child) => Ok(*child),
+                    child => Ok(QueryNode::Not(Box::new(child))),
+                }
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<QueryNode, ParseError> {
+        let position = self.position();
+        match self.advance() {
+            Some(Token { kind: TokenKind::Word(word), .. }) => Ok(QueryNode::Term(word)),
+            Some(Token { kind: TokenKind::Phrase(phrase), .. }) => {
+                Ok(QueryNode::Phrase(phrase.split_whitespace().map(str::to_string).collect()))
+            }
+            Some(Token { kind: TokenKind::LeftParen, .. }) => {
+                let node = self.parse_or()?;
+                match self.advance() {
+                    Some(Token { kind: TokenKind::RightParen, .. }) => Ok(node),
+                    _ => Err(ParseError::new("Missing ')' for '('", position)),
+                }
+            }
+            Some(Token { kind: TokenKind::RightParen, .. }) => Err(ParseError::new("Unexpected ')'", position)),
+            Some(Token { kind: TokenKind::And, .. }) => Err(ParseError::new("Expected a term before AND", position)),
+            Some(Token { kind: TokenKind::Or, .. }) => Err(ParseError::new("Expected a term before OR", position)),
+            Some(Token { kind: TokenKind::Not, .. }) => Err(ParseError::new("Expected a term after NOT", position)),
+            None => Err(ParseError::new("Expected a term", self.end)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn term(word: &str) -> QueryNode {
+        QueryNode::Term(word.to_string())
+    }
+
+    #[test]
+    fn can_parse_boolean_query() {
+        let query = QueryNode::parse("rust AND (tokio OR async) -java").unwrap();
+        assert_eq!(query, QueryNode::And(vec![
+            term("rust"),
+            QueryNode::Or(vec![term("tokio"), term("async")]),
+            QueryNode::Not(Box::new(term("java"))),
+        ]));
+    }
+
+    // AND binds tighter than OR, adjacent terms are combined with AND
+    #[test]
+    fn can_parse_precedence() {
+        let query = QueryNode::parse("a b OR c").unwrap();
+        assert_eq!(query, QueryNode::Or(vec![
+            QueryNode::And(vec![term("a"), term("b")]),
+            term("c"),
+        ]));
+    }
+
+    #[test]
+    fn can_parse_phrase() {
+        let query = QueryNode::parse("\"new york\" NOT pizza").unwrap();
+        assert_eq!(query, QueryNode::And(vec![
+            QueryNode::Phrase(vec!["new".to_string(), "york".to_string()]),
+            QueryNode::Not(Box::new(term("pizza"))),
+        ]));
+    }
+
+    #[test]
+    fn can_collect_keywords() {
+        let query = QueryNode::parse("rust AND (tokio OR rust) -java").unwrap();
+        assert_eq!(query.keywords(), vec!["rust".to_string(), "tokio".to_string(), "java".to_string()]);
+        assert_eq!(query.positive_keywords(), vec!["rust".to_string(), "tokio".to_string()]);
+    }
+
+    // Only a query without OR requires its keywords
+    #[test]
+    fn can_collect_required_keywords() {
+        let query = QueryNode::parse("rust \"async tokio\" -java").unwrap();
+        assert_eq!(query.required_keywords(), Some(vec!["rust".to_string(), "async".to_string(), "tokio".to_string()]));
+        assert_eq!(QueryNode::parse("rust (tokio OR async)").unwrap().required_keywords(), None);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(QueryNode::parse("").unwrap_err().position, 0);
+        assert_eq!(QueryNode::parse("rust AND").unwrap_err().position, 8);
+        assert_eq!(QueryNode::parse("(rust OR go").unwrap_err().position, 0);
+        assert_eq!(QueryNode::parse("rust)").unwrap_err().position, 4);
+        assert_eq!(QueryNode::parse("rust \"async").unwrap_err().position, 5);
+        assert_eq!(QueryNode::parse("rust OR -java").unwrap_err().position, 8);
+        assert_eq!(QueryNode::parse("OR rust").unwrap_err().position, 0);
+    }
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Ranking selects how the search service scores a website for a query term.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Ranking {
+    /// Use the tfidf stored in `website_keyword_tfidf` by the text pool.
+    #[default]
+    TfIdf,
+    /// Compute Okapi BM25 at query time from the keyword frequencies and website lengths.
+    Bm25(Bm25Params),
+}
+
+/// Tuning parameters of BM25.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bm25Params {
+    // `k1` controls how quickly repeated occurrences of a term stop adding to the score.
+    pub k1: f64,
+    // `b` controls how strongly the score is normalized by document length, from 0 (none) to 1 (full).
+    pub b: f64,
+}
+
+impl Default for Bm25Params {
+    fn default() -> Self {
+        Self {
+            k1: 1.2,
+            b: 0.75,
+        }
+    }
+}
+
+impl Bm25Params {
+    /// Calculate the BM25 inverse document frequency of a term.
+    pub fn idf(&self, total_docs_with_keyword: i64, total_docs: i64) -> f64 {
+        let total_docs_with_keyword = total_docs_with_keyword as f64;
+        let total_docs = total_docs as f64;
+        (1.0 + (total_docs - total_docs_with_keyword + 0.5) / (total_docs_with_keyword + 0.5)).ln()
+    }
+
+    /// Calculate the BM25 score of a term occurring `frequency` times in a document of `document_length` words.
+    pub fn score(&self, frequency: f64, document_length: f64, average_document_length: f64, idf: f64) -> f64 {
+        let length_ratio = if average_document_length > 0.0 {
+            document_length / average_document_length
+        } else {
+            1.0
+        };
+        let normalization = self.k1 * (1.0 - self.b + self.b * length_ratio);
+        idf * frequency * (self.k1 + 1.0) / (frequency + normalization)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Rare terms are worth more than common ones
+    #[test]
+    fn idf_favours_rare_terms() {
+        let params = Bm25Params::default();
+        assert!(params.idf(1, 100) > params.idf(50, 100));
+        assert!(params.idf(100, 100) > 0.0);
+    }
+
+    // Longer documents are penalized unless `b` is zero
+    #[test]
+    fn score_normalizes_document_length() {
+        let params = Bm25Params::default();
+        let short = params.score(3.0, 50.0, 100.0, 1.0);
+        let long = params.score(3.0, 200.0, 100.0, 1.0);
+        assert!(short > long);
+
+        let params = Bm25Params { k1: 1.2, b: 0.0 };
+        assert_eq!(params.score(3.0, 50.0, 100.0, 1.0), params.score(3.0, 200.0, 100.0, 1.0));
+    }
+
+    // Term frequency saturates at k1 + 1
+    #[test]
+    fn score_saturates() {
+        let params = Bm25Params::default();
+        let score = params.score(10_000.0, 100.0, 100.0, 1.0);
+        assert!(score < params.k1 + 1.0);
+        assert!(score > params.score(1.0, 100.0, 100.0, 1.0));
+    }
+}
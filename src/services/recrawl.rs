@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use crate::frontier::Frontier;
+
+// Largest number of due urls queued again at once.
+const REQUEUE_BATCH_SIZE: usize = 1_000;
+// How often the frontier is checked for due urls.
+const REQUEUE_INTERVAL: Duration = Duration::from_secs(60);
+// How long to wait for crawl reports when there were none.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Rank of the least popular domain of the Tranco list.
+const LOWEST_RANK: f64 = 1_000_000.0;
+
+/// A crawl of a page, reported once the page went through the index.
+#[derive(Debug, Clone)]
+pub struct CrawlReport {
+    pub url: url::Url,
+    // `changed` is whether the page was indexed anew, `false` when it was unchanged or removed.
+    pub changed: bool,
+}
+
+/// How long crawled urls wait before they are crawled again.
+#[derive(Debug, Clone, Copy)]
+pub struct RecrawlPolicy {
+    // `min_interval` is the wait of a url found changed on every crawl.
+    pub min_interval: Duration,
+    // `max_interval` is the wait of a url never found changed.
+    pub max_interval: Duration,
+}
+
+impl RecrawlPolicy {
+    /// How long to wait before crawling a url again, from its crawl history, the Tranco rank of its domain and its
+    /// sitemap priority.
+    ///
+    /// The change rate is the share of crawls that found the url changed, smoothed so a url crawled once sits halfway.
+    /// The wait goes geometrically from `max_interval` for a rate of 0 to `min_interval` for a rate of 1, and the urls of
+    /// the most popular domains wait up to half as long. A sitemap priority of 1 halves the wait and one of 0 makes it
+    /// half as long again, the default priority of 0.5 leaves it as it is.
+    pub fn interval(&self, crawl_count: i32, change_count: i32, rank: Option<i32>, priority: Option<f32>) -> Duration {
+        // A wait of at least a millisecond keeps the ratio of the bounds finite.
+        let min = self.min_interval.as_secs_f64().max(0.001);
+        let max = self.max_interval.as_secs_f64().max(min);
+        let change_rate = ((change_count.max(0) + 1) as f64 / (crawl_count.max(0) + 1) as f64).min(1.0);
+        let interval = min * (max / min).powf(1.0 - change_rate);
+        let popularity = match rank {
+            Some(rank) => 0.5 + 0.5 * ((rank.max(1) as f64).log10() / LOWEST_RANK.log10()).min(1.0),
+            None => 1.0,
+        };
+        let importance = 1.5 - priority.unwrap_or(0.5).clamp(0.0, 1.0) as f64;
+        Duration::from_secs_f64((interval * popularity * importance).clamp(min, max))
+    }
+}
+
+/// RecrawlScheduler keeps the crawl history of every url in the frontier and queues the urls again once they are due,
+/// so the site pool crawls them again: pages that change often are kept fresh and static pages are left alone.
+pub struct RecrawlScheduler<F: Frontier> {
+    // `report_receiver` is a mpsc channel receiver that receives the crawl of every page indexed.
+    report_receiver: crossbeam_channel::Receiver<CrawlReport>,
+    // `frontier` holds the crawl history of the urls.
+    frontier: Arc<F>,
+    policy: RecrawlPolicy,
+}
+
+impl<F: Frontier> RecrawlScheduler<F> {
+    /// Create a new RecrawlScheduler instance.
+    pub fn new(report_receiver: crossbeam_channel::Receiver<CrawlReport>, frontier: Arc<F>, policy: RecrawlPolicy) -> Self {
+        Self {
+            report_receiver,
+            frontier,
+            policy,
+        }
+    }
+
+    /// Start the recrawl scheduler in background.
+    pub async fn start(self) {
+        let mut requeue_at = Instant::now();
+        loop {
+            let mut reported = 0;
+            while let Ok(report) = self.report_receiver.try_recv() {
+                if let Err(e) = self.record(report).await {
+                    eprintln!("Error scheduling recrawl: {:?}", e);
+                }
+                reported += 1;
+            }
+            if Instant::now() >= requeue_at {
+                requeue_at = Instant::now() + REQUEUE_INTERVAL;
+                match self.frontier.requeue_due(REQUEUE_BATCH_SIZE).await {
+                    Ok(0) => (),
+                    Ok(count) => println!("Queued {} urls due for a recrawl.", count),
+                    Err(e) => eprintln!("Error queueing due urls: {:?}", e),
+                }
+            }
+            if reported == 0 {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Count the crawl of a page and set when it is due again.
+    async fn record(&self, report: CrawlReport) -> Result<(), Box<dyn std::error::Error>> {
+        let frontier_url = self.frontier.record_crawl(&report.url, report.changed).await?;
+        let interval = self.policy.interval(frontier_url.crawl_count, frontier_url.change_count, frontier_url.rank, frontier_url.priority);
+        self.frontier.schedule_recrawl(frontier_url.id, OffsetDateTime::now_utc() + interval).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use crate::frontier::MemoryFrontier;
+
+    fn policy() -> RecrawlPolicy {
+        RecrawlPolicy {
+            min_interval: Duration::from_secs(60 * 60),
+            max_interval: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    // Urls that change often, urls of popular domains and urls their sitemap puts first wait less, within the bounds of
+    // the policy
+    #[test]
+    fn can_compute_interval() {
+        let policy = policy();
+        assert_eq!(policy.interval(10, 10, None, None), policy.min_interval);
+        assert!(policy.interval(10, 9, None, None) < policy.interval(1, 0, None, None));
+        assert!(policy.interval(1, 0, None, None) < policy.interval(10, 0, None, None));
+        assert!(policy.interval(10, 0, None, None) <= policy.max_interval);
+        assert!(policy.interval(1, 0, Some(1), None) < policy.interval(1, 0, Some(100_000), None));
+        assert!(policy.interval(1, 0, Some(1_000_000), None) <= policy.interval(1, 0, None, None));
+        assert!(policy.interval(10, 10, Some(1), None) >= policy.min_interval);
+        assert!(policy.interval(1, 0, None, Some(1.0)) < policy.interval(1, 0, None, None));
+        assert!(policy.interval(1, 0, None, None) < policy.interval(1, 0, None, Some(0.0)));
+        assert_eq!(policy.interval(100, 0, None, Some(0.0)), policy.max_interval);
+    }
+
+    // A crawled page is added to the frontier with its crawl history and queued again once due
+    #[tokio::test]
+    async fn can_schedule_recrawl() {
+        let frontier = Arc::new(MemoryFrontier::new());
+        let (_report_sender, report_receiver) = unbounded();
+        // A due url is queued again at once.
+        let policy = RecrawlPolicy {
+            min_interval: Duration::ZERO,
+            max_interval: Duration::ZERO,
+        };
+        let scheduler = RecrawlScheduler::new(report_receiver, frontier.clone(), policy);
+        let url = url::Url::parse("https://a.example/page").unwrap();
+        scheduler.record(CrawlReport { url: url.clone(), changed: true }).await.unwrap();
+        scheduler.record(CrawlReport { url: url.clone(), changed: true }).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(frontier.requeue_due(10).await.unwrap(), 1);
+        let leased = frontier.lease(10).await.unwrap();
+        assert_eq!(leased.len(), 1);
+        assert_eq!(leased[0].url, url.as_str());
+        assert_eq!(leased[0].crawl_count, 2);
+        // The first crawl of a page can't find it changed.
+        assert_eq!(leased[0].change_count, 1);
+    }
+}
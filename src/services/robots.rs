@@ -0,0 +1,21 @@
+/// Find the sitemaps a robots.txt lists, whichever group their `Sitemap:` lines are in.
+/// Relative or invalid sitemap urls are left out.
+pub fn sitemaps(robots_txt: &str) -> Vec<url::Url> {
+    robots_txt.lines()
+        .filter_map(|line| line.split('#').next().unwrap_or_default().trim().split_once(':'))
+        .filter(|(field, _)| field.trim().eq_ignore_ascii_case("sitemap"))
+        .filter_map(|(_, value)| url::Url::parse(value.trim()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_find_sitemaps() {
+        let robots_txt = "User-agent: *\nDisallow: /private\nSitemap: https://a.example/sitemap.xml\n\nsitemap:https://a.example/news.xml.gz # news\nSitemap: /relative.xml\n";
+        let sitemaps: Vec<String> = sitemaps(robots_txt).iter().map(|url| url.to_string()).collect();
+        assert_eq!(sitemaps, vec!["https://a.example/sitemap.xml", "https://a.example/news.xml.gz"]);
+    }
+}
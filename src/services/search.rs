@@ -0,0 +1,639 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use serde::Serialize;
+use sqlx::types::BigDecimal;
+use uuid::Uuid;
+use crate::models;
+use crate::services::{snippet, Analyzer, Autocomplete, Bm25Params, Completion, FieldBoosts, ParseError, QueryNode, Ranking, SpellingCorrector};
+use crate::store::IndexStore;
+
+/// SearchService ranks the indexed websites against a query.
+pub struct SearchService<S: IndexStore> {
+    // `store` is the index the queries run against.
+    store: Arc<S>,
+    // `analyzer` normalizes the query the same way the page parser normalizes pages.
+    analyzer: Analyzer,
+    // `spelling` holds the indexed vocabulary for "did you mean" suggestions.
+    spelling: tokio::sync::RwLock<SpellingCorrector>,
+    // `autocomplete` completes typed prefixes from keywords and past queries.
+    autocomplete: tokio::sync::RwLock<Autocomplete>,
+}
+
+// Largest number of edits between a query word and its suggested correction.
+const MAX_SPELLING_DISTANCE: usize = 2;
+// Number of past queries loaded for autocompletion on startup.
+const POPULAR_QUERIES: i64 = 10_000;
+// Longest query that is remembered for autocompletion.
+const MAX_RECORDED_QUERY_LENGTH: usize = 255;
+// How far back from the newest keyword loaded a refresh looks again. A keyword is created at the start of the
+// transaction inserting it, so it can commit after keywords created later were loaded.
+const VOCABULARY_REFRESH_OVERLAP: time::Duration = time::Duration::minutes(10);
+// Least number of ranked websites whose clusters are looked up at once while collapsing near duplicates.
+const COLLAPSE_BATCH: usize = 100;
+
+/// A query to run against the index.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub text: String,
+    pub limit: usize,
+    pub offset: usize,
+    pub ranking: Ranking,
+    // `field_boosts` weighs the occurrences in each field, `None` weighs them with the default boosts.
+    pub field_boosts: Option<FieldBoosts>,
+    // `collapse_duplicates` keeps a single result per near duplicate cluster.
+    pub collapse_duplicates: bool,
+}
+
+/// The contribution of a single query term to a result's score.
+#[derive(Debug, Clone, Serialize)]
+pub struct TermScore {
+    pub term: String,
+    pub score: f64,
+}
+
+/// A website matching a query.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub website_id: Uuid,
+    pub url: String,
+    pub score: f64,
+    pub terms: Vec<TermScore>,
+    // `snippet` is HTML with the words matching the query wrapped in `<mark>`.
+    pub snippet: String,
+    // `duplicates` is the number of near duplicates collapsed into the result, among the websites ranked up to the end
+    // of the page.
+    pub duplicates: usize,
+}
+
+/// Postings of the query keywords, keyword to website id to posting.
+type Postings = HashMap<String, HashMap<Uuid, models::website_keywords::WebsiteKeywords>>;
+
+/// Number of websites containing each query keyword, the postings may only hold the websites matching the query.
+type DocumentFrequencies = HashMap<String, i64>;
+
+impl<S: IndexStore> SearchService<S> {
+    /// Create a new SearchService instance.
+    pub fn new(store: Arc<S>, analyzer: Analyzer) -> Self {
+        Self {
+            store,
+            analyzer,
+            spelling: tokio::sync::RwLock::new(SpellingCorrector::new(MAX_SPELLING_DISTANCE)),
+            autocomplete: tokio::sync::RwLock::new(Autocomplete::new()),
+        }
+    }
+
+    /// Load the keywords created since the last refresh into the spelling corrector and autocomplete, returning how many were loaded.
+    /// The keywords created shortly before the newest one loaded are loaded again, loading a keyword twice updates it.
+    pub async fn refresh_vocabulary(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let since = self.spelling.read().await.refreshed_at().saturating_sub(VOCABULARY_REFRESH_OVERLAP);
+        self.load_vocabulary(since).await
+    }
+
+    /// Load every keyword again, so the document frequencies of the keywords loaded before are brought up to date.
+    /// Returns how many were loaded.
+    pub async fn reload_vocabulary(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        self.load_vocabulary(time::OffsetDateTime::UNIX_EPOCH).await
+    }
+
+    /// Load the keywords created at or after `since` into the spelling corrector and autocomplete.
+    async fn load_vocabulary(&self, since: time::OffsetDateTime) -> Result<usize, Box<dyn std::error::Error>> {
+        let keywords = self.store.find_keyword_frequencies_since(since).await?;
+        let count = keywords.len();
+        {
+            let mut autocomplete = self.autocomplete.write().await;
+            for keyword in keywords.iter() {
+                autocomplete.insert_keyword(&keyword.surface, keyword.document_frequency);
+            }
+        }
+        self.spelling.write().await.add_keywords(keywords);
+        Ok(count)
+    }
+
+    /// Load the most searched past queries into autocomplete, returning how many were loaded.
+    pub async fn load_popular_queries(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let query_logs = self.store.find_popular_queries(POPULAR_QUERIES).await?;
+        let mut autocomplete = self.autocomplete.write().await;
+        for query_log in query_logs.iter() {
+            autocomplete.insert_query(&query_log.query, query_log.count);
+        }
+        Ok(query_logs.len())
+    }
+
+    /// Remember a searched query for autocompletion.
+    pub async fn record_query(&self, query: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let query = query.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if query.is_empty() || query.len() > MAX_RECORDED_QUERY_LENGTH {
+            return Ok(());
+        }
+        let query_log = self.store.record_query(&query).await?;
+        self.autocomplete.write().await.insert_query(&query_log.query, query_log.count);
+        Ok(())
+    }
+
+    /// Find the best `limit` completions of a typed prefix.
+    pub async fn complete(&self, prefix: &str, limit: usize) -> Vec<Completion> {
+        self.autocomplete.read().await.complete(prefix, limit)
+    }
+
+    /// Suggest the query with every word that is not in the vocabulary replaced by its closest keyword.
+    /// Returns `None` when every word is known or has no close keyword.
+    pub async fn suggest(&self, query: &str) -> Option<String> {
+        let spelling = self.spelling.read().await;
+        let mut corrected = false;
+        let words: Vec<String> = query.split(' ')
+            .map(|chunk| {
+                // Keep the operators, quotes and parentheses around the word.
+                let word = chunk.trim_matches(|c: char| !c.is_alphanumeric());
+                let keyword = match self.analyzer.preprocess_text(vec![word.to_string()]).pop() {
+                    Some(keyword) => keyword,
+                    None => return chunk.to_string(),
+                };
+                match spelling.correct(&keyword) {
+                    Some(correction) => {
+                        corrected = true;
+                        // Show the word the keyword was normalized from, a stem is not always a word.
+                        chunk.replacen(word, spelling.surface(&correction), 1)
+                    }
+                    None => chunk.to_string(),
+                }
+            })
+            .collect();
+        corrected.then(|| words.join(" "))
+    }
+
+    /// Whether the stored tfidf scores were computed against an older corpus than the current one.
+    /// BM25 computes idf at query time and is never stale.
+    pub async fn scores_stale(&self, ranking: &Ranking) -> Result<bool, Box<dyn std::error::Error>> {
+        if !matches!(ranking, Ranking::TfIdf) {
+            return Ok(false);
+        }
+        let corpus_stats = self.store.corpus_stats().await?;
+        Ok(corpus_stats.is_idf_stale())
+    }
+
+    /// Parse the query and normalize its terms into keywords, `None` when nothing in it can match.
+    pub fn parse_query(&self, query: &str) -> Result<Option<QueryNode>, ParseError> {
+        Ok(QueryNode::parse(query)?.analyze(&self.analyzer))
+    }
+
+    /// Search the index, returning at most `limit` results after skipping `offset`, best first.
+    /// A malformed query fails with a [`ParseError`].
+    pub async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
+        let node = match self.parse_query(&query.text)? {
+            Some(node) => node,
+            None => return Ok(Vec::new()),
+        };
+        let (postings, document_frequencies) = self.find_postings(&node).await?;
+        let websites = evaluate(&node, &postings);
+
+        // Only the keywords that are not negated contribute to the score.
+        let terms = node.positive_keywords();
+        let field_boosts = query.field_boosts.unwrap_or_default();
+        let mut matches = match query.ranking {
+            Ranking::TfIdf => self.score_tfidf(&terms, &postings, field_boosts).await?,
+            Ranking::Bm25(params) => self.score_bm25(&terms, &postings, &document_frequencies, params, field_boosts).await?,
+        };
+        matches.retain(|website_id, _| websites.contains(website_id));
+        for website_id in websites {
+            matches.entry(website_id).or_default();
+        }
+
+        let mut ranked: Vec<(Uuid, f64, Vec<TermScore>)> = matches.into_iter()
+            .map(|(website_id, terms)| {
+                let score = terms.iter().map(|term| term.score).sum();
+                (website_id, score, terms)
+            })
+            .collect();
+        // Highest score first, ties broken by id so that pages are stable.
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        // Collapse before paginating, so that a page never holds a near duplicate of a result of an earlier page.
+        let ranked: Vec<(Uuid, f64, Vec<TermScore>, usize)> = if query.collapse_duplicates {
+            self.collapse_duplicates(ranked, query.offset.saturating_add(query.limit)).await?
+        } else {
+            ranked.into_iter().map(|(website_id, score, terms)| (website_id, score, terms, 0)).collect()
+        };
+        let ranked: Vec<(Uuid, f64, Vec<TermScore>, usize)> = ranked.into_iter().skip(query.offset).take(query.limit).collect();
+
+        // Resolve the urls and snippets of the requested page only.
+        let ids: Vec<Uuid> = ranked.iter().map(|(website_id, _, _, _)| *website_id).collect();
+        let websites = self.find_websites(&ids).await?;
+        let contents: HashMap<Uuid, String> = self.store.find_contents(&ids).await?
+            .into_iter()
+            .map(|website_content| (website_content.id, website_content.content))
+            .collect();
+        let results = ranked.into_iter()
+            .filter_map(|(website_id, score, term_scores, duplicates)| {
+                let website = websites.get(&website_id)?;
+                let snippet = contents.get(&website_id)
+                    .map(|content| snippet(content, &self.analyzer.tokenize(content), &terms))
+                    .unwrap_or_default();
+                Some(SearchResult {
+                    website_id,
+                    url: website.url.clone(),
+                    score,
+                    terms: term_scores,
+                    snippet,
+                    duplicates,
+                })
+            })
+            .collect();
+        Ok(results)
+    }
+
+    /// Load the postings of every keyword of the query, keywords that were never indexed have none.
+    /// The keywords every match must contain only get the postings of the websites containing all of them, which
+    /// the store finds without reading the whole posting lists.
+    async fn find_postings(&self, node: &QueryNode) -> Result<(Postings, DocumentFrequencies), Box<dyn std::error::Error>> {
+        let mut keyword_ids: Vec<(String, Uuid)> = Vec::new();
+        for term in node.keywords() {
+            if let Some(keyword) = self.store.find_keyword(&term).await? {
+                keyword_ids.push((term, keyword.id));
+            }
+        }
+        let required = node.required_keywords().unwrap_or_default();
+        let (required, others): (Vec<_>, Vec<_>) = keyword_ids.into_iter()
+            .partition(|(term, _)| required.contains(term));
+
+        let mut postings: Postings = HashMap::new();
+        let mut document_frequencies: DocumentFrequencies = HashMap::new();
+        if !required.is_empty() {
+            let ids: Vec<Uuid> = required.iter().map(|(_, keyword_id)| *keyword_id).collect();
+            let common = self.store.find_common_postings(&ids).await?;
+            for ((term, keyword_id), rows) in required.into_iter().zip(common) {
+                document_frequencies.insert(term.clone(), self.store.document_frequency(keyword_id).await?);
+                postings.insert(term, rows.into_iter().map(|row| (row.website_id, row)).collect());
+            }
+        }
+        for (term, keyword_id) in others {
+            let rows = self.store.find_postings(keyword_id).await?;
+            document_frequencies.insert(term.clone(), rows.len() as i64);
+            postings.insert(term, rows.into_iter().map(|row| (row.website_id, row)).collect());
+        }
+        Ok((postings, document_frequencies))
+    }
+
+    /// Score every website containing a term with the idf stored by the text pool.
+    /// The term frequency is recomputed from the boosted field frequencies, the stored one counts the body only.
+    async fn score_tfidf(&self, terms: &[String], postings: &Postings, field_boosts: FieldBoosts) -> Result<HashMap<Uuid, Vec<TermScore>>, Box<dyn std::error::Error>> {
+        let websites = self.find_websites(&posting_website_ids(terms, postings)).await?;
+        let mut matches: HashMap<Uuid, Vec<TermScore>> = HashMap::new();
+        for term in terms {
+            let term_postings = match postings.get(term) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            let keyword_id = match term_postings.values().next() {
+                Some(row) => row.keyword_id,
+                None => continue,
+            };
+            let website_ids: Vec<Uuid> = term_postings.keys().copied().collect();
+            let rows = self.store.find_scores_in(keyword_id, &website_ids).await?;
+            for row in rows {
+                let score = match (term_postings.get(&row.website_id), websites.get(&row.website_id)) {
+                    (Some(posting), Some(website)) => {
+                        let word_count = website.word_count.max(1) as f64;
+                        field_boosts.weighted_frequency(posting) / word_count * to_f64(&row.idf)
+                    }
+                    _ => to_f64(&row.tfidf),
+                };
+                matches.entry(row.website_id).or_default().push(TermScore {
+                    term: term.clone(),
+                    score,
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Score every website containing a term with BM25, using the website word count as the document length.
+    /// The boosted field frequencies are used as the term frequency.
+    async fn score_bm25(&self, terms: &[String], postings: &Postings, document_frequencies: &DocumentFrequencies, params: Bm25Params, field_boosts: FieldBoosts) -> Result<HashMap<Uuid, Vec<TermScore>>, Box<dyn std::error::Error>> {
+        let corpus_stats = self.store.corpus_stats().await?;
+        let average_document_length = corpus_stats.average_document_length();
+
+        // Fetch the lengths of every website containing a term at once.
+        let websites = self.find_websites(&posting_website_ids(terms, postings)).await?;
+
+        let mut matches: HashMap<Uuid, Vec<TermScore>> = HashMap::new();
+        for term in terms {
+            let rows = match postings.get(term) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            let document_frequency = document_frequencies.get(term).copied().unwrap_or(rows.len() as i64);
+            let idf = params.idf(document_frequency, corpus_stats.document_count);
+            for row in rows.values() {
+                let document_length = match websites.get(&row.website_id) {
+                    Some(website) => website.word_count as f64,
+                    None => continue,
+                };
+                let frequency = field_boosts.weighted_frequency(row);
+                matches.entry(row.website_id).or_default().push(TermScore {
+                    term: term.clone(),
+                    score: params.score(frequency, document_length, average_document_length, idf),
+                });
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Collapse the near duplicates of the ranked websites until `count` results are found.
+    /// The clusters are looked up a batch of websites at a time, so the websites ranked after the page are never read.
+    async fn collapse_duplicates(&self, ranked: Vec<(Uuid, f64, Vec<TermScore>)>, count: usize) -> Result<Vec<(Uuid, f64, Vec<TermScore>, usize)>, Box<dyn std::error::Error>> {
+        let mut clusters: HashMap<Uuid, usize> = HashMap::new();
+        let mut collapsed = Vec::new();
+        let mut ranked = ranked.into_iter();
+        while collapsed.len() < count {
+            // Every website adds a result at most, a batch can fill the page unless it holds near duplicates.
+            let batch: Vec<(Uuid, f64, Vec<TermScore>)> = ranked.by_ref().take((count - collapsed.len()).max(COLLAPSE_BATCH)).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let ids: Vec<Uuid> = batch.iter().map(|(website_id, _, _)| *website_id).collect();
+            let canonical_ids: HashMap<Uuid, Uuid> = self.find_websites(&ids).await?
+                .into_iter()
+                .map(|(website_id, website)| (website_id, website.canonical_id()))
+                .collect();
+            collapse_duplicates(batch, &canonical_ids, &mut clusters, &mut collapsed);
+        }
+        Ok(collapsed)
+    }
+
+    /// Find websites by id, keyed by id.
+    async fn find_websites(&self, ids: &[Uuid]) -> Result<HashMap<Uuid, models::website::Website>, Box<dyn std::error::Error>> {
+        let websites = self.store.find_websites(ids).await?;
+        Ok(websites.into_iter().map(|website| (website.id, website)).collect())
+    }
+}
+
+/// The ids of every website containing one of the terms.
+fn posting_website_ids(terms: &[String], postings: &Postings) -> Vec<Uuid> {
+    let mut ids: Vec<Uuid> = terms.iter()
+        .filter_map(|term| postings.get(term))
+        .flat_map(|rows| rows.keys().copied())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Collapse every near duplicate cluster of the ranked websites to a single result in `collapsed`, counting the
+/// websites collapsed into it. The result takes the rank of the best ranked website of its cluster, and shows the
+/// canonical website of the cluster when it matched, otherwise the best ranked one.
+/// `clusters` maps each canonical website to the position of the result of its cluster, so that the ranked websites
+/// can be collapsed a batch at a time.
+fn collapse_duplicates(ranked: Vec<(Uuid, f64, Vec<TermScore>)>, canonical_ids: &HashMap<Uuid, Uuid>, clusters: &mut HashMap<Uuid, usize>, collapsed: &mut Vec<(Uuid, f64, Vec<TermScore>, usize)>) {
+    for (website_id, score, terms) in ranked {
+        let canonical_id = canonical_ids.get(&website_id).copied().unwrap_or(website_id);
+        match clusters.get(&canonical_id) {
+            Some(position) => {
+                let result = &mut collapsed[*position];
+                result.3 += 1;
+                if website_id == canonical_id {
+                    (result.0, result.1, result.2) = (website_id, score, terms);
+                }
+            }
+            None => {
+                clusters.insert(canonical_id, collapsed.len());
+                collapsed.push((website_id, score, terms, 0));
+            }
+        }
+    }
+}
+
+/// Convert a stored numeric score to a float.
+fn to_f64(value: &BigDecimal) -> f64 {
+    value.to_string().parse().unwrap_or(0.0)
+}
+
+/// Find the websites matching the query.
+fn evaluate(node: &QueryNode, postings: &Postings) -> HashSet<Uuid> {
+    match node {
+        QueryNode::Term(keyword) => postings.get(keyword)
+            .map(|rows| rows.keys().copied().collect())
+            .unwrap_or_default(),
+        QueryNode::Phrase(phrase) => find_phrase(phrase, postings),
+        QueryNode::Or(children) => children.iter()
+            .flat_map(|child| evaluate(child, postings))
+            .collect(),
+        QueryNode::And(children) => {
+            let (negated, required): (Vec<&QueryNode>, Vec<&QueryNode>) = children.iter()
+                .partition(|child| matches!(child, QueryNode::Not(_)));
+            let mut required = required.into_iter().map(|child| evaluate(child, postings));
+            // Negations only exclude from the websites matched by the other children.
+            let mut websites = match required.next() {
+                Some(websites) => websites,
+                None => return HashSet::new(),
+            };
+            for other in required {
+                websites.retain(|website_id| other.contains(website_id));
+            }
+            for child in negated {
+                let excluded = evaluate(child, postings);
+                websites.retain(|website_id| !excluded.contains(website_id));
+            }
+            websites
+        }
+        QueryNode::Not(child) => evaluate(child, postings),
+    }
+}
+
+/// Find the websites containing the keywords of the phrase at consecutive positions.
+fn find_phrase(phrase: &[String], postings: &Postings) -> HashSet<Uuid> {
+    let mut phrase_postings = Vec::new();
+    for keyword in phrase {
+        match postings.get(keyword) {
+            Some(rows) => phrase_postings.push(rows),
+            None => return HashSet::new(),
+        }
+    }
+    let (first, rest) = match phrase_postings.split_first() {
+        Some(phrase_postings) => phrase_postings,
+        None => return HashSet::new(),
+    };
+    first.iter()
+        .filter(|(website_id, row)| {
+            let mut term_positions: Vec<&[i32]> = vec![row.positions.as_slice()];
+            for rows in rest {
+                match rows.get(*website_id) {
+                    Some(row) => term_positions.push(row.positions.as_slice()),
+                    None => return false,
+                }
+            }
+            contains_phrase(&term_positions)
+        })
+        .map(|(website_id, _)| *website_id)
+        .collect()
+}
+
+/// Check whether the terms occur one after another, given the sorted positions of each term.
+fn contains_phrase(term_positions: &[&[i32]]) -> bool {
+    let (first, rest) = match term_positions.split_first() {
+        Some(term_positions) => term_positions,
+        None => return false,
+    };
+    first.iter().any(|start| {
+        rest.iter()
+            .enumerate()
+            .all(|(offset, positions)| positions.binary_search(&(start + offset as i32 + 1)).is_ok())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::store::fixtures::{self, near_duplicate_words};
+    use crate::store::{IndexPage, MemoryIndexStore};
+
+    // Index the content the way the text pool does
+    fn page(analyzer: &Analyzer, url: &str, content: &str) -> IndexPage {
+        let tokens = analyzer.tokenize(content);
+        let keywords: Vec<&str> = tokens.iter().map(|token| token.keyword.as_str()).collect();
+        let mut page = IndexPage {
+            content: content.to_string(),
+            ..fixtures::page(url, &keywords)
+        };
+        for token in tokens.iter() {
+            if let Some(occurrences) = page.keywords.get_mut(&token.keyword) {
+                occurrences.surface.get_or_insert_with(|| token.surface(content));
+            }
+        }
+        page
+    }
+
+    #[tokio::test]
+    async fn can_search_memory_store() {
+        let store = Arc::new(MemoryIndexStore::new());
+        let analyzer = Analyzer::new(PathBuf::from("assets/lemmatizedMap.json")).unwrap();
+        store.index_page(page(&analyzer, "https://rust.example/", "Rust makes fast and safe programs")).await.unwrap();
+        store.index_page(page(&analyzer, "https://python.example/", "Python makes readable programs")).await.unwrap();
+        let search_service = SearchService::new(store, analyzer);
+
+        let query = SearchQuery {
+            text: "safe programs".to_string(),
+            limit: 10,
+            offset: 0,
+            ranking: Ranking::TfIdf,
+            field_boosts: None,
+            collapse_duplicates: false,
+        };
+        let results = search_service.search(&query).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].url, "https://rust.example/");
+        assert!(results[0].snippet.contains("<mark>safe</mark>"));
+
+        let query = SearchQuery {
+            text: "programs -rust".to_string(),
+            ranking: Ranking::Bm25(Bm25Params::default()),
+            ..query
+        };
+        let results = search_service.search(&query).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].url, "https://python.example/");
+    }
+
+    // Suggestions show the words of the pages rather than their keywords, ranked by their latest frequencies
+    #[tokio::test]
+    async fn can_suggest_surface_forms() {
+        let store = Arc::new(MemoryIndexStore::new());
+        let analyzer = Analyzer::new(PathBuf::from("assets/lemmatizedMap.json")).unwrap();
+        store.index_page(page(&analyzer, "https://a.example/", "carts")).await.unwrap();
+        store.index_page(page(&analyzer, "https://b.example/", "cards")).await.unwrap();
+        let search_service = SearchService::new(store.clone(), analyzer);
+        search_service.refresh_vocabulary().await.unwrap();
+        assert_eq!(search_service.suggest("carx").await, Some("cards".to_string()));
+
+        store.index_page(page(&search_service.analyzer, "https://c.example/", "carts")).await.unwrap();
+        store.index_page(page(&search_service.analyzer, "https://d.example/", "carts")).await.unwrap();
+        search_service.refresh_vocabulary().await.unwrap();
+        // The keywords were created moments ago, the refresh loads them again.
+        assert_eq!(search_service.suggest("carx").await, Some("carts".to_string()));
+        search_service.reload_vocabulary().await.unwrap();
+        assert_eq!(search_service.suggest("carx").await, Some("carts".to_string()));
+    }
+
+    // Completions are the words of the pages rather than their keywords
+    #[tokio::test]
+    async fn can_complete_surface_forms() {
+        let store = Arc::new(MemoryIndexStore::new());
+        let analyzer = Analyzer::new(PathBuf::from("assets/lemmatizedMap.json")).unwrap();
+        store.index_page(page(&analyzer, "https://a.example/", "Running programs")).await.unwrap();
+        let search_service = SearchService::new(store, analyzer);
+        search_service.refresh_vocabulary().await.unwrap();
+        let completions: Vec<String> = search_service.complete("fast progr", 5).await.into_iter().map(|completion| completion.text).collect();
+        assert_eq!(completions, vec!["fast programs".to_string()]);
+    }
+
+    // Mirrors of a page collapse into the result of the page, and into the page itself once it matches
+    #[tokio::test]
+    async fn can_collapse_duplicates() {
+        let store = Arc::new(MemoryIndexStore::new());
+        let analyzer = Analyzer::new(PathBuf::from("assets/lemmatizedMap.json")).unwrap();
+        let (content, mirrored) = near_duplicate_words("rust");
+        store.index_page(page(&analyzer, "https://a.example/", &content.join(" "))).await.unwrap();
+        store.index_page(page(&analyzer, "https://b.example/", &mirrored.join(" "))).await.unwrap();
+        store.index_page(page(&analyzer, "https://c.example/", "word0 stands alone")).await.unwrap();
+        let search_service = SearchService::new(store, analyzer);
+
+        let query = SearchQuery {
+            text: "word0".to_string(),
+            limit: 10,
+            offset: 0,
+            ranking: Ranking::Bm25(Bm25Params::default()),
+            field_boosts: None,
+            collapse_duplicates: false,
+        };
+        assert_eq!(search_service.search(&query).await.unwrap().len(), 3);
+        let query = SearchQuery {
+            collapse_duplicates: true,
+            ..query
+        };
+        let results = search_service.search(&query).await.unwrap();
+        assert_eq!(results.len(), 2);
+        let mirror = results.iter().find(|result| result.url != "https://c.example/").unwrap();
+        assert_eq!(mirror.url, "https://a.example/");
+        assert_eq!(mirror.duplicates, 1);
+
+        let query = SearchQuery {
+            text: "rust".to_string(),
+            ..query
+        };
+        let results = search_service.search(&query).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].url, "https://b.example/");
+    }
+
+    // A later batch collapses into the results of the earlier ones
+    #[test]
+    fn can_collapse_in_batches() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let canonical_ids = HashMap::from([(a, a), (b, a), (c, c)]);
+        let mut clusters = HashMap::new();
+        let mut collapsed = Vec::new();
+        collapse_duplicates(vec![(b, 3.0, Vec::new()), (c, 2.0, Vec::new())], &canonical_ids, &mut clusters, &mut collapsed);
+        collapse_duplicates(vec![(a, 1.0, Vec::new())], &canonical_ids, &mut clusters, &mut collapsed);
+        let results: Vec<(Uuid, usize)> = collapsed.into_iter().map(|(website_id, _, _, duplicates)| (website_id, duplicates)).collect();
+        assert_eq!(results, vec![(a, 1), (c, 0)]);
+    }
+
+    // Terms must be adjacent and in order
+    #[test]
+    fn can_match_phrase() {
+        let new: &[i32] = &[0, 7];
+        let york: &[i32] = &[3, 8];
+        assert!(contains_phrase(&[new, york]));
+        assert!(!contains_phrase(&[york, new]));
+
+        let far: &[i32] = &[5];
+        assert!(!contains_phrase(&[new, far]));
+    }
+
+    // A single term is a phrase of its own
+    #[test]
+    fn can_match_single_term_phrase() {
+        let positions: &[i32] = &[2];
+        assert!(contains_phrase(&[positions]));
+        assert!(!contains_phrase(&[]));
+    }
+}
@@ -1,32 +1,384 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use uuid::Uuid;
+use crate::frontier::{Frontier, Site};
+use crate::models::frontier::{FrontierState, FrontierUrl, StopReason};
+use crate::services::{CrawlJob, HostScheduler, PolitenessPolicy};
+
+// Largest number of urls read from the sites file that are queued at once.
+const ENQUEUE_BATCH_SIZE: usize = 1_000;
+// Number of times a url is leased before it is marked as failed.
+const MAX_CRAWL_ATTEMPTS: i32 = 3;
+// How long to wait before leasing again when there was nothing to do.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Number of urls leased per crawler, so there are urls of other hosts to crawl while a host waits out its delay.
+const LEASED_PER_CRAWLER: usize = 10;
+// How long a lease lasts unless it is renewed, the urls of a pipeline that stopped are queued again after it.
+const LEASE_TIMEOUT: Duration = Duration::from_secs(300);
+// How often the leases of the urls held are renewed.
+const RENEW_INTERVAL: Duration = Duration::from_secs(60);
+// How long resolving a host may take.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the address and Crawl-delay of a host are remembered.
+const HOST_TTL: Duration = Duration::from_secs(3600);
+
+/// The result of crawling a url leased from the frontier.
+#[derive(Debug)]
+pub struct CrawlOutcome {
+    // `id` is the id of the url in the frontier.
+    pub id: Uuid,
+    // `error` is why the crawl failed, `None` when it succeeded.
+    pub error: Option<String>,
+    // `stop_reason` is the budget that stopped the crawl before it reached every page, `None` when none did.
+    pub stop_reason: Option<StopReason>,
+    // `crawl_delay` is the Crawl-delay the robots.txt read by the crawl asks for, `None` when it asks for none.
+    pub crawl_delay: Option<Duration>,
+}
+
+/// Resolver finds the address of the hosts to crawl, so the crawls of hosts sharing an address can be limited.
+#[async_trait]
+pub trait Resolver: Send + Sync + 'static {
+    /// Resolve a host, `None` when it can't be resolved.
+    async fn resolve(&self, host: &str, port: u16) -> Option<IpAddr>;
+}
+
+/// DnsResolver resolves hosts with the resolver of the system.
+pub struct DnsResolver;
+
+#[async_trait]
+impl Resolver for DnsResolver {
+    async fn resolve(&self, host: &str, port: u16) -> Option<IpAddr> {
+        match tokio::time::timeout(LOOKUP_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+            Ok(Ok(mut addresses)) => addresses.next().map(|address| address.ip()),
+            _ => None,
+        }
+    }
+}
+
 /// SitePool is a pool of sites that are to be crawled.
-pub struct SitePool {
-    // `site_receiver` is a mpsc channel receiver that receives a URL to crawl.
-    site_receiver: crossbeam_channel::Receiver<url::Url>,
-    // `crawler_sender` is a mpsc channel sender that sends a URL to the crawler.
-    crawler_sender: crossbeam_channel::Sender<url::Url>,
+/// The sites are queued in the frontier and leased in batches, the highest-ranked first, then handed to the crawlers per
+/// host and per IP address as the politeness policy allows, so a restart resumes the crawl and no host is crawled too hard.
+/// Hosts are resolved in the background, their urls wait aside meanwhile. The Crawl-delay of a host is learned from the
+/// robots.txt its crawls read, so the robots.txt is only fetched by the crawler.
+pub struct SitePool<F: Frontier, R: Resolver> {
+    // `site_receiver` is a mpsc channel receiver that receives a URL to queue and the rank of its domain.
+    site_receiver: crossbeam_channel::Receiver<Site>,
+    // `crawler_sender` is a mpsc channel sender that sends a leased URL and its delay to the crawler.
+    crawler_sender: crossbeam_channel::Sender<CrawlJob>,
+    // `outcome_receiver` is a mpsc channel receiver that receives the outcome of every crawl.
+    outcome_receiver: crossbeam_channel::Receiver<CrawlOutcome>,
+    // `frontier` holds the queued urls and their state.
+    frontier: Arc<F>,
+    // `crawler_count` is the number of crawlers, the largest number of urls crawled at once.
+    crawler_count: usize,
+    // `scheduler` holds the leased urls per host until politeness allows them to be crawled.
+    scheduler: HostScheduler,
+    // `resolver` resolves the hosts the scheduler does not know.
+    resolver: Arc<R>,
+    // `lookup_sender` and `lookup_receiver` carry the address of every host resolved in the background.
+    lookup_sender: crossbeam_channel::Sender<(String, Option<IpAddr>)>,
+    lookup_receiver: crossbeam_channel::Receiver<(String, Option<IpAddr>)>,
+    // `resolving` holds the leased urls of the hosts being resolved.
+    resolving: HashMap<String, Vec<FrontierUrl>>,
+    // `known_hosts` remembers what was found out about the hosts, for `HOST_TTL`.
+    known_hosts: HashMap<String, KnownHost>,
 }
 
-impl SitePool {
+/// What was found out about a host.
+struct KnownHost {
+    ip: Option<IpAddr>,
+    crawl_delay: Option<Duration>,
+    resolved_at: Instant,
+}
+
+impl<F: Frontier, R: Resolver> SitePool<F, R> {
     /// Create a new SitePool instance.
-    pub fn new(site_receiver: crossbeam_channel::Receiver<url::Url>, crawler_sender: crossbeam_channel::Sender<url::Url>) -> Self {
+    pub fn new(site_receiver: crossbeam_channel::Receiver<Site>, crawler_sender: crossbeam_channel::Sender<CrawlJob>, outcome_receiver: crossbeam_channel::Receiver<CrawlOutcome>, frontier: Arc<F>, resolver: Arc<R>, crawler_count: usize, policy: PolitenessPolicy) -> Self {
+        let (lookup_sender, lookup_receiver) = crossbeam_channel::unbounded();
         Self {
             site_receiver,
             crawler_sender,
+            outcome_receiver,
+            frontier,
+            crawler_count,
+            scheduler: HostScheduler::new(policy),
+            resolver,
+            lookup_sender,
+            lookup_receiver,
+            resolving: HashMap::new(),
+            known_hosts: HashMap::new(),
         }
     }
 
     /// Start the site pool in background.
-    pub async fn start(self) {
-        // Loop to receive URLs from the site receiver.
-        while let Ok(url) = self.site_receiver.recv() {
-            
+    pub async fn start(mut self) {
+        let mut renewed_at = Instant::now();
+        self.renew_leases().await;
+        loop {
+            if renewed_at.elapsed() >= RENEW_INTERVAL {
+                self.renew_leases().await;
+                self.known_hosts.retain(|_, known_host| known_host.resolved_at.elapsed() < HOST_TTL);
+                renewed_at = Instant::now();
+            }
+            let queued = self.enqueue_sites().await;
+            let mut finished = 0;
+            while let Ok(outcome) = self.outcome_receiver.try_recv() {
+                if let Some(crawl_delay) = outcome.crawl_delay {
+                    self.learn_crawl_delay(outcome.id, crawl_delay);
+                }
+                self.scheduler.finish(outcome.id);
+                self.record_outcome(outcome).await;
+                finished += 1;
+            }
+            let resolved = self.add_resolved_hosts();
+            let leased = self.lease().await;
+            let dispatched = self.dispatch();
+            if queued == 0 && finished == 0 && resolved == 0 && leased == 0 && dispatched == 0 {
+                // Wake up when the next host is ready, or poll for new urls and outcomes.
+                let wait = self.scheduler.next_ready_at()
+                    .map(|ready_at| ready_at.saturating_duration_since(Instant::now()).min(POLL_INTERVAL))
+                    .unwrap_or(POLL_INTERVAL);
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    /// Renew the leases of the urls held by the scheduler, then queue again the urls whose lease expired, which a
+    /// pipeline that stopped was crawling.
+    async fn renew_leases(&self) {
+        let mut ids = self.scheduler.leased_ids();
+        ids.extend(self.resolving.values().flatten().map(|frontier_url| frontier_url.id));
+        if let Err(e) = self.frontier.renew(&ids).await {
+            eprintln!("Error renewing leases: {:?}", e);
+        }
+        match self.frontier.requeue_expired(LEASE_TIMEOUT).await {
+            Ok(0) => (),
+            Ok(count) => println!("Requeued {} urls whose lease expired.", count),
+            Err(e) => eprintln!("Error requeueing urls: {:?}", e),
+        }
+    }
+
+    /// Queue the urls received from the file reader, returning how many were received.
+    async fn enqueue_sites(&self) -> usize {
+        let mut sites = Vec::new();
+        while sites.len() < ENQUEUE_BATCH_SIZE {
+            match self.site_receiver.try_recv() {
+                Ok(site) => sites.push(site),
+                // Nothing to queue for now, or the file reader is done.
+                Err(_) => break,
+            }
+        }
+        if !sites.is_empty() {
+            if let Err(e) = self.frontier.enqueue(&sites).await {
+                eprintln!("Error queueing URLs: {:?}", e);
+            }
+        }
+        sites.len()
+    }
+
+    /// Lease urls until the scheduler holds `LEASED_PER_CRAWLER` per crawler, and queue them per host.
+    /// The urls of hosts not known yet wait for their host to be resolved in the background. Returns how many urls
+    /// were leased.
+    async fn lease(&mut self) -> usize {
+        let resolving = self.resolving.values().map(Vec::len).sum::<usize>();
+        let limit = (self.crawler_count * LEASED_PER_CRAWLER).saturating_sub(self.scheduler.queued() + resolving);
+        if limit == 0 {
+            return 0;
+        }
+        let frontier_urls = match self.frontier.lease(limit).await {
+            Ok(frontier_urls) => frontier_urls,
+            Err(e) => {
+                eprintln!("Error leasing URLs: {:?}", e);
+                return 0;
+            }
+        };
+        let leased = frontier_urls.len();
+        let now = Instant::now();
+        for frontier_url in frontier_urls {
+            let url = match url::Url::parse(&frontier_url.url) {
+                Ok(url) => url,
+                Err(e) => {
+                    self.reject(frontier_url.id, &format!("Invalid URL: {:?}", e)).await;
+                    continue;
+                }
+            };
+            let host = match url.host_str() {
+                Some(host) => host.to_lowercase(),
+                None => {
+                    self.reject(frontier_url.id, "URL has no host").await;
+                    continue;
+                }
+            };
+            if !self.scheduler.knows_host(&host) {
+                if let Some(urls) = self.resolving.get_mut(&host) {
+                    urls.push(frontier_url);
+                    continue;
+                }
+                match self.known_hosts.get(&host).filter(|known_host| known_host.resolved_at.elapsed() < HOST_TTL) {
+                    Some(known_host) => self.scheduler.add_host(&host, known_host.ip, known_host.crawl_delay, now),
+                    None => {
+                        self.resolve(host.clone(), url.port_or_known_default().unwrap_or(80));
+                        self.resolving.insert(host, vec![frontier_url]);
+                        continue;
+                    }
+                }
+            }
+            self.scheduler.push(&host, frontier_url, now);
+        }
+        leased
+    }
+
+    /// Resolve a host in the background, its address is picked up by `add_resolved_hosts`.
+    fn resolve(&self, host: String, port: u16) {
+        let resolver = self.resolver.clone();
+        let lookup_sender = self.lookup_sender.clone();
+        tokio::spawn(async move {
+            let ip = resolver.resolve(&host, port).await;
+            // The site pool holds the receiver, it is only gone once the site pool is.
+            let _ = lookup_sender.send((host, ip));
+        });
+    }
+
+    /// Add the hosts resolved since the last call to the scheduler with the urls waiting for them, returning how many
+    /// urls were queued.
+    fn add_resolved_hosts(&mut self) -> usize {
+        let now = Instant::now();
+        let mut queued = 0;
+        while let Ok((host, ip)) = self.lookup_receiver.try_recv() {
+            let crawl_delay = self.known_hosts.get(&host).and_then(|known_host| known_host.crawl_delay);
+            self.known_hosts.insert(host.clone(), KnownHost {
+                ip,
+                crawl_delay,
+                resolved_at: now,
+            });
+            self.scheduler.add_host(&host, ip, crawl_delay, now);
+            for frontier_url in self.resolving.remove(&host).unwrap_or_default() {
+                self.scheduler.push(&host, frontier_url, now);
+                queued += 1;
+            }
+        }
+        queued
+    }
+
+    /// Remember the Crawl-delay the crawl of a url found in the robots.txt of its host.
+    fn learn_crawl_delay(&mut self, id: Uuid, crawl_delay: Duration) {
+        let host = match self.scheduler.running_host(id) {
+            Some(host) => host.to_string(),
+            None => return,
+        };
+        self.scheduler.set_crawl_delay(&host, crawl_delay);
+        if let Some(known_host) = self.known_hosts.get_mut(&host) {
+            known_host.crawl_delay = Some(crawl_delay);
+        }
+    }
+
+    /// Send the urls politeness allows to the crawlers, as long as one is free, returning how many were sent.
+    fn dispatch(&mut self) -> usize {
+        let mut sent = 0;
+        while self.scheduler.running() < self.crawler_count {
+            let crawl_job = match self.scheduler.pop(Instant::now()) {
+                Some(crawl_job) => crawl_job,
+                None => break,
+            };
+            let id = crawl_job.frontier_url.id;
             // Send the URL to the crawler.
-            match self.crawler_sender.send(url){
-                Ok(_) => {}
+            match self.crawler_sender.send(crawl_job) {
+                Ok(_) => sent += 1,
                 Err(e) => {
                     eprintln!("Error sending URL to crawler: {:?}", e);
+                    self.scheduler.finish(id);
+                    break;
+                }
+            }
+        }
+        sent
+    }
+
+    /// Mark a url that can never be crawled as failed, without retrying it.
+    async fn reject(&self, id: Uuid, error: &str) {
+        eprintln!("Giving up on URL: {}", error);
+        if let Err(e) = self.frontier.fail(id, error, 0).await {
+            eprintln!("Error recording crawl outcome: {:?}", e);
+        }
+    }
+
+    /// Record the outcome of a crawl in the frontier.
+    async fn record_outcome(&self, outcome: CrawlOutcome) {
+        let result = match outcome.error {
+            None => self.frontier.complete(outcome.id, outcome.stop_reason).await,
+            Some(error) => match self.frontier.fail(outcome.id, &error, MAX_CRAWL_ATTEMPTS).await {
+                Ok(FrontierState::Failed) => {
+                    eprintln!("Giving up on URL after {} attempts: {}", MAX_CRAWL_ATTEMPTS, error);
+                    Ok(())
                 }
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("Error recording crawl outcome: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use crossbeam_channel::unbounded;
+    use crate::frontier::MemoryFrontier;
+    use crate::models::frontier::CrawlScope;
+
+    /// Resolves every host to the same address, b.example slower than the others.
+    struct StubResolver;
+
+    #[async_trait]
+    impl Resolver for StubResolver {
+        async fn resolve(&self, host: &str, _port: u16) -> Option<IpAddr> {
+            if host == "b.example" {
+                tokio::time::sleep(Duration::from_millis(200)).await;
             }
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+        }
+    }
+
+    // A url that fails is leased again with the Crawl-delay its crawl found, only as many urls as there are crawlers
+    // or the address allows are crawled at once, the highest-ranked url goes first and no url waits for the host of
+    // another to be resolved
+    #[tokio::test(flavor = "multi_thread")]
+    async fn can_lease_to_crawlers() {
+        let frontier = Arc::new(MemoryFrontier::new());
+        let (site_sender, site_receiver) = unbounded();
+        let (crawler_sender, crawler_receiver) = unbounded();
+        let (outcome_sender, outcome_receiver) = unbounded();
+        let policy = PolitenessPolicy {
+            min_delay: Duration::from_millis(10),
+            max_crawl_delay: Duration::from_secs(1),
+            max_per_host: 1,
+            max_per_ip: 1,
+        };
+        let site_pool = SitePool::new(site_receiver, crawler_sender, outcome_receiver, frontier, Arc::new(StubResolver), 2, policy);
+        for (url, rank) in [("https://b.example/", None), ("https://a.example/", Some(1))] {
+            site_sender.send(Site { url: url::Url::parse(url).unwrap(), rank, scope: CrawlScope::Site, lastmod: None, priority: None }).unwrap();
         }
+        tokio::spawn(site_pool.start());
+
+        let timeout = Duration::from_secs(5);
+        let first = crawler_receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(first.frontier_url.url, "https://a.example/");
+        assert_eq!(first.delay, Duration::from_millis(10));
+        assert!(crawler_receiver.recv_timeout(POLL_INTERVAL * 2).is_err());
+        outcome_sender.send(CrawlOutcome { id: first.frontier_url.id, error: Some("timed out".to_string()), stop_reason: None, crawl_delay: Some(Duration::from_millis(500)) }).unwrap();
+        // The failed url is still the highest-ranked.
+        let retried = crawler_receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(retried.frontier_url.url, "https://a.example/");
+        assert_eq!(retried.frontier_url.attempts, 2);
+        assert_eq!(retried.delay, Duration::from_millis(500));
+        outcome_sender.send(CrawlOutcome { id: retried.frontier_url.id, error: None, stop_reason: Some(StopReason::MaxPages), crawl_delay: None }).unwrap();
+        let second = crawler_receiver.recv_timeout(timeout).unwrap();
+        assert_eq!(second.frontier_url.url, "https://b.example/");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,332 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::time::Duration;
+use flate2::read::GzDecoder;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use time::format_description::well_known::Rfc3339;
+use time::{Date, Month, OffsetDateTime};
+use crate::services::robots;
+
+// Largest number of sitemap files read for a site, sitemap indexes included.
+const MAX_SITEMAPS: usize = 50;
+// Largest number of urls read from the sitemaps of a site, the most a single sitemap may list.
+const MAX_SITEMAP_URLS: usize = 50_000;
+// Largest size of a sitemap file once decompressed, the most a single sitemap may weigh.
+const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+// Largest size of a robots.txt, the rest of a larger one is not read for Sitemap lines.
+const MAX_ROBOTS_BYTES: u64 = 500 * 1024;
+// How long fetching a robots.txt or a sitemap may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+// The first bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A url listed in a sitemap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapUrl {
+    pub url: url::Url,
+    // `lastmod` is when the page was last modified, according to the site.
+    pub lastmod: Option<OffsetDateTime>,
+    // `priority` is how important the page is relative to the other pages of the site, from 0 to 1.
+    pub priority: Option<f32>,
+}
+
+/// What a sitemap file lists.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    // `Index` is a sitemap index listing other sitemaps.
+    Index(Vec<url::Url>),
+    // `UrlSet` is a sitemap listing pages.
+    UrlSet(Vec<SitemapUrl>),
+}
+
+/// SitemapReader finds the sitemaps of a site and reads the urls they list.
+pub struct SitemapReader {
+    http: reqwest::Client,
+}
+
+impl Default for SitemapReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SitemapReader {
+    /// Create a new SitemapReader instance.
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder().timeout(FETCH_TIMEOUT).build().unwrap_or_default(),
+        }
+    }
+
+    /// Read the urls the sitemaps of a site list, waiting `delay` before every request as the crawl of the site does.
+    /// The sitemaps are the ones its robots.txt lists, or `/sitemap.xml` when it lists none. Sitemap indexes are
+    /// followed, and only the urls on the host of the sitemap listing them are kept, as the sitemap protocol requires.
+    pub async fn read_site(&self, site: &url::Url, delay: Duration) -> Vec<SitemapUrl> {
+        let origin = site.origin().ascii_serialization();
+        tokio::time::sleep(delay).await;
+        let mut queue = match self.fetch(&format!("{}/robots.txt", origin), MAX_ROBOTS_BYTES).await {
+            Ok(robots_txt) => robots::sitemaps(&String::from_utf8_lossy(&robots_txt)),
+            Err(_) => Vec::new(),
+        };
+        if queue.is_empty() {
+            if let Ok(sitemap_url) = url::Url::parse(&format!("{}/sitemap.xml", origin)) {
+                queue.push(sitemap_url);
+            }
+        }
+        let mut seen: HashSet<url::Url> = queue.iter().cloned().collect();
+        let mut urls = Vec::new();
+        let mut read = 0;
+        while let Some(sitemap_url) = queue.pop() {
+            if read == MAX_SITEMAPS || urls.len() >= MAX_SITEMAP_URLS {
+                break;
+            }
+            read += 1;
+            tokio::time::sleep(delay).await;
+            let sitemap = match self.fetch(sitemap_url.as_str(), MAX_SITEMAP_BYTES).await.and_then(|bytes| parse_sitemap(&decompress(bytes)?)) {
+                Ok(sitemap) => sitemap,
+                Err(e) => {
+                    eprintln!("Error reading sitemap {}: {:?}", sitemap_url, e);
+                    continue;
+                }
+            };
+            match sitemap {
+                Sitemap::Index(sitemap_urls) => {
+                    for sitemap_url in sitemap_urls {
+                        if seen.insert(sitemap_url.clone()) {
+                            queue.push(sitemap_url);
+                        }
+                    }
+                }
+                Sitemap::UrlSet(sitemap_urls) => {
+                    let remaining = MAX_SITEMAP_URLS - urls.len();
+                    urls.extend(sitemap_urls.into_iter()
+                        .filter(|listed| listed.url.host_str() == sitemap_url.host_str())
+                        .take(remaining));
+                }
+            }
+        }
+        urls
+    }
+
+    /// Fetch a file, failing on an error status or when it is larger than `max_bytes`.
+    /// The body is read chunk by chunk, so a huge file is given up on before it is held in memory.
+    async fn fetch(&self, url: &str, max_bytes: u64) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut response = self.http.get(url).send().await.map_err(|e| format!("Error fetching {}: {:?}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("Error fetching {}: {}", url, response.status()).into());
+        }
+        if response.content_length().is_some_and(|length| length > max_bytes) {
+            return Err(format!("Error fetching {}: larger than {} bytes", url, max_bytes).into());
+        }
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| format!("Error reading {}: {:?}", url, e))? {
+            if (bytes.len() + chunk.len()) as u64 > max_bytes {
+                return Err(format!("Error fetching {}: larger than {} bytes", url, max_bytes).into());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decompress a gzipped sitemap, a sitemap that is not gzipped is returned as it is.
+/// The gzip magic bytes are checked rather than the file name, as servers serve either under both.
+pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if !bytes.starts_with(&GZIP_MAGIC) {
+        return Ok(bytes);
+    }
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes.as_slice()).take(MAX_SITEMAP_BYTES).read_to_end(&mut decompressed)
+        .map_err(|e| format!("Error decompressing sitemap: {:?}", e))?;
+    Ok(decompressed)
+}
+
+/// Parse a sitemap index or urlset.
+/// Entries without a valid `<loc>` are left out, as are a `<lastmod>` or `<priority>` that can't be parsed. Only the
+/// elements where the sitemap protocol puts them are read, so the `<image:loc>` of an image extension nested in a `<url>`
+/// is not taken for its `<loc>`.
+pub fn parse_sitemap(xml: &[u8]) -> Result<Sitemap, Box<dyn std::error::Error>> {
+    let xml = String::from_utf8_lossy(xml);
+    let mut reader = Reader::from_str(&xml);
+    reader.trim_text(true);
+    let mut is_index = None;
+    let mut sitemap_urls = Vec::new();
+    let mut urls = Vec::new();
+    // `depth` is how deep the element read is nested, the root element being 1, `tag` the child of the entry whose text
+    // is read, `entry` the `<sitemap>` or `<url>` entry being read.
+    let mut depth = 0;
+    let mut tag: Option<Vec<u8>> = None;
+    let mut entry: Option<(Option<url::Url>, Option<OffsetDateTime>, Option<f32>)> = None;
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Error parsing sitemap: {:?}", e))?;
+        let text = match event {
+            Event::Start(start) => {
+                depth += 1;
+                let name = start.local_name().as_ref().to_vec();
+                match (depth, name.as_slice()) {
+                    (1, b"sitemapindex") => is_index = Some(true),
+                    (1, b"urlset") => is_index = Some(false),
+                    (2, b"sitemap" | b"url") => entry = Some((None, None, None)),
+                    (3, _) => tag = Some(name),
+                    _ => (),
+                }
+                continue;
+            }
+            Event::End(_) => {
+                match depth {
+                    2 => {
+                        if let Some((Some(url), lastmod, priority)) = entry.take() {
+                            match is_index {
+                                Some(true) => sitemap_urls.push(url),
+                                _ => urls.push(SitemapUrl { url, lastmod, priority }),
+                            }
+                        }
+                    }
+                    3 => tag = None,
+                    _ => (),
+                }
+                depth -= 1;
+                continue;
+            }
+            Event::Text(text) => text.unescape().map_err(|e| format!("Error parsing sitemap: {:?}", e))?.trim().to_string(),
+            Event::CData(cdata) => String::from_utf8_lossy(&cdata.into_inner()).trim().to_string(),
+            Event::Eof => break,
+            _ => continue,
+        };
+        if depth != 3 {
+            continue;
+        }
+        let (loc, lastmod, priority) = match entry.as_mut() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        match tag.as_deref() {
+            Some(b"loc") => *loc = url::Url::parse(&text).ok(),
+            Some(b"lastmod") => *lastmod = parse_lastmod(&text),
+            Some(b"priority") => *priority = text.parse::<f32>().ok().filter(|priority| (0.0..=1.0).contains(priority)),
+            _ => (),
+        }
+    }
+    match is_index {
+        Some(true) => Ok(Sitemap::Index(sitemap_urls)),
+        Some(false) => Ok(Sitemap::UrlSet(urls)),
+        None => Err("Error parsing sitemap: neither a sitemap index nor a urlset".into()),
+    }
+}
+
+/// Parse a W3C datetime, either a full date and time with a time zone or a date alone, taken as midnight UTC.
+pub fn parse_lastmod(lastmod: &str) -> Option<OffsetDateTime> {
+    if let Ok(datetime) = OffsetDateTime::parse(lastmod, &Rfc3339) {
+        return Some(datetime);
+    }
+    let mut parts = lastmod.splitn(3, '-');
+    let year = parts.next()?.parse::<i32>().ok()?;
+    let month = parts.next()?.parse::<u8>().ok()?;
+    let day = parts.next()?.parse::<u8>().ok()?;
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    Some(date.midnight().assume_utc())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    #[test]
+    fn can_parse_sitemap_index() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <sitemap><loc>https://a.example/pages.xml</loc><lastmod>2024-04-01</lastmod></sitemap>
+                <sitemap><loc>https://a.example/news.xml.gz</loc></sitemap>
+                <sitemap><loc>not a url</loc></sitemap>
+            </sitemapindex>"#;
+        let sitemap = parse_sitemap(xml.as_bytes()).unwrap();
+        assert_eq!(sitemap, Sitemap::Index(vec![
+            url::Url::parse("https://a.example/pages.xml").unwrap(),
+            url::Url::parse("https://a.example/news.xml.gz").unwrap(),
+        ]));
+    }
+
+    // A gzipped urlset is read with the last modification and priority of its urls
+    #[test]
+    fn can_parse_gzipped_urlset() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+                <url>
+                    <loc>https://a.example/?a=1&amp;b=2</loc>
+                    <lastmod>2024-04-01T12:30:00+02:00</lastmod>
+                    <priority>0.8</priority>
+                </url>
+                <url><loc><![CDATA[https://a.example/about]]></loc><priority>2</priority></url>
+            </urlset>"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+        let sitemap = parse_sitemap(&decompress(encoder.finish().unwrap()).unwrap()).unwrap();
+        let lastmod = OffsetDateTime::parse("2024-04-01T10:30:00Z", &Rfc3339).unwrap();
+        assert_eq!(sitemap, Sitemap::UrlSet(vec![
+            SitemapUrl { url: url::Url::parse("https://a.example/?a=1&b=2").unwrap(), lastmod: Some(lastmod), priority: Some(0.8) },
+            SitemapUrl { url: url::Url::parse("https://a.example/about").unwrap(), lastmod: None, priority: None },
+        ]));
+        assert!(parse_sitemap(b"<html></html>").is_err());
+    }
+
+    // A file larger than the limit is given up on, whether its size is announced or not
+    #[tokio::test]
+    async fn can_limit_fetched_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let body = "x".repeat(2048);
+                // The chunked file does not announce its size.
+                let response = match request.starts_with(b"GET /chunked") {
+                    true => format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n800\r\n{}\r\n0\r\n\r\n", body),
+                    false => format!("HTTP/1.1 200 OK\r\nContent-Length: 2048\r\nConnection: close\r\n\r\n{}", body),
+                };
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        let reader = SitemapReader::new();
+        for path in ["sized", "chunked"] {
+            let url = format!("http://{}/{}", address, path);
+            assert_eq!(reader.fetch(&url, 4096).await?.len(), 2048);
+            assert!(reader.fetch(&url, 1024).await.is_err());
+        }
+        Ok(())
+    }
+
+    // The urls of an image extension nested in an entry are not taken for the url of the entry
+    #[test]
+    fn can_parse_urlset_with_images() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"
+                    xmlns:image="http://www.google.com/schemas/sitemap-image/1.1">
+                <url>
+                    <loc>https://a.example/gallery</loc>
+                    <image:image><image:loc>https://a.example/cat.jpg</image:loc></image:image>
+                    <priority>0.6</priority>
+                </url>
+                <url>
+                    <image:image><image:loc>https://a.example/dog.jpg</image:loc></image:image>
+                </url>
+            </urlset>"#;
+        let sitemap = parse_sitemap(xml.as_bytes()).unwrap();
+        assert_eq!(sitemap, Sitemap::UrlSet(vec![
+            SitemapUrl { url: url::Url::parse("https://a.example/gallery").unwrap(), lastmod: None, priority: Some(0.6) },
+        ]));
+    }
+
+    #[test]
+    fn can_parse_lastmod() {
+        assert_eq!(parse_lastmod("2024-04-01"), Some(Date::from_calendar_date(2024, Month::April, 1).unwrap().midnight().assume_utc()));
+        assert!(parse_lastmod("2024-04-01T12:30:00Z").is_some());
+        assert_eq!(parse_lastmod("2024-13-01"), None);
+        assert_eq!(parse_lastmod("yesterday"), None);
+    }
+}
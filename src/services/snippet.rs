@@ -0,0 +1,110 @@
+use crate::services::Token;
+
+// Number of keywords shown in a snippet, the words removed by the analyzer in between are shown as well.
+const SNIPPET_TOKENS: usize = 20;
+
+/// Build a snippet of the content around the densest cluster of query keywords.
+/// The snippet is HTML escaped and the source words of matching keywords are wrapped in `<mark>`.
+pub fn snippet(content: &str, tokens: &[Token], keywords: &[String]) -> String {
+    if tokens.is_empty() {
+        return String::new();
+    }
+    let window = SNIPPET_TOKENS.min(tokens.len());
+    // Pick the window with the most distinct keywords, then the most matches, the earliest on ties.
+    let mut best_start = 0;
+    let mut best_score = (0, 0);
+    for start in 0..=tokens.len() - window {
+        let matched: Vec<&String> = tokens[start..start + window].iter()
+            .map(|token| &token.keyword)
+            .filter(|keyword| keywords.contains(keyword))
+            .collect();
+        let mut distinct = matched.clone();
+        distinct.sort();
+        distinct.dedup();
+        let score = (distinct.len(), matched.len());
+        if score > best_score {
+            best_start = start;
+            best_score = score;
+        }
+    }
+
+    let window = &tokens[best_start..best_start + window];
+    let start = window[0].start;
+    let end = window[window.len() - 1].end;
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str("… ");
+    }
+    let mut cursor = start;
+    for token in window.iter().filter(|token| keywords.contains(&token.keyword)) {
+        // Leave the punctuation around the word out of the highlight.
+        let word = &content[token.start..token.end];
+        let word_start = token.start + (word.len() - word.trim_start_matches(|c: char| c.is_ascii_punctuation()).len());
+        let word_end = token.end - (word.len() - word.trim_end_matches(|c: char| c.is_ascii_punctuation()).len());
+        if word_start >= word_end {
+            continue;
+        }
+        snippet.push_str(&escape_html(&content[cursor..word_start]));
+        snippet.push_str("<mark>");
+        snippet.push_str(&escape_html(&content[word_start..word_end]));
+        snippet.push_str("</mark>");
+        cursor = word_end;
+    }
+    snippet.push_str(&escape_html(&content[cursor..end]));
+    if end < content.len() {
+        snippet.push_str(" …");
+    }
+    snippet
+}
+
+/// Escape the characters with a meaning in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Tokenize on whitespace, using the lowercase word as the keyword
+    fn tokens(content: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut offset = 0;
+        for word in content.split(' ') {
+            tokens.push(Token {
+                keyword: word.trim_matches(|c: char| c.is_ascii_punctuation()).to_lowercase(),
+                start: offset,
+                end: offset + word.len(),
+            });
+            offset += word.len() + 1;
+        }
+        tokens
+    }
+
+    #[test]
+    fn can_highlight_source_words() {
+        let content = "Rust is fast, and Rust <is> safe.";
+        let snippet = snippet(content, &tokens(content), &["rust".to_string(), "safe".to_string()]);
+        assert_eq!(snippet, "<mark>Rust</mark> is fast, and <mark>Rust</mark> &lt;is&gt; <mark>safe</mark>.");
+    }
+
+    #[test]
+    fn can_pick_densest_window() {
+        let filler = vec!["word"; 30].join(" ");
+        let content = format!("{} tokio async {}", filler, filler);
+        let snippet = snippet(&content, &tokens(&content), &["tokio".to_string(), "async".to_string()]);
+        assert!(snippet.starts_with("… "));
+        assert!(snippet.ends_with(" …"));
+        assert!(snippet.contains("<mark>tokio</mark> <mark>async</mark>"));
+    }
+}
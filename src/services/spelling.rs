@@ -0,0 +1,194 @@
+use std::collections::{HashMap, HashSet};
+use time::OffsetDateTime;
+use crate::models::keyword::KeywordFrequency;
+
+/// SpellingCorrector suggests indexed keywords for misspelled ones.
+/// It keeps every string reachable from a keyword by deleting up to `max_distance` characters,
+/// so the candidates for a word are found by looking up the deletes of the word (SymSpell).
+pub struct SpellingCorrector {
+    // `max_distance` is the largest edit distance between a word and its correction.
+    max_distance: usize,
+    // `frequencies` maps each keyword to the number of websites containing it.
+    frequencies: HashMap<String, i64>,
+    // `surfaces` maps each keyword to the word it was normalized from, shown instead of the keyword.
+    surfaces: HashMap<String, String>,
+    // `deletes` maps each delete variant to the keywords it was derived from.
+    deletes: HashMap<String, Vec<String>>,
+    // `refreshed_at` is the creation time of the newest keyword loaded.
+    refreshed_at: OffsetDateTime,
+}
+
+impl SpellingCorrector {
+    /// Create an empty SpellingCorrector instance.
+    pub fn new(max_distance: usize) -> Self {
+        Self {
+            max_distance,
+            frequencies: HashMap::new(),
+            surfaces: HashMap::new(),
+            deletes: HashMap::new(),
+            refreshed_at: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+
+    /// Creation time of the newest keyword loaded, the next refresh loads the keywords created since.
+    pub fn refreshed_at(&self) -> OffsetDateTime {
+        self.refreshed_at
+    }
+
+    /// Add keywords loaded from the database, updating the frequency and surface form of the ones already known.
+    pub fn add_keywords(&mut self, keywords: Vec<KeywordFrequency>) {
+        for keyword in keywords {
+            if keyword.created_at > self.refreshed_at {
+                self.refreshed_at = keyword.created_at;
+            }
+            self.insert(&keyword.keyword, keyword.document_frequency);
+            self.surfaces.insert(keyword.keyword, keyword.surface);
+        }
+    }
+
+    /// Add a keyword with its document frequency.
+    pub fn insert(&mut self, keyword: &str, document_frequency: i64) {
+        if self.frequencies.insert(keyword.to_string(), document_frequency).is_some() {
+            return;
+        }
+        for delete in deletes(keyword, self.max_distance) {
+            self.deletes.entry(delete).or_default().push(keyword.to_string());
+        }
+    }
+
+    /// Whether the keyword is in the vocabulary.
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.frequencies.contains_key(keyword)
+    }
+
+    /// The word a keyword was normalized from, the keyword itself when it is not known.
+    pub fn surface<'a>(&'a self, keyword: &'a str) -> &'a str {
+        self.surfaces.get(keyword).map(String::as_str).unwrap_or(keyword)
+    }
+
+    /// Find the closest keyword to a word that is not in the vocabulary.
+    /// Closer keywords win, then keywords found on more websites.
+    pub fn correct(&self, word: &str) -> Option<String> {
+        if self.contains(word) {
+            return None;
+        }
+        let mut candidates: HashSet<&String> = HashSet::new();
+        for delete in deletes(word, self.max_distance) {
+            if let Some(keywords) = self.deletes.get(&delete) {
+                candidates.extend(keywords);
+            }
+        }
+        candidates.into_iter()
+            .map(|keyword| (edit_distance(word, keyword), keyword))
+            .filter(|(distance, _)| *distance <= self.max_distance)
+            .min_by(|(a_distance, a), (b_distance, b)| {
+                a_distance.cmp(b_distance)
+                    .then_with(|| self.frequencies[*b].cmp(&self.frequencies[*a]))
+                    .then_with(|| a.cmp(b))
+            })
+            .map(|(_, keyword)| keyword.clone())
+    }
+}
+
+/// Every string made by deleting up to `max_distance` characters from the word, the word included.
+fn deletes(word: &str, max_distance: usize) -> HashSet<String> {
+    let mut deletes = HashSet::new();
+    deletes.insert(word.to_string());
+    let mut frontier = vec![word.to_string()];
+    for _ in 0..max_distance {
+        let mut next = Vec::new();
+        for variant in frontier {
+            let chars: Vec<char> = variant.chars().collect();
+            for index in 0..chars.len() {
+                let delete: String = chars.iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != index)
+                    .map(|(_, c)| c)
+                    .collect();
+                if deletes.insert(delete.clone()) {
+                    next.push(delete);
+                }
+            }
+        }
+        frontier = next;
+    }
+    deletes
+}
+
+/// Damerau-Levenshtein distance (optimal string alignment) between two words.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            // Swapping two adjacent characters is a single edit.
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_measure_edit_distance() {
+        assert_eq!(edit_distance("search", "search"), 0);
+        assert_eq!(edit_distance("serch", "search"), 1);
+        assert_eq!(edit_distance("saerch", "search"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn can_correct_word() {
+        let mut corrector = SpellingCorrector::new(2);
+        corrector.insert("rust", 10);
+        corrector.insert("async", 4);
+        assert_eq!(corrector.correct("rsut"), Some("rust".to_string()));
+        assert_eq!(corrector.correct("asinc"), Some("async".to_string()));
+        assert_eq!(corrector.correct("rust"), None);
+        assert_eq!(corrector.correct("completely"), None);
+    }
+
+    // Equally close keywords are ranked by document frequency
+    #[test]
+    fn prefers_frequent_keywords() {
+        let mut corrector = SpellingCorrector::new(1);
+        corrector.insert("cart", 3);
+        corrector.insert("card", 30);
+        assert_eq!(corrector.correct("carx"), Some("card".to_string()));
+    }
+
+    // Reloading a keyword overwrites its frequency, and its surface form is shown instead of it
+    #[test]
+    fn can_reload_keywords() {
+        let mut corrector = SpellingCorrector::new(1);
+        let keyword = |keyword: &str, surface: &str, document_frequency| KeywordFrequency {
+            keyword: keyword.to_string(),
+            surface: surface.to_string(),
+            document_frequency,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+        };
+        corrector.add_keywords(vec![keyword("cart", "carts", 30), keyword("card", "cards", 3)]);
+        assert_eq!(corrector.correct("carx"), Some("cart".to_string()));
+        corrector.add_keywords(vec![keyword("cart", "carts", 3), keyword("card", "cards", 30)]);
+        let correction = corrector.correct("carx").unwrap();
+        assert_eq!(corrector.surface(&correction), "cards");
+        assert_eq!(corrector.surface("unknown"), "unknown");
+    }
+}
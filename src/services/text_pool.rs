@@ -1,34 +1,56 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use spider::page::Page;
-use sqlx::types::BigDecimal;
-use crate::models;
-use crate::models::website::{InsertWebsiteDao, Website};
+use crate::services::{fingerprint, heading_index, simhash, CrawlReport, Field, ParsedPage};
+use crate::store::{IndexOutcome, IndexPage, IndexStore, Occurrences};
 
-pub struct TextPool {
-    // `text_sender` is a mpsc channel sender that sends a vector of processed texts to the text pool.
-    text_rx: crossbeam_channel:: Receiver<(Page, Vec<String>)>,
-    // `db` is a postgres connection pool.
-    db: sqlx::PgPool,
+pub struct TextPool<S: IndexStore> {
+    // `text_rx` is a mpsc channel receiver that receives a parsed page from the page parser.
+    text_rx: crossbeam_channel:: Receiver<ParsedPage>,
+    // `store` is the index the pages are written to, shared by every text pool worker.
+    store: Arc<S>,
+    // `report_sender` is a mpsc channel sender that reports whether every page crawled changed to the recrawl scheduler.
+    report_sender: crossbeam_channel::Sender<CrawlReport>,
 }
 
-impl TextPool {
+impl<S: IndexStore> TextPool<S> {
     /// Create a new TextPool instance.
-    pub fn new(text_rx: crossbeam_channel:: Receiver<(Page, Vec<String>)>, db: sqlx::PgPool) -> Self {
+    pub fn new(text_rx: crossbeam_channel:: Receiver<ParsedPage>, store: Arc<S>, report_sender: crossbeam_channel::Sender<CrawlReport>) -> Self {
         Self {
             text_rx,
-            db,
+            store,
+            report_sender,
         }
     }
     /// Start the text pool in background.
     pub async fn start(self) {
         // Loop to receive texts from the text receiver.
-        while let Ok((page, texts)) = self.text_rx.recv() {
-            let term_frequency = self.tf(texts.clone());
+        while let Ok(ParsedPage { page, content, texts, surfaces, fields, removal }) = self.text_rx.recv() {
+            // Remove the pages that are gone or ask not to be indexed.
+            if let Some(removal) = removal {
+                match self.remove_page(&page).await {
+                    Ok(true) => println!("Page removed ({:?}): {}", removal, page.get_url()),
+                    Ok(false) => (),
+                    Err(e) => eprintln!("Error removing page: {:?}", e),
+                }
+                // A page that is gone is checked again as rarely as a page that never changes.
+                self.report_crawl(page.get_url(), false);
+                continue;
+            }
+            let page_url = page.get_url().to_string();
+            let fingerprint = fingerprint(&texts, &fields);
+            let simhash = simhash(&texts);
+            let occurrences = self.occurrences(&texts, surfaces, &fields);
             let total_count = texts.len();
-            // Save the texts to the database.
-            match self.save_texts(page, total_count as i64, term_frequency).await {
+            // Save the texts to the index.
+            match self.save_texts(page, content, total_count as i64, occurrences, fingerprint, simhash).await {
+                Ok(IndexOutcome::Unchanged) => {
+                    println!("Page unchanged, texts kept.");
+                    self.report_crawl(&page_url, false);
+                }
                 Ok(_) => {
                     println!("Texts saved successfully.");
+                    self.report_crawl(&page_url, true);
                 }
                 Err(e) => {
                     eprintln!("Error saving texts: {:?}", e);
@@ -37,99 +59,69 @@ impl TextPool {
             }
         }
     }
-    /// Save the texts to the database.
-    async fn save_texts(&self, page: Page, count: i64, term_frequency: HashMap<String, i64>) -> Result<(), Box<dyn std::error::Error>> {
+    /// Save the texts to the index.
+    async fn save_texts(&self, page: Page, content: String, count: i64, occurrences: HashMap<String, Occurrences>, fingerprint: i64, simhash: Option<i64>) -> Result<IndexOutcome, Box<dyn std::error::Error>> {
         let page_url = url::Url::parse(page.get_url())?;
-        // Find website by url, create a new website if it doesn't exist.
-        let website = models::website::Website::find_by_url(&self.db, page_url.as_str().to_string()).await;
-        match website {
-            Ok(website) => {
-                // Update the word count of the website.
-                Self::update_website(&self, website, count, term_frequency).await?;
-            }
-            Err(sqlx::Error::RowNotFound) => {
-                // Insert the website to the database
-                Self::insert_website(&self, page_url.as_str(), count as i32, term_frequency).await?;
-            }
-            Err(e) => {
-                return Err(Box::new(e));
-            }
-        }
-        Ok(())
-    }
-
-    async fn insert_website(&self, url: &str, word_count: i32, term_frequency: HashMap<String, i64>) -> Result<(), Box<dyn std::error::Error>> {
-        let insert_website = InsertWebsiteDao {
-            url: url::Url::parse(url).map_err(|_| "Error parsing URL")?,
-            word_count,
+        let index_page = IndexPage {
+            url: page_url,
+            content,
+            word_count: count as i32,
+            keywords: occurrences,
+            fingerprint,
+            simhash,
         };
-        // Insert the website to the database
-        let website = models::website::Website::insert(&self.db, insert_website).await.map_err(|e| format!("Error inserting website: {:?}", e))?;
-        // Insert the keywords to the database
-        for (keyword, frequency) in term_frequency.iter() {
-            self.insert_keyword(&website, keyword.clone(), *frequency as i32).await.map_err(|e| format!("Error inserting keyword: {:?}", e))?;
-        }
-        Ok(())
-    }
-
-    async fn update_website(&self, website: models::website::Website, count: i64, term_frequency: HashMap<String, i64>) -> Result<(), Box<dyn std::error::Error>> {
-        // Update the word count of the website.
-        models::website::Website::update_word_count(&self.db, website.id, count as i32).await.map_err(|e| format!("Error updating word count: {:?}", e))?;
-        // Remove all the keywords associated with the website.
-        models::website_keywords::WebsiteKeywords::delete_by_website(&self.db, website.id).await.map_err(|e| format!("Error deleting website keywords: {:?}", e))?;
-        // Insert the keywords to the database
-        for (keyword, frequency) in term_frequency.iter() {
-            self.insert_keyword(&website, keyword.clone(), *frequency as i32).await.map_err(|e| format!("Error inserting keyword: {:?}", e))?;
-        }
-        Ok(())
+        self.store.index_page(index_page).await
     }
 
-    async fn insert_keyword(&self, website: &Website, keyword: String, frequency: i32) -> Result<(), Box<dyn std::error::Error>> {
-        let keyword = models::keyword::Keyword::find_or_create(&self.db, &keyword).await.map_err(|e| format!("Error finding or creating keyword: {:?}", e))?;
-        // Insert the keyword to the database
-        let insert_website_keywords = models::website_keywords::InsertWebsiteKeywordsDao {
-            keyword_id: keyword.id,
-            website_id: website.id,
-            frequency,
-        };
-        // Insert the website keywords to the database
-        models::website_keywords::WebsiteKeywords::insert(&self.db, insert_website_keywords).await?;
-        let total_docs_with_keyword = models::website_keywords::WebsiteKeywords::count_by_keyword_id(&self.db, keyword.id).await.map_err(|e| format!("Error counting total docs with keyword: {:?}", e))?;
-        let total_docs = models::website::Website::count(&self.db).await.map_err(|e| format!("Error counting total docs: {:?}", e))?;
-        let idf = self.idf(total_docs_with_keyword, total_docs);
-        let normalized_frequency = frequency as f64 / website.word_count as f64;
-        let tfidf = self.tfidf(normalized_frequency, idf);
-        let insert_website_keyword_tfidf = models::website_keyword_tfidf::InsertWebsiteKeywordTfidfDao {
-            website_id: website.id,
-            keyword_id: keyword.id,
-            tf: BigDecimal::try_from(normalized_frequency).map_err(|_| "Error converting to BigDecimal")?,
-            idf: BigDecimal::try_from(idf).map_err(|_| "Error converting to BigDecimal")?,
-            tfidf: BigDecimal::try_from(tfidf).map_err(|_| "Error converting to BigDecimal")?,
+    /// Report the crawl of a page to the recrawl scheduler.
+    fn report_crawl(&self, page_url: &str, changed: bool) {
+        let url = match url::Url::parse(page_url) {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("Error parsing URL: {:?}", e);
+                return;
+            }
         };
-        // Insert the website keyword tfidf to the database
-        models::website_keyword_tfidf::WebsiteKeywordTfidf::upsert_by_website_keyword(&self.db, insert_website_keyword_tfidf).await.map_err(|e| format!("Error upserting website keyword tfidf: {:?}", e))?;
-        Ok(())
-    }
-
-    /// Calculate the term frequency of the text.
-    fn tf(&self, texts: Vec<String>) -> HashMap<String, i64> {
-        let mut tf: HashMap<String, i64> = HashMap::new();
-        for text in texts.iter() {
-            let count = tf.entry(text.clone()).or_insert(0);
-            *count += 1;
+        if let Err(e) = self.report_sender.send(CrawlReport { url, changed }) {
+            eprintln!("Error sending crawl report to recrawl scheduler: {:?}", e);
         }
-        tf
     }
 
-    /// Calculate the inverse document frequency of the keyword.
-    fn idf(&self, total_docs_with_keyword: i64, total_docs: i64) -> f64 {
-        // Calculate the idf
-        let idf = 1.0 + (total_docs as f64 / total_docs_with_keyword as f64).ln();
-        idf
+    /// Remove the page from the index, returning whether it was indexed.
+    async fn remove_page(&self, page: &Page) -> Result<bool, Box<dyn std::error::Error>> {
+        let page_url = url::Url::parse(page.get_url())?;
+        self.store.remove_page(&page_url).await
     }
 
-    // Calculate TF-IDF
-    fn tfidf(&self, tf: f64, idf: f64) -> f64 {
-        tf * idf
+    /// Collect the positions of every keyword in the body and count its occurrences in the other fields.
+    /// A keyword found only outside the body has no positions nor surface form.
+    fn occurrences(&self, texts: &[String], surfaces: HashMap<String, String>, fields: &HashMap<Field, Vec<String>>) -> HashMap<String, Occurrences> {
+        let mut occurrences: HashMap<String, Occurrences> = HashMap::new();
+        for (position, text) in texts.iter().enumerate() {
+            occurrences.entry(text.clone()).or_default().positions.push(position as i32);
+        }
+        for (keyword, surface) in surfaces {
+            if let Some(occurrence) = occurrences.get_mut(&keyword) {
+                occurrence.surface = Some(surface);
+            }
+        }
+        for (field, keywords) in fields.iter() {
+            for keyword in keywords {
+                let occurrence = occurrences.entry(keyword.clone()).or_default();
+                match field {
+                    Field::Title => occurrence.title += 1,
+                    Field::Heading(level) => {
+                        if let Some(index) = heading_index(*level) {
+                            occurrence.headings[index] += 1;
+                        }
+                    }
+                    Field::Description => occurrence.description += 1,
+                    Field::Url => occurrence.url += 1,
+                    // The body is counted from its positions above.
+                    Field::Body => (),
+                }
+            }
+        }
+        occurrences
     }
 }
\ No newline at end of file
@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use crate::services::{hamming_distance, simhash_bands, NEAR_DUPLICATE_DISTANCE};
+
+/// DuplicateClusters groups the websites of an in-memory index into near duplicate clusters by simhash.
+///
+/// Each cluster is represented by its canonical website, the first one indexed, and a website joins the cluster of
+/// its closest near duplicate. Candidates are found through the bands of the simhashes rather than a full scan.
+#[derive(Default)]
+pub(crate) struct DuplicateClusters {
+    // `simhashes` maps each website with a simhash to it.
+    simhashes: HashMap<Uuid, i64>,
+    // `bands` maps each band number and value to the websites whose simhash has it.
+    bands: HashMap<(usize, i64), HashSet<Uuid>>,
+    // `canonical_ids` maps each website that is a near duplicate to the canonical website of its cluster.
+    canonical_ids: HashMap<Uuid, Uuid>,
+    // `members` maps each canonical website to the other websites of its cluster, in the order they joined.
+    members: HashMap<Uuid, Vec<Uuid>>,
+}
+
+impl DuplicateClusters {
+    /// Add or replace the simhash of a website, returning the canonical website of the cluster it joined.
+    /// `None` means the website is canonical. The near duplicates of a website that joins a cluster move along.
+    pub(crate) fn insert(&mut self, website_id: Uuid, simhash: Option<i64>) -> Option<Uuid> {
+        // Taken out first so that removing the website doesn't promote one of them.
+        let members = self.members.remove(&website_id).unwrap_or_default();
+        self.remove(website_id);
+        let cluster_id = simhash.and_then(|simhash| {
+            let candidates: HashSet<Uuid> = simhash_bands(simhash).iter()
+                .enumerate()
+                .filter_map(|(band, value)| self.bands.get(&(band, *value)))
+                .flatten()
+                .copied()
+                .collect();
+            // The taken out members still point to the website, so they are not a cluster to join.
+            closest_cluster(simhash, website_id, candidates.into_iter()
+                .filter_map(|candidate| Some((*self.simhashes.get(&candidate)?, self.canonical_id(candidate)))))
+        });
+        if let Some(simhash) = simhash {
+            self.simhashes.insert(website_id, simhash);
+            for (band, value) in simhash_bands(simhash).into_iter().enumerate() {
+                self.bands.entry((band, value)).or_default().insert(website_id);
+            }
+        }
+        let canonical_id = cluster_id.unwrap_or(website_id);
+        for member in members {
+            self.join(member, canonical_id);
+        }
+        if let Some(cluster_id) = cluster_id {
+            self.join(website_id, cluster_id);
+        }
+        cluster_id
+    }
+
+    /// Remove a website, the first near duplicate that joined it becomes the canonical website of the others.
+    pub(crate) fn remove(&mut self, website_id: Uuid) {
+        if let Some(simhash) = self.simhashes.remove(&website_id) {
+            for (band, value) in simhash_bands(simhash).into_iter().enumerate() {
+                if let Some(websites) = self.bands.get_mut(&(band, value)) {
+                    websites.remove(&website_id);
+                    if websites.is_empty() {
+                        self.bands.remove(&(band, value));
+                    }
+                }
+            }
+        }
+        if let Some(canonical_id) = self.canonical_ids.remove(&website_id) {
+            if let Some(members) = self.members.get_mut(&canonical_id) {
+                members.retain(|member| *member != website_id);
+                if members.is_empty() {
+                    self.members.remove(&canonical_id);
+                }
+            }
+        }
+        if let Some(mut members) = self.members.remove(&website_id) {
+            let canonical_id = members.remove(0);
+            self.canonical_ids.remove(&canonical_id);
+            for member in members {
+                self.join(member, canonical_id);
+            }
+        }
+    }
+
+    /// The canonical website of the cluster of a website, `None` when it is canonical itself.
+    pub(crate) fn cluster_id(&self, website_id: Uuid) -> Option<Uuid> {
+        self.canonical_ids.get(&website_id).copied()
+    }
+
+    fn canonical_id(&self, website_id: Uuid) -> Uuid {
+        self.cluster_id(website_id).unwrap_or(website_id)
+    }
+
+    fn join(&mut self, website_id: Uuid, canonical_id: Uuid) {
+        self.canonical_ids.insert(website_id, canonical_id);
+        self.members.entry(canonical_id).or_default().push(website_id);
+    }
+}
+
+/// Pick the cluster of the closest near duplicate of a website among candidates given as simhash and canonical
+/// website, `None` when no candidate is a near duplicate. Ties go to the smallest canonical id so the pick is stable.
+pub(crate) fn closest_cluster(simhash: i64, website_id: Uuid, candidates: impl IntoIterator<Item = (i64, Uuid)>) -> Option<Uuid> {
+    candidates.into_iter()
+        // A website is never a near duplicate of itself or of its own cluster.
+        .filter(|(_, canonical_id)| *canonical_id != website_id)
+        .map(|(candidate, canonical_id)| (hamming_distance(simhash, candidate), canonical_id))
+        .filter(|(distance, _)| *distance <= NEAR_DUPLICATE_DISTANCE)
+        .min()
+        .map(|(_, canonical_id)| canonical_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_cluster_near_duplicates() {
+        let mut clusters = DuplicateClusters::default();
+        let (a, b, c, d) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(clusters.insert(a, Some(0b1111)), None);
+        assert_eq!(clusters.insert(b, Some(0b1110)), Some(a));
+        assert_eq!(clusters.insert(c, Some(0b0110)), Some(a));
+        assert_eq!(clusters.insert(d, Some(-1)), None);
+
+        // The first near duplicate to join takes over the cluster.
+        clusters.remove(a);
+        assert_eq!(clusters.cluster_id(b), None);
+        assert_eq!(clusters.cluster_id(c), Some(b));
+
+        // A canonical website that joins another cluster takes its near duplicates along.
+        assert_eq!(clusters.insert(b, Some(-2)), Some(d));
+        assert_eq!(clusters.cluster_id(c), Some(d));
+        assert_eq!(clusters.insert(d, None), None);
+        assert_eq!(clusters.cluster_id(b), Some(d));
+    }
+}
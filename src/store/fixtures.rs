@@ -0,0 +1,28 @@
+use std::collections::HashMap;
+use crate::services::{fingerprint, simhash};
+use crate::store::{IndexPage, Occurrences};
+
+/// A page whose body is the keywords in order, with no other field.
+pub(crate) fn page(url: &str, keywords: &[&str]) -> IndexPage {
+    let texts: Vec<String> = keywords.iter().map(|keyword| keyword.to_string()).collect();
+    let mut occurrences: HashMap<String, Occurrences> = HashMap::new();
+    for (position, keyword) in keywords.iter().enumerate() {
+        occurrences.entry(keyword.to_string()).or_default().positions.push(position as i32);
+    }
+    IndexPage {
+        url: url::Url::parse(url).unwrap(),
+        content: keywords.join(" "),
+        word_count: keywords.len() as i32,
+        keywords: occurrences,
+        fingerprint: fingerprint(&texts, &HashMap::new()),
+        simhash: simhash(&texts),
+    }
+}
+
+/// A body of 200 distinct words, and a near duplicate of it with the word in the middle replaced by `changed`.
+pub(crate) fn near_duplicate_words(changed: &str) -> (Vec<String>, Vec<String>) {
+    let words: Vec<String> = (0..200).map(|n| format!("word{}", n)).collect();
+    let mut near_duplicate = words.clone();
+    near_duplicate[100] = changed.to_string();
+    (words, near_duplicate)
+}
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use async_trait::async_trait;
+use sqlx::types::BigDecimal;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::models::corpus_stats::CorpusStats;
+use crate::models::keyword::{Keyword, KeywordFrequency};
+use crate::models::query_log::QueryLog;
+use crate::models::website::{Website, WebsiteContent};
+use crate::models::website_keyword_tfidf::WebsiteKeywordTfidf;
+use crate::models::website_keywords::WebsiteKeywords;
+use crate::store::{idf, tf, DuplicateClusters, IndexOutcome, IndexPage, IndexStore};
+
+/// MemoryIndexStore keeps the index in memory, for tests and small embedded deployments.
+#[derive(Default)]
+pub struct MemoryIndexStore {
+    index: RwLock<MemoryIndex>,
+}
+
+#[derive(Default)]
+struct MemoryIndex {
+    websites: HashMap<Uuid, Website>,
+    // `website_ids` maps each url to its website id.
+    website_ids: HashMap<String, Uuid>,
+    contents: HashMap<Uuid, String>,
+    keywords: HashMap<String, Keyword>,
+    // `surfaces` maps each keyword to the word it was first normalized from.
+    surfaces: HashMap<String, String>,
+    // `postings` maps each keyword id to the postings of the websites containing it, keyed by website id.
+    postings: HashMap<Uuid, HashMap<Uuid, WebsiteKeywords>>,
+    // `website_keywords` maps each website id to the ids of its keywords, to drop its postings without a full scan.
+    website_keywords: HashMap<Uuid, Vec<Uuid>>,
+    total_word_count: i64,
+    version: i64,
+    query_logs: HashMap<String, QueryLog>,
+    // `clusters` groups the websites into near duplicate clusters, the source of their cluster id.
+    clusters: DuplicateClusters,
+}
+
+impl MemoryIndexStore {
+    /// Create an empty MemoryIndexStore instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, MemoryIndex> {
+        self.index.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, MemoryIndex> {
+        self.index.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl MemoryIndex {
+    /// Remove the postings of a website.
+    fn remove_postings(&mut self, website_id: Uuid) {
+        for keyword_id in self.website_keywords.remove(&website_id).unwrap_or_default() {
+            if let Some(postings) = self.postings.get_mut(&keyword_id) {
+                postings.remove(&website_id);
+            }
+        }
+    }
+
+    /// Find a keyword, creating it if it doesn't exist.
+    fn find_or_create_keyword(&mut self, keyword: &str, now: OffsetDateTime) -> Uuid {
+        self.keywords.entry(keyword.to_string())
+            .or_insert_with(|| Keyword {
+                id: Uuid::new_v4(),
+                keyword: keyword.to_string(),
+                created_at: now,
+                updated_at: now,
+            })
+            .id
+    }
+}
+
+#[async_trait]
+impl IndexStore for MemoryIndexStore {
+    /// Write the page under the write lock, so readers see either the old or the new version of it.
+    async fn index_page(&self, page: IndexPage) -> Result<IndexOutcome, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut index = self.write();
+        let url = page.url.to_string();
+        let (website_id, outcome) = match index.website_ids.get(&url).copied() {
+            Some(website_id) => {
+                let website = index.websites.get_mut(&website_id).ok_or("Website of url is missing")?;
+                website.last_seen_at = now;
+                website.updated_at = now;
+                if website.fingerprint == Some(page.fingerprint) {
+                    return Ok(IndexOutcome::Unchanged);
+                }
+                let old_word_count = website.word_count as i64;
+                website.word_count = page.word_count;
+                website.fingerprint = Some(page.fingerprint);
+                website.simhash = page.simhash;
+                index.total_word_count += page.word_count as i64 - old_word_count;
+                index.remove_postings(website_id);
+                (website_id, IndexOutcome::Updated)
+            }
+            None => {
+                let website_id = Uuid::new_v4();
+                index.websites.insert(website_id, Website {
+                    id: website_id,
+                    url: url.clone(),
+                    word_count: page.word_count,
+                    fingerprint: Some(page.fingerprint),
+                    simhash: page.simhash,
+                    cluster_id: None,
+                    last_seen_at: now,
+                    created_at: now,
+                    updated_at: now,
+                });
+                index.website_ids.insert(url, website_id);
+                index.total_word_count += page.word_count as i64;
+                (website_id, IndexOutcome::Inserted)
+            }
+        };
+        index.contents.insert(website_id, page.content);
+        index.clusters.insert(website_id, page.simhash);
+        index.version += 1;
+
+        let mut keyword_ids = Vec::with_capacity(page.keywords.len());
+        for (keyword, occurrences) in page.keywords {
+            let keyword_id = index.find_or_create_keyword(&keyword, now);
+            if let Some(surface) = occurrences.surface {
+                index.surfaces.entry(keyword).or_insert(surface);
+            }
+            keyword_ids.push(keyword_id);
+            index.postings.entry(keyword_id).or_default().insert(website_id, WebsiteKeywords {
+                id: Uuid::new_v4(),
+                keyword_id,
+                website_id,
+                frequency: occurrences.positions.len() as i32,
+                positions: occurrences.positions,
+                title_frequency: occurrences.title,
+                heading_frequencies: occurrences.headings.to_vec(),
+                description_frequency: occurrences.description,
+                url_frequency: occurrences.url,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+        index.website_keywords.insert(website_id, keyword_ids);
+        Ok(outcome)
+    }
+
+    async fn remove_page(&self, url: &url::Url) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut index = self.write();
+        let website_id = match index.website_ids.remove(url.as_str()) {
+            Some(website_id) => website_id,
+            None => return Ok(false),
+        };
+        index.remove_postings(website_id);
+        index.contents.remove(&website_id);
+        index.clusters.remove(website_id);
+        if let Some(website) = index.websites.remove(&website_id) {
+            index.total_word_count -= website.word_count as i64;
+        }
+        index.version += 1;
+        Ok(true)
+    }
+
+    async fn find_keyword(&self, keyword: &str) -> Result<Option<Keyword>, Box<dyn std::error::Error>> {
+        Ok(self.read().keywords.get(keyword).cloned())
+    }
+
+    async fn find_keyword_frequencies_since(&self, since: OffsetDateTime) -> Result<Vec<KeywordFrequency>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let mut frequencies: Vec<KeywordFrequency> = index.keywords.values()
+            .filter(|keyword| keyword.created_at >= since)
+            .map(|keyword| KeywordFrequency {
+                keyword: keyword.keyword.clone(),
+                surface: index.surfaces.get(&keyword.keyword).unwrap_or(&keyword.keyword).clone(),
+                document_frequency: index.postings.get(&keyword.id).map(|postings| postings.len() as i64).unwrap_or(0),
+                created_at: keyword.created_at,
+            })
+            .collect();
+        frequencies.sort_by_key(|frequency| frequency.created_at);
+        Ok(frequencies)
+    }
+
+    async fn find_postings(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywords>, Box<dyn std::error::Error>> {
+        Ok(self.read().postings.get(&keyword_id)
+            .map(|postings| postings.values().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn document_frequency(&self, keyword_id: Uuid) -> Result<i64, Box<dyn std::error::Error>> {
+        Ok(self.read().postings.get(&keyword_id).map(|postings| postings.len() as i64).unwrap_or(0))
+    }
+
+    /// Scores are computed from the current corpus, so they are never stale.
+    async fn find_scores(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let index = self.read();
+        let postings = match index.postings.get(&keyword_id) {
+            Some(postings) => postings,
+            None => return Ok(Vec::new()),
+        };
+        let idf = idf(postings.len() as i64, index.websites.len() as i64);
+        let mut scores = Vec::with_capacity(postings.len());
+        for posting in postings.values() {
+            let word_count = index.websites.get(&posting.website_id).map(|website| website.word_count).unwrap_or(0);
+            let normalized_frequency = tf(posting.frequency, word_count);
+            scores.push(WebsiteKeywordTfidf {
+                id: Uuid::nil(),
+                website_id: posting.website_id,
+                keyword_id,
+                tf: BigDecimal::try_from(normalized_frequency).map_err(|_| "Error converting to BigDecimal")?,
+                idf: BigDecimal::try_from(idf).map_err(|_| "Error converting to BigDecimal")?,
+                tfidf: BigDecimal::try_from(normalized_frequency * idf).map_err(|_| "Error converting to BigDecimal")?,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+        Ok(scores)
+    }
+
+    async fn find_websites(&self, ids: &[Uuid]) -> Result<Vec<Website>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        // Clusters change as other websites come and go, so the cluster id is looked up rather than stored.
+        Ok(ids.iter()
+            .filter_map(|id| index.websites.get(id).cloned())
+            .map(|mut website| {
+                website.cluster_id = index.clusters.cluster_id(website.id);
+                website
+            })
+            .collect())
+    }
+
+    async fn find_contents(&self, ids: &[Uuid]) -> Result<Vec<WebsiteContent>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        Ok(ids.iter()
+            .filter_map(|id| index.contents.get(id).map(|content| WebsiteContent {
+                id: *id,
+                content: content.clone(),
+            }))
+            .collect())
+    }
+
+    async fn corpus_stats(&self) -> Result<CorpusStats, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let now = OffsetDateTime::now_utc();
+        // Scores are computed at query time, so they are never stale.
+        Ok(CorpusStats {
+            document_count: index.websites.len() as i64,
+            total_word_count: index.total_word_count,
+            version: index.version,
+            idf_version: index.version,
+            idf_recomputed_at: None,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    async fn record_query(&self, query: &str) -> Result<QueryLog, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut index = self.write();
+        let query_log = index.query_logs.entry(query.to_string())
+            .and_modify(|query_log| {
+                query_log.count += 1;
+                query_log.updated_at = now;
+            })
+            .or_insert_with(|| QueryLog {
+                id: Uuid::new_v4(),
+                query: query.to_string(),
+                count: 1,
+                created_at: now,
+                updated_at: now,
+            });
+        Ok(query_log.clone())
+    }
+
+    async fn find_popular_queries(&self, limit: i64) -> Result<Vec<QueryLog>, Box<dyn std::error::Error>> {
+        let mut query_logs: Vec<QueryLog> = self.read().query_logs.values().cloned().collect();
+        query_logs.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.query.cmp(&b.query)));
+        query_logs.truncate(limit.max(0) as usize);
+        Ok(query_logs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::fixtures::{near_duplicate_words, page};
+
+    #[tokio::test]
+    async fn can_index_page() {
+        let store = MemoryIndexStore::new();
+        store.index_page(page("https://a.example/", &["rust", "async", "rust"])).await.unwrap();
+        store.index_page(page("https://b.example/", &["rust"])).await.unwrap();
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        let postings = store.find_postings(rust.id).await.unwrap();
+        assert_eq!(postings.len(), 2);
+        let corpus_stats = store.corpus_stats().await.unwrap();
+        assert_eq!(corpus_stats.document_count, 2);
+        assert_eq!(corpus_stats.total_word_count, 4);
+    }
+
+    // Reindexing a page replaces its postings and word count
+    #[tokio::test]
+    async fn can_reindex_page() {
+        let store = MemoryIndexStore::new();
+        store.index_page(page("https://a.example/", &["rust", "async"])).await.unwrap();
+        store.index_page(page("https://a.example/", &["tokio"])).await.unwrap();
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        assert!(store.find_postings(rust.id).await.unwrap().is_empty());
+        assert!(store.find_scores(rust.id).await.unwrap().is_empty());
+        let tokio = store.find_keyword("tokio").await.unwrap().unwrap();
+        assert_eq!(store.find_postings(tokio.id).await.unwrap().len(), 1);
+        assert_eq!(store.corpus_stats().await.unwrap().total_word_count, 1);
+    }
+
+    // The idf of a page indexed early follows the pages indexed after it
+    #[tokio::test]
+    async fn scores_follow_corpus() {
+        let store = MemoryIndexStore::new();
+        store.index_page(page("https://a.example/", &["rust"])).await.unwrap();
+        store.index_page(page("https://b.example/", &["tokio"])).await.unwrap();
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        let scores = store.find_scores(rust.id).await.unwrap();
+        assert_eq!(scores[0].idf, BigDecimal::try_from(idf(1, 2)).unwrap());
+        assert!(!store.corpus_stats().await.unwrap().is_idf_stale());
+    }
+
+    // Crawling a page again without changes keeps its postings and the corpus version
+    #[tokio::test]
+    async fn can_skip_unchanged_page() {
+        let store = MemoryIndexStore::new();
+        assert_eq!(store.index_page(page("https://a.example/", &["rust"])).await.unwrap(), IndexOutcome::Inserted);
+        let version = store.corpus_stats().await.unwrap().version;
+        assert_eq!(store.index_page(page("https://a.example/", &["rust"])).await.unwrap(), IndexOutcome::Unchanged);
+        assert_eq!(store.corpus_stats().await.unwrap().version, version);
+        assert_eq!(store.index_page(page("https://a.example/", &["tokio"])).await.unwrap(), IndexOutcome::Updated);
+    }
+
+    #[tokio::test]
+    async fn can_remove_page() {
+        let store = MemoryIndexStore::new();
+        store.index_page(page("https://a.example/", &["rust", "async"])).await.unwrap();
+        store.index_page(page("https://b.example/", &["rust"])).await.unwrap();
+        assert!(store.remove_page(&url::Url::parse("https://a.example/").unwrap()).await.unwrap());
+        assert!(!store.remove_page(&url::Url::parse("https://a.example/").unwrap()).await.unwrap());
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        assert_eq!(store.find_postings(rust.id).await.unwrap().len(), 1);
+        assert_eq!(store.find_scores(rust.id).await.unwrap().len(), 1);
+        let corpus_stats = store.corpus_stats().await.unwrap();
+        assert_eq!(corpus_stats.document_count, 1);
+        assert_eq!(corpus_stats.total_word_count, 1);
+    }
+
+    // Mirrors of a page join its cluster and the cluster survives removing the canonical page
+    #[tokio::test]
+    async fn can_cluster_near_duplicates() {
+        let store = MemoryIndexStore::new();
+        let (words, mirrored) = near_duplicate_words("rust");
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let mirrored: Vec<&str> = mirrored.iter().map(String::as_str).collect();
+        store.index_page(page("https://a.example/", &words)).await.unwrap();
+        store.index_page(page("https://b.example/", &mirrored)).await.unwrap();
+        store.index_page(page("https://c.example/", &mirrored)).await.unwrap();
+        store.index_page(page("https://d.example/", &["rust"])).await.unwrap();
+        let word = store.find_keyword("word0").await.unwrap().unwrap();
+        let ids: Vec<Uuid> = store.find_postings(word.id).await.unwrap().iter().map(|posting| posting.website_id).collect();
+        let websites = store.find_websites(&ids).await.unwrap();
+        let canonical = websites.iter().find(|website| website.url == "https://a.example/").unwrap().id;
+        assert!(websites.iter().all(|website| website.canonical_id() == canonical));
+
+        store.remove_page(&url::Url::parse("https://a.example/").unwrap()).await.unwrap();
+        let websites = store.find_websites(&ids).await.unwrap();
+        assert_eq!(websites.len(), 2);
+        assert_eq!(websites[0].canonical_id(), websites[1].canonical_id());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::models::corpus_stats::CorpusStats;
+use crate::models::keyword::{Keyword, KeywordFrequency};
+use crate::models::query_log::QueryLog;
+use crate::models::website::{Website, WebsiteContent};
+use crate::models::website_keyword_tfidf::WebsiteKeywordTfidf;
+use crate::models::website_keywords::WebsiteKeywords;
+use crate::services::HEADING_LEVELS;
+
+mod duplicates;
+#[cfg(test)]
+pub(crate) mod fixtures;
+mod memory;
+mod postgres;
+#[cfg(feature = "segment")]
+mod segment;
+
+pub(crate) use duplicates::{closest_cluster, DuplicateClusters};
+pub use memory::MemoryIndexStore;
+pub use postgres::PgIndexStore;
+#[cfg(feature = "segment")]
+pub use segment::SegmentIndexStore;
+
+/// Where a keyword occurs in a page.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Occurrences {
+    // `positions` are the positions of the keyword in the body, the body frequency is their number.
+    pub positions: Vec<i32>,
+    pub title: i32,
+    // `headings` are the occurrences in the headings of every level, `<h1>` first.
+    pub headings: [i32; HEADING_LEVELS],
+    pub description: i32,
+    pub url: i32,
+    // `surface` is the word of the body the keyword was first normalized from, `None` when it is not in the body.
+    pub surface: Option<String>,
+}
+
+/// A parsed page ready to be written to the index.
+#[derive(Debug, Clone)]
+pub struct IndexPage {
+    pub url: url::Url,
+    // `content` is the visible text of the page, kept for result snippets.
+    pub content: String,
+    // `word_count` is the number of keywords in the body.
+    pub word_count: i32,
+    pub keywords: HashMap<String, Occurrences>,
+    // `fingerprint` is the fingerprint of the normalized token stream, see [`crate::services::fingerprint`].
+    pub fingerprint: i64,
+    // `simhash` is the simhash of the body tokens, see [`crate::services::simhash`], `None` for a page without body.
+    pub simhash: Option<i64>,
+}
+
+/// What writing a page did to the index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexOutcome {
+    // `Inserted` is a page seen for the first time.
+    Inserted,
+    // `Updated` is a page whose postings and scores were replaced.
+    Updated,
+    // `Unchanged` is a page with the fingerprint it was indexed with, only its last seen time moved.
+    Unchanged,
+}
+
+/// IndexStore holds the websites, keywords, postings and scores of the index.
+#[async_trait]
+pub trait IndexStore: Send + Sync {
+    /// Write a page, replacing the postings and scores of an earlier version of it unless its fingerprint is unchanged.
+    /// Either the whole page is written or nothing is.
+    async fn index_page(&self, page: IndexPage) -> Result<IndexOutcome, Box<dyn std::error::Error>>;
+
+    /// Remove a page with its postings and scores, returning whether it was indexed.
+    async fn remove_page(&self, url: &url::Url) -> Result<bool, Box<dyn std::error::Error>>;
+
+    /// Find a keyword, `None` when it was never indexed.
+    async fn find_keyword(&self, keyword: &str) -> Result<Option<Keyword>, Box<dyn std::error::Error>>;
+
+    /// Find the keywords created at or after `since` with their document frequency, oldest first.
+    async fn find_keyword_frequencies_since(&self, since: OffsetDateTime) -> Result<Vec<KeywordFrequency>, Box<dyn std::error::Error>>;
+
+    /// Find the postings of a keyword.
+    async fn find_postings(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywords>, Box<dyn std::error::Error>>;
+
+    /// Find the postings of each keyword in the websites containing every one of them, in the order of the keywords.
+    async fn find_common_postings(&self, keyword_ids: &[Uuid]) -> Result<Vec<Vec<WebsiteKeywords>>, Box<dyn std::error::Error>> {
+        let mut postings = Vec::with_capacity(keyword_ids.len());
+        for keyword_id in keyword_ids {
+            postings.push(self.find_postings(*keyword_id).await?);
+        }
+        let mut common: HashSet<Uuid> = match postings.first() {
+            Some(rows) => rows.iter().map(|row| row.website_id).collect(),
+            None => HashSet::new(),
+        };
+        for rows in postings.iter().skip(1) {
+            let website_ids: HashSet<Uuid> = rows.iter().map(|row| row.website_id).collect();
+            common.retain(|website_id| website_ids.contains(website_id));
+        }
+        for rows in postings.iter_mut() {
+            rows.retain(|row| common.contains(&row.website_id));
+        }
+        Ok(postings)
+    }
+
+    /// Count the websites containing a keyword.
+    async fn document_frequency(&self, keyword_id: Uuid) -> Result<i64, Box<dyn std::error::Error>> {
+        Ok(self.find_postings(keyword_id).await?.len() as i64)
+    }
+
+    /// Find the tfidf scores of a keyword.
+    async fn find_scores(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>>;
+
+    /// Find the tfidf scores of a keyword in the given websites.
+    async fn find_scores_in(&self, keyword_id: Uuid, website_ids: &[Uuid]) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>> {
+        let website_ids: HashSet<&Uuid> = website_ids.iter().collect();
+        let mut scores = self.find_scores(keyword_id).await?;
+        scores.retain(|score| website_ids.contains(&score.website_id));
+        Ok(scores)
+    }
+
+    /// Find websites by id, ids that do not exist are left out.
+    async fn find_websites(&self, ids: &[Uuid]) -> Result<Vec<Website>, Box<dyn std::error::Error>>;
+
+    /// Find the visible text of websites by id.
+    async fn find_contents(&self, ids: &[Uuid]) -> Result<Vec<WebsiteContent>, Box<dyn std::error::Error>>;
+
+    /// The corpus statistics used for ranking.
+    async fn corpus_stats(&self) -> Result<CorpusStats, Box<dyn std::error::Error>>;
+
+    /// Count one more search for the query.
+    async fn record_query(&self, query: &str) -> Result<QueryLog, Box<dyn std::error::Error>>;
+
+    /// Find the most searched queries.
+    async fn find_popular_queries(&self, limit: i64) -> Result<Vec<QueryLog>, Box<dyn std::error::Error>>;
+}
+
+/// Calculate the inverse document frequency of a keyword.
+pub(crate) fn idf(total_docs_with_keyword: i64, total_docs: i64) -> f64 {
+    1.0 + (total_docs as f64 / total_docs_with_keyword as f64).ln()
+}
+
+/// Calculate the term frequency normalized by the body length.
+/// A page whose keywords are all outside the body has no body to normalize by.
+pub(crate) fn tf(frequency: i32, word_count: i32) -> f64 {
+    if word_count > 0 {
+        frequency as f64 / word_count as f64
+    } else {
+        0.0
+    }
+}
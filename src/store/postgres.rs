@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use sqlx::types::BigDecimal;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::models;
+use crate::models::corpus_stats::CorpusStats;
+use crate::models::keyword::{Keyword, KeywordFrequency};
+use crate::models::query_log::QueryLog;
+use crate::models::website::{InsertWebsiteDao, Website, WebsiteContent};
+use crate::models::website_keyword_tfidf::WebsiteKeywordTfidf;
+use crate::models::website_keywords::WebsiteKeywords;
+use crate::segment::codec::PostingStats;
+use crate::segment::Posting;
+use crate::services::{simhash_bands, KeywordCache, HEADING_LEVELS};
+use crate::store::{closest_cluster, idf, tf, IndexOutcome, IndexPage, IndexStore, Occurrences};
+
+/// PgIndexStore keeps the index in postgres.
+pub struct PgIndexStore {
+    // `db` is a postgres connection pool.
+    db: sqlx::PgPool,
+    // `keyword_cache` maps keywords to ids, shared by every writer of this store.
+    keyword_cache: KeywordCache,
+}
+
+impl PgIndexStore {
+    /// Create a new PgIndexStore instance caching at most `keyword_cache_capacity` keyword ids.
+    pub fn new(db: sqlx::PgPool, keyword_cache_capacity: usize) -> Self {
+        Self {
+            db,
+            keyword_cache: KeywordCache::new(keyword_cache_capacity),
+        }
+    }
+
+    /// Estimate how well the `website_keywords` postings of every keyword would compress, numbering the websites oldest
+    /// first. The postings are kept uncompressed, they are encoded only to be measured.
+    pub async fn posting_stats(&self) -> Result<PostingStats, Box<dyn std::error::Error>> {
+        let docs: HashMap<Uuid, u32> = Website::find_ids(&self.db).await.map_err(|e| format!("Error finding websites: {:?}", e))?
+            .into_iter()
+            .enumerate()
+            .map(|(doc, id)| (id, doc as u32))
+            .collect();
+        let mut stats = PostingStats::default();
+        // The postings of a keyword are gathered until the next keyword starts, then encoded.
+        let mut keyword_id = None;
+        let mut postings: Vec<Posting> = Vec::new();
+        let mut website_keywords = WebsiteKeywords::stream_by_keyword(&self.db);
+        while let Some(website_keywords) = website_keywords.try_next().await.map_err(|e| format!("Error finding website keywords: {:?}", e))? {
+            if keyword_id != Some(website_keywords.keyword_id) {
+                add_postings(&mut stats, &mut postings)?;
+                keyword_id = Some(website_keywords.keyword_id);
+            }
+            let doc = match docs.get(&website_keywords.website_id) {
+                Some(doc) => *doc,
+                None => continue,
+            };
+            postings.push(Posting {
+                doc,
+                frequency: website_keywords.frequency.max(0) as u32,
+                title_frequency: website_keywords.title_frequency.max(0) as u32,
+                heading_frequencies: heading_frequencies(&website_keywords.heading_frequencies),
+                description_frequency: website_keywords.description_frequency.max(0) as u32,
+                url_frequency: website_keywords.url_frequency.max(0) as u32,
+                positions: website_keywords.positions.into_iter().map(|position| position.max(0) as u32).collect(),
+            });
+        }
+        add_postings(&mut stats, &mut postings)?;
+        Ok(stats)
+    }
+
+    /// Find the canonical website of the cluster of the closest near duplicate of a website, `None` when it has none.
+    async fn find_cluster(&self, conn: &mut sqlx::PgConnection, website_id: Uuid, simhash: Option<i64>) -> Result<Option<Uuid>, Box<dyn std::error::Error>> {
+        let simhash = match simhash {
+            Some(simhash) => simhash,
+            None => return Ok(None),
+        };
+        let candidates = Website::find_by_simhash_bands(&mut *conn, simhash_bands(simhash)).await.map_err(|e| format!("Error finding near duplicates: {:?}", e))?;
+        Ok(closest_cluster(simhash, website_id, candidates.iter()
+            .filter(|candidate| candidate.id != website_id)
+            .filter_map(|candidate| Some((candidate.simhash?, candidate.canonical_id())))))
+    }
+
+    /// Insert a new website, returning the keywords resolved from the database.
+    async fn insert_website(&self, conn: &mut sqlx::PgConnection, page: IndexPage) -> Result<HashMap<String, Uuid>, Box<dyn std::error::Error>> {
+        // The website has no id yet, so no candidate is the website itself.
+        let cluster_id = self.find_cluster(&mut *conn, Uuid::nil(), page.simhash).await?;
+        let insert_website = InsertWebsiteDao {
+            url: page.url,
+            word_count: page.word_count,
+            content: page.content,
+            fingerprint: Some(page.fingerprint),
+            simhash: page.simhash,
+            cluster_id,
+        };
+        // Insert the website to the database
+        let website = Website::insert(&mut *conn, insert_website).await.map_err(|e| format!("Error inserting website: {:?}", e))?;
+        // Add the website to the corpus statistics.
+        CorpusStats::adjust(&mut *conn, website.id, 1, page.word_count as i64).await.map_err(|e| format!("Error updating corpus stats: {:?}", e))?;
+        // Insert the keywords to the database
+        let created_keywords = self.insert_keywords(&mut *conn, &website, page.keywords).await.map_err(|e| format!("Error inserting keywords: {:?}", e))?;
+        Ok(created_keywords)
+    }
+
+    /// Reindex an existing website, returning the keywords resolved from the database.
+    async fn update_website(&self, conn: &mut sqlx::PgConnection, mut website: Website, page: IndexPage) -> Result<HashMap<String, Uuid>, Box<dyn std::error::Error>> {
+        // Update the word count and fingerprint of the website.
+        Website::update_word_count(&mut *conn, website.id, page.word_count).await.map_err(|e| format!("Error updating word count: {:?}", e))?;
+        Website::update_fingerprint(&mut *conn, website.id, page.fingerprint).await.map_err(|e| format!("Error updating fingerprint: {:?}", e))?;
+        // Join the cluster of the closest near duplicate, taking the near duplicates of the website along.
+        let cluster_id = self.find_cluster(&mut *conn, website.id, page.simhash).await?;
+        Website::update_simhash(&mut *conn, website.id, page.simhash, cluster_id).await.map_err(|e| format!("Error updating simhash: {:?}", e))?;
+        if let Some(cluster_id) = cluster_id {
+            Website::move_cluster(&mut *conn, website.id, cluster_id).await.map_err(|e| format!("Error moving cluster: {:?}", e))?;
+        }
+        // Replace the visible text of the website.
+        Website::update_content(&mut *conn, website.id, &page.content).await.map_err(|e| format!("Error updating content: {:?}", e))?;
+        // Replace the old word count in the corpus statistics.
+        CorpusStats::adjust(&mut *conn, website.id, 0, page.word_count as i64 - website.word_count as i64).await.map_err(|e| format!("Error updating corpus stats: {:?}", e))?;
+        // The term frequencies are normalized by the new word count.
+        website.word_count = page.word_count;
+        // Remove all the keywords and tfidf scores associated with the website.
+        WebsiteKeywords::delete_by_website(&mut *conn, website.id).await.map_err(|e| format!("Error deleting website keywords: {:?}", e))?;
+        WebsiteKeywordTfidf::delete_by_website(&mut *conn, website.id).await.map_err(|e| format!("Error deleting website keyword tfidf: {:?}", e))?;
+        // Insert the keywords to the database
+        let created_keywords = self.insert_keywords(&mut *conn, &website, page.keywords).await.map_err(|e| format!("Error inserting keywords: {:?}", e))?;
+        Ok(created_keywords)
+    }
+
+    /// Insert the postings and tfidf scores of every keyword of the website with a fixed number of queries.
+    /// Returns the keywords that were not cached, to be cached once the transaction commits.
+    async fn insert_keywords(&self, conn: &mut sqlx::PgConnection, website: &Website, occurrences: HashMap<String, Occurrences>) -> Result<HashMap<String, Uuid>, Box<dyn std::error::Error>> {
+        if occurrences.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let words: Vec<String> = occurrences.keys().cloned().collect();
+        let (mut keyword_ids, missing) = self.keyword_cache.get_many(&words);
+        let created_keywords: HashMap<String, Uuid> = if missing.is_empty() {
+            HashMap::new()
+        } else {
+            let surfaces: HashMap<String, String> = occurrences.iter()
+                .filter_map(|(keyword, occurrences)| Some((keyword.clone(), occurrences.surface.clone()?)))
+                .collect();
+            Keyword::find_or_create_many(&mut *conn, &missing, &surfaces).await.map_err(|e| format!("Error finding or creating keywords: {:?}", e))?
+                .into_iter()
+                .map(|keyword| (keyword.keyword, keyword.id))
+                .collect()
+        };
+        keyword_ids.extend(created_keywords.iter().map(|(keyword, id)| (keyword.clone(), *id)));
+        let mut frequencies: Vec<(Uuid, i32)> = Vec::with_capacity(occurrences.len());
+        let mut insert_website_keywords = Vec::with_capacity(occurrences.len());
+        for (keyword, occurrences) in occurrences.into_iter() {
+            let keyword_id = *keyword_ids.get(&keyword).ok_or_else(|| format!("Keyword was not created: {}", keyword))?;
+            let frequency = occurrences.positions.len() as i32;
+            frequencies.push((keyword_id, frequency));
+            insert_website_keywords.push(models::website_keywords::InsertWebsiteKeywordsDao {
+                keyword_id,
+                website_id: website.id,
+                frequency,
+                positions: occurrences.positions,
+                title_frequency: occurrences.title,
+                heading_frequencies: occurrences.headings.to_vec(),
+                description_frequency: occurrences.description,
+                url_frequency: occurrences.url,
+            });
+        }
+        // Insert the website keywords to the database
+        WebsiteKeywords::insert_many(&mut *conn, &insert_website_keywords).await.map_err(|e| format!("Error inserting website keywords: {:?}", e))?;
+
+        let ids: Vec<Uuid> = frequencies.iter().map(|(keyword_id, _)| *keyword_id).collect();
+        let total_docs_with_keywords = WebsiteKeywords::count_by_keyword_ids(&mut *conn, &ids).await.map_err(|e| format!("Error counting total docs with keywords: {:?}", e))?;
+        // The corpus stats were adjusted for the website in the same transaction.
+        let total_docs = CorpusStats::get(&mut *conn).await.map_err(|e| format!("Error getting corpus stats: {:?}", e))?.document_count;
+        let mut insert_website_keyword_tfidfs = Vec::with_capacity(frequencies.len());
+        for (keyword_id, frequency) in frequencies {
+            // The posting was just inserted, so the keyword is in at least one website.
+            let total_docs_with_keyword = total_docs_with_keywords.get(&keyword_id).copied().unwrap_or(1);
+            let idf = idf(total_docs_with_keyword, total_docs);
+            let normalized_frequency = tf(frequency, website.word_count);
+            let tfidf = normalized_frequency * idf;
+            insert_website_keyword_tfidfs.push(models::website_keyword_tfidf::InsertWebsiteKeywordTfidfDao {
+                website_id: website.id,
+                keyword_id,
+                tf: BigDecimal::try_from(normalized_frequency).map_err(|_| "Error converting to BigDecimal")?,
+                idf: BigDecimal::try_from(idf).map_err(|_| "Error converting to BigDecimal")?,
+                tfidf: BigDecimal::try_from(tfidf).map_err(|_| "Error converting to BigDecimal")?,
+            });
+        }
+        // The tfidf rows of the website were deleted with its postings, so they are inserted rather than upserted.
+        WebsiteKeywordTfidf::insert_many(&mut *conn, insert_website_keyword_tfidfs).await.map_err(|e| format!("Error inserting website keyword tfidf: {:?}", e))?;
+        Ok(created_keywords)
+    }
+}
+
+#[async_trait]
+impl IndexStore for PgIndexStore {
+    /// Write the page in a single transaction, so a failure leaves the previous index of the page untouched.
+    async fn index_page(&self, page: IndexPage) -> Result<IndexOutcome, Box<dyn std::error::Error>> {
+        let mut tx = self.db.begin().await.map_err(|e| format!("Error starting transaction: {:?}", e))?;
+        // Find website by url, create a new website if it doesn't exist.
+        let website = Website::find_by_url(&mut *tx, page.url.as_str().to_string()).await;
+        let (created_keywords, outcome) = match website {
+            Ok(website) if website.fingerprint == Some(page.fingerprint) => {
+                // The postings would be the same, only record that the website was seen.
+                Website::touch(&mut *tx, website.id).await.map_err(|e| format!("Error touching website: {:?}", e))?;
+                (HashMap::new(), IndexOutcome::Unchanged)
+            }
+            Ok(website) => {
+                // Update the word count of the website.
+                (self.update_website(&mut tx, website, page).await?, IndexOutcome::Updated)
+            }
+            Err(sqlx::Error::RowNotFound) => {
+                // Insert the website to the database
+                (self.insert_website(&mut tx, page).await?, IndexOutcome::Inserted)
+            }
+            Err(e) => {
+                return Err(Box::new(e));
+            }
+        };
+        // Returning early drops the transaction, which rolls it back.
+        tx.commit().await.map_err(|e| format!("Error committing transaction: {:?}", e))?;
+        // The keywords are committed, other writers can use their ids now.
+        self.keyword_cache.insert_many(created_keywords);
+        Ok(outcome)
+    }
+
+    /// Delete the website and adjust the corpus statistics in a single transaction.
+    async fn remove_page(&self, url: &url::Url) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut tx = self.db.begin().await.map_err(|e| format!("Error starting transaction: {:?}", e))?;
+        let website = match Website::find_by_url(&mut *tx, url.as_str().to_string()).await {
+            Ok(website) => website,
+            Err(sqlx::Error::RowNotFound) => return Ok(false),
+            Err(e) => return Err(Box::new(e)),
+        };
+        // Hand the near duplicates of the website over to the oldest of them.
+        Website::promote_cluster(&mut *tx, website.id).await.map_err(|e| format!("Error promoting cluster: {:?}", e))?;
+        // Deleting the website cascades to its postings and tfidf scores.
+        Website::delete(&mut *tx, website.id).await.map_err(|e| format!("Error deleting website: {:?}", e))?;
+        // Remove the website from the corpus statistics.
+        CorpusStats::adjust(&mut *tx, website.id, -1, -(website.word_count as i64)).await.map_err(|e| format!("Error updating corpus stats: {:?}", e))?;
+        tx.commit().await.map_err(|e| format!("Error committing transaction: {:?}", e))?;
+        Ok(true)
+    }
+
+    async fn find_keyword(&self, keyword: &str) -> Result<Option<Keyword>, Box<dyn std::error::Error>> {
+        match Keyword::find_by_word(&self.db, keyword).await {
+            Ok(keyword) => Ok(Some(keyword)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    async fn find_keyword_frequencies_since(&self, since: OffsetDateTime) -> Result<Vec<KeywordFrequency>, Box<dyn std::error::Error>> {
+        Ok(Keyword::find_frequencies_created_since(&self.db, since).await.map_err(|e| format!("Error finding keywords: {:?}", e))?)
+    }
+
+    async fn find_postings(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywords>, Box<dyn std::error::Error>> {
+        Ok(WebsiteKeywords::find_by_keyword_id(&self.db, keyword_id).await.map_err(|e| format!("Error finding website keywords: {:?}", e))?)
+    }
+
+    async fn document_frequency(&self, keyword_id: Uuid) -> Result<i64, Box<dyn std::error::Error>> {
+        Ok(WebsiteKeywords::count_by_keyword_id(&self.db, keyword_id).await.map_err(|e| format!("Error counting website keywords: {:?}", e))?)
+    }
+
+    async fn find_scores(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>> {
+        Ok(WebsiteKeywordTfidf::find_by_keyword_id(&self.db, keyword_id).await.map_err(|e| format!("Error finding website keyword tfidf: {:?}", e))?)
+    }
+
+    async fn find_websites(&self, ids: &[Uuid]) -> Result<Vec<Website>, Box<dyn std::error::Error>> {
+        Ok(Website::find_by_ids(&self.db, ids).await.map_err(|e| format!("Error finding websites: {:?}", e))?)
+    }
+
+    async fn find_contents(&self, ids: &[Uuid]) -> Result<Vec<WebsiteContent>, Box<dyn std::error::Error>> {
+        Ok(Website::find_contents_by_ids(&self.db, ids).await.map_err(|e| format!("Error finding website contents: {:?}", e))?)
+    }
+
+    async fn corpus_stats(&self) -> Result<CorpusStats, Box<dyn std::error::Error>> {
+        Ok(CorpusStats::get(&self.db).await.map_err(|e| format!("Error getting corpus stats: {:?}", e))?)
+    }
+
+    async fn record_query(&self, query: &str) -> Result<QueryLog, Box<dyn std::error::Error>> {
+        Ok(QueryLog::record(&self.db, query).await.map_err(|e| format!("Error recording query: {:?}", e))?)
+    }
+
+    async fn find_popular_queries(&self, limit: i64) -> Result<Vec<QueryLog>, Box<dyn std::error::Error>> {
+        Ok(QueryLog::find_popular(&self.db, limit).await.map_err(|e| format!("Error finding popular queries: {:?}", e))?)
+    }
+}
+
+/// The heading frequencies of a stored posting, one per level whatever the length of the stored array.
+/// Measure the postings of a keyword in doc order, leaving none.
+fn add_postings(stats: &mut PostingStats, postings: &mut Vec<Posting>) -> Result<(), Box<dyn std::error::Error>> {
+    if postings.is_empty() {
+        return Ok(());
+    }
+    postings.sort_by_key(|posting| posting.doc);
+    stats.add(postings).map_err(|e| format!("Error encoding postings: {:?}", e))?;
+    postings.clear();
+    Ok(())
+}
+
+fn heading_frequencies(stored: &[i32]) -> [u32; HEADING_LEVELS] {
+    let mut heading_frequencies = [0; HEADING_LEVELS];
+    for (heading_frequency, stored) in heading_frequencies.iter_mut().zip(stored) {
+        *heading_frequency = (*stored).max(0) as u32;
+    }
+    heading_frequencies
+}
@@ -0,0 +1,1024 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::types::BigDecimal;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use crate::models::corpus_stats::CorpusStats;
+use crate::models::keyword::{Keyword, KeywordFrequency};
+use crate::models::query_log::QueryLog;
+use crate::models::website::{Website, WebsiteContent};
+use crate::models::website_keyword_tfidf::WebsiteKeywordTfidf;
+use crate::models::website_keywords::WebsiteKeywords;
+use crate::segment::codec::{intersect, seek_docs, PostingStats};
+use crate::segment::{Posting, SegmentReader, SegmentWriter, StoredDoc};
+use crate::store::{idf, tf, DuplicateClusters, IndexOutcome, IndexPage, IndexStore};
+
+// Name of the file listing the segments of the index.
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// The segments making up the index, replaced atomically whenever a segment is flushed or merged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Manifest {
+    // `next_generation` is the generation of the next flushed segment.
+    next_generation: u64,
+    segments: Vec<ManifestSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ManifestSegment {
+    // `generation` orders the segments, a page in a later generation shadows the same url in earlier ones.
+    generation: u64,
+    file: String,
+}
+
+/// Where the live version of a website is stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DocAddress {
+    generation: u64,
+    doc: u32,
+    word_count: i32,
+    fingerprint: i64,
+    simhash: Option<i64>,
+    // `last_seen_at` is the last time the page was crawled, changed or not.
+    last_seen_at: OffsetDateTime,
+}
+
+struct LoadedSegment {
+    generation: u64,
+    reader: Arc<SegmentReader>,
+    // `website_ids` holds the website id of every doc, to tell live docs from shadowed ones without reading the doc store.
+    website_ids: Vec<Uuid>,
+    // `tombstones` maps the docs marking a page as removed to its url.
+    tombstones: HashMap<u32, String>,
+    // `shadowed` holds the docs that are not live, tombstones included, to correct the document frequencies of the
+    // segment without decoding its posting lists.
+    shadowed: BTreeSet<u32>,
+}
+
+/// SegmentIndexStore keeps the index in immutable memory-mapped segments on local disk.
+///
+/// Pages are buffered in memory, where they are searchable right away, and flushed as a new segment once enough of
+/// them are buffered. The buffer is lost unless it is flushed before the process exits. Reindexing a page adds a new version of it, shadowing the old one until a merge drops it.
+/// Scores are computed at query time from the live documents, so they are never stale.
+/// Query logs and last seen times are only kept in memory.
+pub struct SegmentIndexStore {
+    path: PathBuf,
+    // `flush_docs` is the number of buffered pages that triggers a flush.
+    flush_docs: usize,
+    // `merge_factor` is the number of segments merged together at once.
+    merge_factor: usize,
+    index: RwLock<SegmentIndex>,
+    // `merging` makes sure a single merge runs at a time.
+    merging: Mutex<()>,
+}
+
+struct SegmentIndex {
+    manifest: Manifest,
+    segments: Vec<LoadedSegment>,
+    // `buffer` holds the pages of generation `manifest.next_generation`, not flushed yet.
+    buffer: SegmentWriter,
+    // `websites` maps each website id to its live version.
+    websites: HashMap<Uuid, DocAddress>,
+    // `website_ids` maps each url to its website id.
+    website_ids: HashMap<String, Uuid>,
+    keywords: HashMap<String, Keyword>,
+    // `keyword_words` maps each keyword id back to its keyword.
+    keyword_words: HashMap<Uuid, String>,
+    // `surfaces` maps each keyword to the word it was first normalized from.
+    surfaces: HashMap<String, String>,
+    total_word_count: i64,
+    version: i64,
+    query_logs: HashMap<String, QueryLog>,
+    // `clusters` groups the live websites into near duplicate clusters, rebuilt from the stored simhashes on load.
+    clusters: DuplicateClusters,
+}
+
+impl SegmentIndex {
+    /// Load the segments listed by the manifest.
+    fn load(path: &Path, manifest: Manifest) -> io::Result<Self> {
+        let now = OffsetDateTime::now_utc();
+        let mut index = Self {
+            manifest: Manifest::default(),
+            segments: Vec::new(),
+            buffer: SegmentWriter::new(),
+            websites: HashMap::new(),
+            website_ids: HashMap::new(),
+            keyword_words: HashMap::new(),
+            keywords: HashMap::new(),
+            surfaces: HashMap::new(),
+            total_word_count: 0,
+            version: 0,
+            query_logs: HashMap::new(),
+            clusters: DuplicateClusters::default(),
+        };
+        let mut manifest_segments = manifest.segments.clone();
+        manifest_segments.sort_by_key(|segment| segment.generation);
+        for manifest_segment in manifest_segments {
+            let reader = SegmentReader::open(&path.join(&manifest_segment.file))?;
+            let mut website_ids = Vec::with_capacity(reader.doc_count() as usize);
+            let mut tombstones = HashMap::new();
+            for doc in 0..reader.doc_count() {
+                let stored_doc = reader.doc(doc)?;
+                website_ids.push(stored_doc.website_id);
+                if stored_doc.removed {
+                    index.remove_live_doc(&stored_doc.url);
+                    tombstones.insert(doc, stored_doc.url);
+                } else {
+                    index.add_live_doc(stored_doc.url, stored_doc.website_id, DocAddress {
+                        generation: manifest_segment.generation,
+                        doc,
+                        word_count: stored_doc.word_count as i32,
+                        fingerprint: stored_doc.fingerprint,
+                        simhash: stored_doc.simhash,
+                        last_seen_at: now,
+                    });
+                }
+            }
+            for (term, surface) in reader.surfaces()? {
+                index.find_or_create_keyword(&term, now);
+                index.surfaces.entry(term).or_insert(surface);
+            }
+            index.segments.push(LoadedSegment {
+                generation: manifest_segment.generation,
+                reader: Arc::new(reader),
+                website_ids,
+                tombstones,
+                shadowed: BTreeSet::new(),
+            });
+        }
+        // A url may be shadowed by a later doc of its own segment, so the shadowed docs are found once all are loaded.
+        let shadowed: Vec<BTreeSet<u32>> = index.segments.iter()
+            .map(|segment| index.shadowed_docs(segment.generation, &segment.website_ids))
+            .collect();
+        for (segment, shadowed) in index.segments.iter_mut().zip(shadowed) {
+            segment.shadowed = shadowed;
+        }
+        index.manifest = manifest;
+        Ok(index)
+    }
+
+    /// Take over the keywords and query logs of the index this one replaces, so the keyword ids handed out stay valid.
+    fn take_over(&mut self, replaced: &mut SegmentIndex) {
+        for (word, keyword) in std::mem::take(&mut replaced.keywords) {
+            self.keyword_words.insert(keyword.id, word.clone());
+            if let Some(loaded) = self.keywords.insert(word, keyword) {
+                self.keyword_words.remove(&loaded.id);
+            }
+        }
+        self.query_logs = std::mem::take(&mut replaced.query_logs);
+    }
+
+    /// Make `address` the live version of the url, shadowing the previous one.
+    fn add_live_doc(&mut self, url: String, website_id: Uuid, address: DocAddress) {
+        if let Some(old_website_id) = self.website_ids.insert(url, website_id) {
+            if let Some(old_address) = self.websites.remove(&old_website_id) {
+                self.total_word_count -= old_address.word_count as i64;
+                self.shadow(old_address);
+            }
+            if old_website_id != website_id {
+                self.clusters.remove(old_website_id);
+            }
+        }
+        self.clusters.insert(website_id, address.simhash);
+        self.websites.insert(website_id, address);
+        self.total_word_count += address.word_count as i64;
+        self.version += 1;
+    }
+
+    /// Drop the live version of the url, returning its website id.
+    fn remove_live_doc(&mut self, url: &str) -> Option<Uuid> {
+        let website_id = self.website_ids.remove(url)?;
+        if let Some(address) = self.websites.remove(&website_id) {
+            self.total_word_count -= address.word_count as i64;
+            self.shadow(address);
+        }
+        self.clusters.remove(website_id);
+        self.version += 1;
+        Some(website_id)
+    }
+
+    /// Record that the doc at `address` is no longer live in its segment.
+    fn shadow(&mut self, address: DocAddress) {
+        if let Some(segment) = self.segments.iter_mut().find(|segment| segment.generation == address.generation) {
+            segment.shadowed.insert(address.doc);
+        }
+    }
+
+    /// The docs of the segment of `generation` that are not live, given the website id of each of its docs.
+    fn shadowed_docs(&self, generation: u64, website_ids: &[Uuid]) -> BTreeSet<u32> {
+        website_ids.iter()
+            .enumerate()
+            .filter(|(doc, website_id)| !self.is_live(**website_id, generation, *doc as u32))
+            .map(|(doc, _)| doc as u32)
+            .collect()
+    }
+
+    /// Find a keyword, creating it if it doesn't exist.
+    fn find_or_create_keyword(&mut self, keyword: &str, now: OffsetDateTime) -> Uuid {
+        let keyword = self.keywords.entry(keyword.to_string())
+            .or_insert_with(|| Keyword {
+                id: Uuid::new_v4(),
+                keyword: keyword.to_string(),
+                created_at: now,
+                updated_at: now,
+            });
+        self.keyword_words.insert(keyword.id, keyword.keyword.clone());
+        keyword.id
+    }
+
+    /// Whether the doc is the live version of its website.
+    fn is_live(&self, website_id: Uuid, generation: u64, doc: u32) -> bool {
+        self.websites.get(&website_id)
+            .map(|address| address.generation == generation && address.doc == doc)
+            .unwrap_or(false)
+    }
+
+    /// Find the postings of a term in every segment and the buffer, leaving out the shadowed docs.
+    fn live_postings(&self, term: &str) -> io::Result<Vec<(Uuid, Posting)>> {
+        let mut live_postings = Vec::new();
+        for segment in self.segments.iter() {
+            for posting in segment.reader.postings(term)?.unwrap_or_default() {
+                if let Some(website_id) = segment.website_ids.get(posting.doc as usize).copied() {
+                    if self.is_live(website_id, segment.generation, posting.doc) {
+                        live_postings.push((website_id, posting));
+                    }
+                }
+            }
+        }
+        for posting in self.buffer.postings(term).unwrap_or_default() {
+            if let Some(doc) = self.buffer.doc(posting.doc) {
+                if self.is_live(doc.website_id, self.manifest.next_generation, posting.doc) {
+                    live_postings.push((doc.website_id, posting.clone()));
+                }
+            }
+        }
+        Ok(live_postings)
+    }
+
+    /// Count the live docs containing a term. The posting lists of the segments are only seeked to their shadowed docs.
+    fn live_document_frequency(&self, term: &str) -> io::Result<i64> {
+        let mut document_frequency = 0;
+        for segment in self.segments.iter() {
+            if let Some(mut list) = segment.reader.posting_list(term)? {
+                document_frequency += list.len() as i64;
+                document_frequency -= seek_docs(&mut list, segment.shadowed.iter().copied())?.len() as i64;
+            }
+        }
+        for posting in self.buffer.postings(term).unwrap_or_default() {
+            if let Some(doc) = self.buffer.doc(posting.doc) {
+                if self.is_live(doc.website_id, self.manifest.next_generation, posting.doc) {
+                    document_frequency += 1;
+                }
+            }
+        }
+        Ok(document_frequency)
+    }
+
+    /// Find the postings of a term in the given live websites, seeking each segment to the docs of the websites it holds.
+    fn live_postings_in(&self, term: &str, website_ids: &[Uuid]) -> io::Result<Vec<(Uuid, Posting)>> {
+        let mut docs: HashMap<u64, Vec<u32>> = HashMap::new();
+        for website_id in website_ids {
+            if let Some(address) = self.websites.get(website_id) {
+                docs.entry(address.generation).or_default().push(address.doc);
+            }
+        }
+        let mut live_postings = Vec::new();
+        for segment in self.segments.iter() {
+            let mut segment_docs = match docs.remove(&segment.generation) {
+                Some(segment_docs) => segment_docs,
+                None => continue,
+            };
+            let mut list = match segment.reader.posting_list(term)? {
+                Some(list) => list,
+                None => continue,
+            };
+            segment_docs.sort();
+            segment_docs.dedup();
+            for posting in seek_docs(&mut list, segment_docs)? {
+                if let Some(website_id) = segment.website_ids.get(posting.doc as usize).copied() {
+                    live_postings.push((website_id, posting));
+                }
+            }
+        }
+        if let Some(mut buffered_docs) = docs.remove(&self.manifest.next_generation) {
+            buffered_docs.sort();
+            for posting in self.buffer.postings(term).unwrap_or_default() {
+                if let (Ok(_), Some(doc)) = (buffered_docs.binary_search(&posting.doc), self.buffer.doc(posting.doc)) {
+                    live_postings.push((doc.website_id, posting.clone()));
+                }
+            }
+        }
+        Ok(live_postings)
+    }
+
+    /// Find the postings of the live docs containing every term, in the order of the terms.
+    /// Each segment intersects the posting lists of the terms with their skip data and only decodes the docs in all of them.
+    fn common_live_postings(&self, terms: &[&str]) -> io::Result<Vec<Vec<(Uuid, Posting)>>> {
+        let mut common = vec![Vec::new(); terms.len()];
+        'segments: for segment in self.segments.iter() {
+            let mut lists = Vec::with_capacity(terms.len());
+            for term in terms {
+                match segment.reader.posting_list(term)? {
+                    Some(list) => lists.push(list),
+                    None => continue 'segments,
+                }
+            }
+            let docs: Vec<u32> = intersect(&mut lists)?.into_iter()
+                .filter(|doc| segment.website_ids.get(*doc as usize)
+                    .is_some_and(|website_id| self.is_live(*website_id, segment.generation, *doc)))
+                .collect();
+            if docs.is_empty() {
+                continue;
+            }
+            // The intersection reordered and moved the lists, the postings are decoded from fresh ones.
+            for (term, term_postings) in terms.iter().zip(common.iter_mut()) {
+                if let Some(mut list) = segment.reader.posting_list(term)? {
+                    for posting in seek_docs(&mut list, docs.iter().copied())? {
+                        term_postings.push((segment.website_ids[posting.doc as usize], posting));
+                    }
+                }
+            }
+        }
+        // The buffer is small enough to filter.
+        let buffered: Vec<HashMap<u32, &Posting>> = terms.iter()
+            .map(|term| self.buffer.postings(term).unwrap_or_default().iter().map(|posting| (posting.doc, posting)).collect())
+            .collect();
+        if let Some(first) = buffered.first() {
+            for doc in first.keys() {
+                let website_id = match self.buffer.doc(*doc) {
+                    Some(stored_doc) => stored_doc.website_id,
+                    None => continue,
+                };
+                if !self.is_live(website_id, self.manifest.next_generation, *doc) || !buffered.iter().all(|postings| postings.contains_key(doc)) {
+                    continue;
+                }
+                for (term_postings, postings) in common.iter_mut().zip(buffered.iter()) {
+                    term_postings.push((website_id, postings[doc].clone()));
+                }
+            }
+        }
+        Ok(common)
+    }
+
+    /// Score the postings of a keyword, given the number of live docs containing it.
+    fn scores(&self, keyword_id: Uuid, postings: Vec<(Uuid, Posting)>, document_frequency: i64) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let idf = idf(document_frequency, self.websites.len() as i64);
+        let mut scores = Vec::with_capacity(postings.len());
+        for (website_id, posting) in postings {
+            let word_count = self.websites.get(&website_id).map(|address| address.word_count).unwrap_or(0);
+            let normalized_frequency = tf(posting.frequency as i32, word_count);
+            scores.push(WebsiteKeywordTfidf {
+                id: Uuid::nil(),
+                website_id,
+                keyword_id,
+                tf: BigDecimal::try_from(normalized_frequency).map_err(|_| "Error converting to BigDecimal")?,
+                idf: BigDecimal::try_from(idf).map_err(|_| "Error converting to BigDecimal")?,
+                tfidf: BigDecimal::try_from(normalized_frequency * idf).map_err(|_| "Error converting to BigDecimal")?,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+        Ok(scores)
+    }
+
+    /// Read the live version of a website.
+    fn doc(&self, website_id: Uuid) -> io::Result<Option<StoredDoc>> {
+        let address = match self.websites.get(&website_id) {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+        if address.generation == self.manifest.next_generation {
+            return Ok(self.buffer.doc(address.doc).cloned());
+        }
+        match self.segments.iter().find(|segment| segment.generation == address.generation) {
+            Some(segment) => segment.reader.doc(address.doc).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl SegmentIndexStore {
+    /// Open the index stored in the `path` directory, creating it if it doesn't exist.
+    pub fn open(path: &Path, flush_docs: usize, merge_factor: usize) -> io::Result<Self> {
+        fs::create_dir_all(path)?;
+        let manifest = read_manifest(path)?;
+        let index = SegmentIndex::load(path, manifest)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            flush_docs: flush_docs.max(1),
+            merge_factor: merge_factor.max(2),
+            index: RwLock::new(index),
+            merging: Mutex::new(()),
+        })
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, SegmentIndex> {
+        self.index.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, SegmentIndex> {
+        self.index.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of segments on disk.
+    pub fn segment_count(&self) -> usize {
+        self.read().segments.len()
+    }
+
+    /// Measure how well the posting lists of the segments on disk compress, shadowed docs included.
+    pub fn posting_stats(&self) -> io::Result<PostingStats> {
+        let index = self.read();
+        let mut stats = PostingStats::default();
+        for segment in index.segments.iter() {
+            for (term, _) in segment.reader.terms()? {
+                stats.add(&segment.reader.postings(&term)?.unwrap_or_default())?;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Write the buffered pages as a new segment.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut index = self.write();
+        self.flush_locked(&mut index)
+    }
+
+    fn flush_locked(&self, index: &mut SegmentIndex) -> io::Result<()> {
+        if index.buffer.is_empty() {
+            return Ok(());
+        }
+        let generation = index.manifest.next_generation;
+        let file = segment_file(generation);
+        index.buffer.write(&self.path.join(&file))?;
+        let reader = SegmentReader::open(&self.path.join(&file))?;
+        let mut manifest = index.manifest.clone();
+        manifest.segments.push(ManifestSegment { generation, file });
+        manifest.next_generation += 1;
+        write_manifest(&self.path, &manifest)?;
+
+        let (website_ids, tombstones) = doc_ids(&index.buffer);
+        let shadowed = index.shadowed_docs(generation, &website_ids);
+        index.segments.push(LoadedSegment {
+            generation,
+            reader: Arc::new(reader),
+            website_ids,
+            tombstones,
+            shadowed,
+        });
+        index.manifest = manifest;
+        index.buffer = SegmentWriter::new();
+        Ok(())
+    }
+
+    /// Merge the `merge_factor` smallest segments into one, dropping their shadowed docs.
+    /// Returns whether a merge happened. Pages keep being indexed and searched while the merged segment is written.
+    pub fn merge(&self) -> io::Result<bool> {
+        let _merging = self.merging.lock().unwrap_or_else(|e| e.into_inner());
+        // Pick the inputs and the docs to copy from them.
+        let (inputs, copied_docs) = {
+            let index = self.read();
+            if index.segments.len() < self.merge_factor {
+                return Ok(false);
+            }
+            let mut candidates: Vec<&LoadedSegment> = index.segments.iter().collect();
+            candidates.sort_by_key(|segment| (segment.reader.doc_count(), segment.generation));
+            candidates.truncate(self.merge_factor);
+            candidates.sort_by_key(|segment| segment.generation);
+            let inputs: Vec<(u64, Arc<SegmentReader>)> = candidates.iter()
+                .map(|segment| (segment.generation, segment.reader.clone()))
+                .collect();
+            // A tombstone must outlive the segments left out of the merge that may hold an older version of its url.
+            let newest_input = inputs.iter().map(|(generation, _)| *generation).max().unwrap_or(0);
+            let older_segments_left = index.segments.iter()
+                .any(|segment| segment.generation < newest_input && !inputs.iter().any(|(generation, _)| *generation == segment.generation));
+            let copied_docs: Vec<Vec<bool>> = candidates.iter()
+                .map(|segment| segment.website_ids.iter()
+                    .enumerate()
+                    .map(|(doc, website_id)| match segment.tombstones.get(&(doc as u32)) {
+                        // A tombstone of a page indexed again since is obsolete.
+                        Some(url) => older_segments_left && !index.website_ids.contains_key(url),
+                        None => index.is_live(*website_id, segment.generation, doc as u32),
+                    })
+                    .collect())
+                .collect();
+            (inputs, copied_docs)
+        };
+        // The merged segment takes the generation of its newest input, so it still shadows the older segments left.
+        let generation = inputs.iter().map(|(generation, _)| *generation).max().unwrap_or(0);
+
+        // Copy the docs, then their postings in the same order so they stay sorted by doc.
+        let mut writer = SegmentWriter::new();
+        let mut doc_maps: Vec<HashMap<u32, u32>> = Vec::with_capacity(inputs.len());
+        for ((_, reader), copied) in inputs.iter().zip(copied_docs.iter()) {
+            let mut doc_map = HashMap::new();
+            for (doc, is_copied) in copied.iter().enumerate() {
+                if *is_copied {
+                    doc_map.insert(doc as u32, writer.add_stored_document(reader.doc(doc as u32)?));
+                }
+            }
+            doc_maps.push(doc_map);
+        }
+        for ((_, reader), doc_map) in inputs.iter().zip(doc_maps.iter()) {
+            for (term, surface) in reader.surfaces()? {
+                writer.set_surface(&term, surface);
+                for mut posting in reader.postings(&term)?.unwrap_or_default() {
+                    if let Some(doc) = doc_map.get(&posting.doc) {
+                        posting.doc = *doc;
+                        writer.add_posting(term.clone(), posting);
+                    }
+                }
+            }
+        }
+        let file = segment_file(generation);
+        writer.write(&self.path.join(&file))?;
+        let reader = SegmentReader::open(&self.path.join(&file))?;
+        let (website_ids, tombstones) = doc_ids(&writer);
+
+        // Swap the inputs for the merged segment and move the websites that were not reindexed meanwhile.
+        let input_files: Vec<PathBuf> = {
+            let mut index = self.write();
+            let input_generations: Vec<u64> = inputs.iter().map(|(generation, _)| *generation).collect();
+            let mut manifest = index.manifest.clone();
+            let input_files = manifest.segments.iter()
+                .filter(|segment| input_generations.contains(&segment.generation))
+                .map(|segment| self.path.join(&segment.file))
+                .collect();
+            manifest.segments.retain(|segment| !input_generations.contains(&segment.generation));
+            manifest.segments.push(ManifestSegment { generation, file });
+            write_manifest(&self.path, &manifest)?;
+
+            for address in index.websites.values_mut() {
+                if let Some(position) = input_generations.iter().position(|generation| *generation == address.generation) {
+                    if let Some(doc) = doc_maps[position].get(&address.doc) {
+                        address.generation = generation;
+                        address.doc = *doc;
+                    }
+                }
+            }
+            index.segments.retain(|segment| !input_generations.contains(&segment.generation));
+            // Pages reindexed while the merged segment was written are already shadowed in it.
+            let shadowed = index.shadowed_docs(generation, &website_ids);
+            index.segments.push(LoadedSegment {
+                generation,
+                reader: Arc::new(reader),
+                website_ids,
+                tombstones,
+                shadowed,
+            });
+            index.segments.sort_by_key(|segment| segment.generation);
+            index.manifest = manifest;
+            input_files
+        };
+        // Readers still holding the inputs keep them mapped until they are dropped.
+        for input_file in input_files {
+            fs::remove_file(input_file)?;
+        }
+        Ok(true)
+    }
+
+    /// Reload the segments if another process changed the manifest, e.g. the crawl pipeline writing the index
+    /// the search API reads. Returns whether the segments were reloaded.
+    /// The segments are loaded without holding the lock, searches only wait for the loaded index to be swapped in.
+    pub fn reload(&self) -> io::Result<bool> {
+        let manifest = read_manifest(&self.path)?;
+        if self.read().manifest == manifest {
+            return Ok(false);
+        }
+        let mut loaded = SegmentIndex::load(&self.path, manifest)?;
+        let mut index = self.write();
+        loaded.take_over(&mut index);
+        *index = loaded;
+        Ok(true)
+    }
+
+    /// Flush and merge every `interval`, in the process writing the index.
+    pub async fn start(&self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            // Segments are written with blocking file I/O.
+            let result = tokio::task::block_in_place(|| {
+                self.flush()?;
+                while self.merge()? {}
+                Ok::<(), io::Error>(())
+            });
+            if let Err(e) = result {
+                eprintln!("Error maintaining segments: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Collect the website id of every doc written and the url of every tombstone.
+fn doc_ids(writer: &SegmentWriter) -> (Vec<Uuid>, HashMap<u32, String>) {
+    let mut website_ids = Vec::with_capacity(writer.doc_count() as usize);
+    let mut tombstones = HashMap::new();
+    for doc in 0..writer.doc_count() {
+        if let Some(stored_doc) = writer.doc(doc) {
+            website_ids.push(stored_doc.website_id);
+            if stored_doc.removed {
+                tombstones.insert(doc, stored_doc.url.clone());
+            }
+        }
+    }
+    (website_ids, tombstones)
+}
+
+/// Convert a posting of a segment to the posting of a keyword in a website.
+fn website_keywords(keyword_id: Uuid, website_id: Uuid, posting: Posting, now: OffsetDateTime) -> WebsiteKeywords {
+    WebsiteKeywords {
+        id: Uuid::nil(),
+        keyword_id,
+        website_id,
+        frequency: posting.frequency as i32,
+        positions: posting.positions.into_iter().map(|position| position as i32).collect(),
+        title_frequency: posting.title_frequency as i32,
+        heading_frequencies: posting.heading_frequencies.iter().map(|frequency| *frequency as i32).collect(),
+        description_frequency: posting.description_frequency as i32,
+        url_frequency: posting.url_frequency as i32,
+        created_at: now,
+        updated_at: now,
+    }
+}
+
+/// Name the file of a segment. The nonce keeps a merged segment from overwriting its input of the same generation.
+fn segment_file(generation: u64) -> String {
+    format!("{:016x}-{}.seg", generation, Uuid::new_v4().simple())
+}
+
+fn read_manifest(path: &Path) -> io::Result<Manifest> {
+    match fs::read(path.join(MANIFEST_FILE)) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace the manifest, writing it next to the old one and renaming it so it is never seen half written.
+fn write_manifest(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    let bytes = serde_json::to_vec(manifest).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let temporary_path = path.join(format!("{}.tmp", MANIFEST_FILE));
+    fs::write(&temporary_path, bytes)?;
+    fs::File::open(&temporary_path)?.sync_all()?;
+    fs::rename(&temporary_path, path.join(MANIFEST_FILE))
+}
+
+#[async_trait]
+impl IndexStore for SegmentIndexStore {
+    /// Buffer the page under the write lock, flushing the buffer once it is full.
+    async fn index_page(&self, page: IndexPage) -> Result<IndexOutcome, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut index = self.write();
+        let url = page.url.to_string();
+        // A reindexed page keeps its website id.
+        let (website_id, outcome) = match index.website_ids.get(&url).copied() {
+            Some(website_id) => (website_id, IndexOutcome::Updated),
+            None => (Uuid::new_v4(), IndexOutcome::Inserted),
+        };
+        if let Some(address) = index.websites.get_mut(&website_id) {
+            if address.fingerprint == page.fingerprint {
+                address.last_seen_at = now;
+                return Ok(IndexOutcome::Unchanged);
+            }
+        }
+        for (keyword, occurrences) in page.keywords.iter() {
+            index.find_or_create_keyword(keyword, now);
+            if let Some(surface) = &occurrences.surface {
+                index.surfaces.entry(keyword.clone()).or_insert_with(|| surface.clone());
+            }
+        }
+        let word_count = page.word_count.max(0);
+        let doc = index.buffer.add_document(StoredDoc {
+            website_id,
+            url: url.clone(),
+            word_count: word_count as u32,
+            content: page.content,
+            fingerprint: page.fingerprint,
+            simhash: page.simhash,
+            removed: false,
+        }, page.keywords);
+        let generation = index.manifest.next_generation;
+        index.add_live_doc(url, website_id, DocAddress {
+            generation,
+            doc,
+            word_count,
+            fingerprint: page.fingerprint,
+            simhash: page.simhash,
+            last_seen_at: now,
+        });
+        if index.buffer.doc_count() as usize >= self.flush_docs {
+            tokio::task::block_in_place(|| self.flush_locked(&mut index)).map_err(|e| format!("Error flushing segment: {:?}", e))?;
+        }
+        Ok(outcome)
+    }
+
+    /// Buffer a tombstone shadowing every earlier version of the page.
+    async fn remove_page(&self, url: &url::Url) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut index = self.write();
+        let website_id = match index.remove_live_doc(url.as_str()) {
+            Some(website_id) => website_id,
+            None => return Ok(false),
+        };
+        index.buffer.add_stored_document(StoredDoc {
+            website_id,
+            url: url.to_string(),
+            word_count: 0,
+            content: String::new(),
+            fingerprint: 0,
+            simhash: None,
+            removed: true,
+        });
+        if index.buffer.doc_count() as usize >= self.flush_docs {
+            tokio::task::block_in_place(|| self.flush_locked(&mut index)).map_err(|e| format!("Error flushing segment: {:?}", e))?;
+        }
+        Ok(true)
+    }
+
+    async fn find_keyword(&self, keyword: &str) -> Result<Option<Keyword>, Box<dyn std::error::Error>> {
+        Ok(self.read().keywords.get(keyword).cloned())
+    }
+
+    /// The document frequency counts the docs of the segments a keyword is in, shadowed ones included until they are merged away.
+    async fn find_keyword_frequencies_since(&self, since: OffsetDateTime) -> Result<Vec<KeywordFrequency>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let mut document_frequencies: HashMap<String, i64> = HashMap::new();
+        for segment in index.segments.iter() {
+            for (term, document_frequency) in segment.reader.terms().map_err(|e| format!("Error reading terms: {:?}", e))? {
+                *document_frequencies.entry(term).or_default() += document_frequency as i64;
+            }
+        }
+        for (term, document_frequency) in index.buffer.terms() {
+            *document_frequencies.entry(term.clone()).or_default() += document_frequency as i64;
+        }
+        let mut frequencies: Vec<KeywordFrequency> = index.keywords.values()
+            .filter(|keyword| keyword.created_at >= since)
+            .map(|keyword| KeywordFrequency {
+                keyword: keyword.keyword.clone(),
+                surface: index.surfaces.get(&keyword.keyword).unwrap_or(&keyword.keyword).clone(),
+                document_frequency: document_frequencies.get(&keyword.keyword).copied().unwrap_or(0),
+                created_at: keyword.created_at,
+            })
+            .collect();
+        frequencies.sort_by_key(|frequency| frequency.created_at);
+        Ok(frequencies)
+    }
+
+    async fn find_postings(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywords>, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let index = self.read();
+        let keyword = match index.keyword_words.get(&keyword_id) {
+            Some(keyword) => keyword,
+            None => return Ok(Vec::new()),
+        };
+        let postings = index.live_postings(keyword).map_err(|e| format!("Error reading postings: {:?}", e))?;
+        Ok(postings.into_iter()
+            .map(|(website_id, posting)| website_keywords(keyword_id, website_id, posting, now))
+            .collect())
+    }
+
+    /// Intersect the posting lists in every segment, only the postings of the docs containing every keyword are decoded.
+    async fn find_common_postings(&self, keyword_ids: &[Uuid]) -> Result<Vec<Vec<WebsiteKeywords>>, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let index = self.read();
+        let mut terms = Vec::with_capacity(keyword_ids.len());
+        for keyword_id in keyword_ids {
+            match index.keyword_words.get(keyword_id) {
+                Some(keyword) => terms.push(keyword.as_str()),
+                None => return Ok(vec![Vec::new(); keyword_ids.len()]),
+            }
+        }
+        let common = index.common_live_postings(&terms).map_err(|e| format!("Error reading postings: {:?}", e))?;
+        Ok(common.into_iter()
+            .zip(keyword_ids)
+            .map(|(postings, keyword_id)| postings.into_iter()
+                .map(|(website_id, posting)| website_keywords(*keyword_id, website_id, posting, now))
+                .collect())
+            .collect())
+    }
+
+    async fn document_frequency(&self, keyword_id: Uuid) -> Result<i64, Box<dyn std::error::Error>> {
+        let index = self.read();
+        match index.keyword_words.get(&keyword_id) {
+            Some(keyword) => Ok(index.live_document_frequency(keyword).map_err(|e| format!("Error reading postings: {:?}", e))?),
+            None => Ok(0),
+        }
+    }
+
+    async fn find_scores(&self, keyword_id: Uuid) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let keyword = match index.keyword_words.get(&keyword_id) {
+            Some(keyword) => keyword,
+            None => return Ok(Vec::new()),
+        };
+        let postings = index.live_postings(keyword).map_err(|e| format!("Error reading postings: {:?}", e))?;
+        let document_frequency = postings.len() as i64;
+        index.scores(keyword_id, postings, document_frequency)
+    }
+
+    async fn find_scores_in(&self, keyword_id: Uuid, website_ids: &[Uuid]) -> Result<Vec<WebsiteKeywordTfidf>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let keyword = match index.keyword_words.get(&keyword_id) {
+            Some(keyword) => keyword,
+            None => return Ok(Vec::new()),
+        };
+        let postings = index.live_postings_in(keyword, website_ids).map_err(|e| format!("Error reading postings: {:?}", e))?;
+        let document_frequency = index.live_document_frequency(keyword).map_err(|e| format!("Error reading postings: {:?}", e))?;
+        index.scores(keyword_id, postings, document_frequency)
+    }
+
+    async fn find_websites(&self, ids: &[Uuid]) -> Result<Vec<Website>, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let index = self.read();
+        let mut websites = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(doc) = index.doc(*id).map_err(|e| format!("Error reading website: {:?}", e))? {
+                let last_seen_at = index.websites.get(id).map(|address| address.last_seen_at).unwrap_or(now);
+                websites.push(Website {
+                    id: *id,
+                    url: doc.url,
+                    word_count: doc.word_count as i32,
+                    fingerprint: Some(doc.fingerprint),
+                    simhash: doc.simhash,
+                    cluster_id: index.clusters.cluster_id(*id),
+                    last_seen_at,
+                    created_at: now,
+                    updated_at: now,
+                });
+            }
+        }
+        Ok(websites)
+    }
+
+    async fn find_contents(&self, ids: &[Uuid]) -> Result<Vec<WebsiteContent>, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let mut contents = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(doc) = index.doc(*id).map_err(|e| format!("Error reading website: {:?}", e))? {
+                contents.push(WebsiteContent {
+                    id: *id,
+                    content: doc.content,
+                });
+            }
+        }
+        Ok(contents)
+    }
+
+    async fn corpus_stats(&self) -> Result<CorpusStats, Box<dyn std::error::Error>> {
+        let index = self.read();
+        let now = OffsetDateTime::now_utc();
+        // Scores are computed at query time, so they are always up to date.
+        Ok(CorpusStats {
+            document_count: index.websites.len() as i64,
+            total_word_count: index.total_word_count,
+            version: index.version,
+            idf_version: index.version,
+            idf_recomputed_at: None,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+
+    async fn record_query(&self, query: &str) -> Result<QueryLog, Box<dyn std::error::Error>> {
+        let now = OffsetDateTime::now_utc();
+        let mut index = self.write();
+        let query_log = index.query_logs.entry(query.to_string())
+            .and_modify(|query_log| {
+                query_log.count += 1;
+                query_log.updated_at = now;
+            })
+            .or_insert_with(|| QueryLog {
+                id: Uuid::new_v4(),
+                query: query.to_string(),
+                count: 1,
+                created_at: now,
+                updated_at: now,
+            });
+        Ok(query_log.clone())
+    }
+
+    async fn find_popular_queries(&self, limit: i64) -> Result<Vec<QueryLog>, Box<dyn std::error::Error>> {
+        let mut query_logs: Vec<QueryLog> = self.read().query_logs.values().cloned().collect();
+        query_logs.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.query.cmp(&b.query)));
+        query_logs.truncate(limit.max(0) as usize);
+        Ok(query_logs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::fixtures::page;
+
+    // Reindexed pages shadow their old version across flushes, merges and reopening
+    #[tokio::test(flavor = "multi_thread")]
+    async fn can_flush_and_merge_segments() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = SegmentIndexStore::open(&path, 1, 2).unwrap();
+        store.index_page(page("https://a.example/", &["rust", "async"])).await.unwrap();
+        store.index_page(page("https://b.example/", &["rust"])).await.unwrap();
+        store.index_page(page("https://a.example/", &["tokio"])).await.unwrap();
+        assert_eq!(store.segment_count(), 3);
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        assert_eq!(store.find_postings(rust.id).await.unwrap().len(), 1);
+
+        while store.merge().unwrap() {}
+        assert_eq!(store.segment_count(), 1);
+        assert_eq!(store.find_postings(rust.id).await.unwrap().len(), 1);
+        let corpus_stats = store.corpus_stats().await.unwrap();
+        assert_eq!(corpus_stats.document_count, 2);
+        assert_eq!(corpus_stats.total_word_count, 2);
+
+        let reopened = SegmentIndexStore::open(&path, 1, 2).unwrap();
+        let tokio = reopened.find_keyword("tokio").await.unwrap().unwrap();
+        let postings = reopened.find_postings(tokio.id).await.unwrap();
+        assert_eq!(postings.len(), 1);
+        let websites = reopened.find_websites(&[postings[0].website_id]).await.unwrap();
+        assert_eq!(websites[0].url, "https://a.example/");
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    // A removed page stays removed when its tombstone is merged while an older segment still holds the page
+    #[tokio::test(flavor = "multi_thread")]
+    async fn can_remove_page() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = SegmentIndexStore::open(&path, 100, 2).unwrap();
+        store.index_page(page("https://a.example/", &["rust"])).await.unwrap();
+        store.index_page(page("https://b.example/", &["rust"])).await.unwrap();
+        store.flush().unwrap();
+        store.index_page(page("https://c.example/", &["rust"])).await.unwrap();
+        store.flush().unwrap();
+        assert!(store.remove_page(&url::Url::parse("https://a.example/").unwrap()).await.unwrap());
+        assert!(!store.remove_page(&url::Url::parse("https://a.example/").unwrap()).await.unwrap());
+        store.flush().unwrap();
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        assert_eq!(store.find_postings(rust.id).await.unwrap().len(), 2);
+
+        // The two small segments are merged, the one holding the removed page is left.
+        assert!(store.merge().unwrap());
+        assert_eq!(store.segment_count(), 2);
+        let reopened = SegmentIndexStore::open(&path, 100, 2).unwrap();
+        let rust = reopened.find_keyword("rust").await.unwrap().unwrap();
+        assert_eq!(reopened.find_postings(rust.id).await.unwrap().len(), 2);
+        assert_eq!(reopened.corpus_stats().await.unwrap().document_count, 2);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    // A reader reloads the segments flushed by the writer, keeping the keyword ids it handed out
+    #[tokio::test(flavor = "multi_thread")]
+    async fn can_reload_segments() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let writer = SegmentIndexStore::open(&path, 100, 2).unwrap();
+        writer.index_page(page("https://a.example/", &["rust"])).await.unwrap();
+        writer.flush().unwrap();
+        let reader = SegmentIndexStore::open(&path, 100, 2).unwrap();
+        let rust = reader.find_keyword("rust").await.unwrap().unwrap();
+        assert!(!reader.reload().unwrap());
+
+        writer.index_page(page("https://b.example/", &["rust", "tokio"])).await.unwrap();
+        writer.flush().unwrap();
+        assert!(reader.reload().unwrap());
+        assert_eq!(reader.find_keyword("rust").await.unwrap().unwrap().id, rust.id);
+        assert_eq!(reader.find_postings(rust.id).await.unwrap().len(), 2);
+        assert!(reader.find_keyword("tokio").await.unwrap().is_some());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    // Conjunctive retrieval skips over the shadowed docs and counts the live docs only, in segments and the buffer
+    #[tokio::test(flavor = "multi_thread")]
+    async fn can_find_common_postings() {
+        let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = SegmentIndexStore::open(&path, 100, 2).unwrap();
+        store.index_page(page("https://a.example/", &["rust", "tokio"])).await.unwrap();
+        store.index_page(page("https://b.example/", &["rust"])).await.unwrap();
+        store.index_page(page("https://c.example/", &["rust", "tokio"])).await.unwrap();
+        store.flush().unwrap();
+        store.index_page(page("https://c.example/", &["rust"])).await.unwrap();
+        store.index_page(page("https://d.example/", &["tokio", "rust"])).await.unwrap();
+        let rust = store.find_keyword("rust").await.unwrap().unwrap();
+        let tokio = store.find_keyword("tokio").await.unwrap().unwrap();
+
+        let common = store.find_common_postings(&[rust.id, tokio.id]).await.unwrap();
+        let websites = store.find_websites(&common[0].iter().map(|row| row.website_id).collect::<Vec<Uuid>>()).await.unwrap();
+        let mut urls: Vec<String> = websites.into_iter().map(|website| website.url).collect();
+        urls.sort();
+        assert_eq!(urls, vec!["https://a.example/".to_string(), "https://d.example/".to_string()]);
+        assert_eq!(common[1].len(), 2);
+        assert_eq!(common[1].iter().find(|row| row.website_id == common[0][0].website_id).unwrap().positions.len(), 1);
+
+        assert_eq!(store.document_frequency(rust.id).await.unwrap(), 4);
+        assert_eq!(store.document_frequency(tokio.id).await.unwrap(), 2);
+        let website_ids: Vec<Uuid> = common[0].iter().map(|row| row.website_id).collect();
+        let scores = store.find_scores_in(tokio.id, &website_ids).await.unwrap();
+        let all_scores = store.find_scores(tokio.id).await.unwrap();
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores[0].idf, all_scores[0].idf);
+        fs::remove_dir_all(&path).unwrap();
+    }
+}